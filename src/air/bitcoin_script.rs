@@ -1,4 +1,8 @@
+use crate::air::ScriptAir;
+use crate::chunker::StateItem;
 use crate::circle::CirclePointGadget;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
 use rust_bitcoin_m31::{qm31_add, qm31_shift_by_i, qm31_shift_by_ij, qm31_shift_by_j, qm31_swap};
 use stwo_prover::core::poly::circle::CanonicCoset;
@@ -63,6 +67,28 @@ impl AirGadget {
     }
 }
 
+/// `ScriptAir::eval_composition_polynomial_at_point_gadget` of an AIR as a `Gadget`, with the
+/// hints under "composition_hint".
+pub struct CompositionAtPoint<'a, A: ScriptAir>(pub &'a A);
+
+impl<A: ScriptAir> Gadget for CompositionAtPoint<'_, A> {
+    fn inputs(&self) -> Vec<StateItem> {
+        let n_mask_values = self.0.mask_offsets().iter().map(Vec::len).sum::<usize>();
+        let mut inputs = vec![StateItem::QM31; 1 + n_mask_values];
+        inputs.push(StateItem::CirclePoint);
+        inputs
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        self.0
+            .eval_composition_polynomial_at_point_gadget(stream, "composition_hint")
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::air::AirGadget;
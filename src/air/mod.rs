@@ -1,21 +1,67 @@
 mod bitcoin_script;
 
-use crate::treepp::pushable::{Builder, Pushable};
+use crate::chunker::StateValue;
+use crate::hint_stream::{HintStream, HintStreamError, HintWriter};
+use crate::treepp::Script;
 pub use bitcoin_script::*;
+use stwo_prover::core::air::Air;
+use stwo_prover::core::circle::CirclePoint;
 use stwo_prover::core::fields::qm31::SecureField;
+use stwo_prover::core::{ColumnVec, ComponentVec};
 
 /// Hint for the two eval quotient results involved in the composition polynomial.
+#[derive(Clone)]
 pub struct CompositionHint {
     /// A vector of the quotient evaluation result for each constraint.
     /// We do not set the number of constraints because different AIR would have different ones.
     pub constraint_eval_quotients_by_mask: Vec<SecureField>,
 }
 
-impl Pushable for CompositionHint {
-    fn bitcoin_script_push(self, mut builder: Builder) -> Builder {
+impl CompositionHint {
+    /// Write the hint as pulled by `ScriptAir::eval_composition_polynomial_at_point_gadget`, all
+    /// under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
         for elem in self.constraint_eval_quotients_by_mask.iter() {
-            builder = elem.bitcoin_script_push(builder);
+            writer.write(label, StateValue::QM31(*elem))?;
         }
-        builder
+        Ok(())
     }
 }
+
+/// An AIR that can be verified in Bitcoin script.
+///
+/// The verifier only supports a trace committed in one tree with at least one column, all the
+/// columns having the same log size, and a composition polynomial with a larger log size than
+/// the trace, which is folded into an inner FRI layer. Other AIRs, such as the ones with trace
+/// columns of several log sizes, are rejected with `UnsupportedAirError` by
+/// `VerifierGadget::run_verifier`, `VerifierGadget::run_verifier_stages`, and `verify_hints`.
+pub trait ScriptAir: Air {
+    /// The mask offsets of each trace column, with respect to the trace domain.
+    fn mask_offsets(&self) -> ColumnVec<Vec<usize>>;
+
+    /// Compute the hint for evaluating the composition polynomial at the point from the mask
+    /// values.
+    fn composition_hint(
+        &self,
+        point: CirclePoint<SecureField>,
+        mask_values: &ComponentVec<Vec<SecureField>>,
+    ) -> CompositionHint;
+
+    /// Evaluate the composition polynomial at the point in Bitcoin script.
+    ///
+    /// Input:
+    /// - random_coeff (qm31)
+    /// - the mask values (qm31, for each column and then for each mask offset)
+    /// - point.x, point.y (qm31)
+    ///
+    /// Output:
+    /// - the composition polynomial at the point (qm31)
+    ///
+    /// Hint (pulled through the stream under the label, one qm31 for each element):
+    /// - CompositionHint
+    fn eval_composition_polynomial_at_point_gadget(
+        &self,
+        stream: &mut HintStream,
+        label: &str,
+    ) -> Script;
+}
@@ -0,0 +1,469 @@
+use crate::treepp::*;
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::Instruction;
+use std::fmt::{Display, Formatter};
+use std::ops::RangeInclusive;
+
+/// The maximum number of elements in the stack and the altstack combined, enforced by consensus.
+pub const MAX_STACK_SIZE: usize = 1000;
+
+/// The position of an instruction in a script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScriptPosition {
+    /// Index of the instruction.
+    pub index: usize,
+    /// Offset of the instruction in the script, in bytes.
+    pub offset: usize,
+}
+
+impl Display for ScriptPosition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "instruction {} (byte {})", self.index, self.offset)
+    }
+}
+
+/// The peak depths of the stack and the altstack over all the paths of a script, and where they
+/// are first reached.
+///
+/// When the branches of a conditional leave different depths, both are kept, so the peaks are
+/// upper bounds of the depths of the actual execution.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StackDepthReport {
+    /// Peak depth of the stack.
+    pub max_stack: usize,
+    /// The instruction after which the stack reaches its peak depth.
+    pub max_stack_at: ScriptPosition,
+    /// Peak depth of the altstack.
+    pub max_altstack: usize,
+    /// The instruction after which the altstack reaches its peak depth.
+    pub max_altstack_at: ScriptPosition,
+    /// Peak number of the elements in the stack and the altstack combined.
+    pub max_total: usize,
+    /// The instruction after which the combined number reaches its peak.
+    pub max_total_at: ScriptPosition,
+    /// The possible depths of the stack at the end of the script.
+    pub final_stack: RangeInclusive<usize>,
+}
+
+/// An error of the stack depth analysis.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StackDepthError {
+    /// The script cannot be parsed.
+    InvalidScript(ScriptPosition),
+    /// An instruction pops more elements than the stack can have.
+    StackUnderflow(ScriptPosition),
+    /// An instruction pops more elements than the altstack can have.
+    AltstackUnderflow(ScriptPosition),
+    /// An `OP_ELSE` or an `OP_ENDIF` without its `OP_IF`, or an `OP_IF` without its `OP_ENDIF`
+    /// (at the end of the script).
+    UnbalancedConditional(ScriptPosition),
+    /// The opcode is disabled in tapscript or its effect on the stack is not known.
+    UnsupportedOpcode(ScriptPosition, Opcode),
+    /// The stack and the altstack may hold more than `MAX_STACK_SIZE` elements.
+    LimitExceeded(StackDepthReport),
+}
+
+impl Display for StackDepthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackDepthError::InvalidScript(position) => {
+                write!(f, "the script cannot be parsed at {}", position)
+            }
+            StackDepthError::StackUnderflow(position) => {
+                write!(f, "the stack underflows at {}", position)
+            }
+            StackDepthError::AltstackUnderflow(position) => {
+                write!(f, "the altstack underflows at {}", position)
+            }
+            StackDepthError::UnbalancedConditional(position) => {
+                write!(f, "unbalanced conditional at {}", position)
+            }
+            StackDepthError::UnsupportedOpcode(position, opcode) => {
+                write!(f, "unsupported opcode {} at {}", opcode, position)
+            }
+            StackDepthError::LimitExceeded(report) => write!(
+                f,
+                "{} stack elements at {} exceed the limit of {} (stack: {} at {}, altstack: {} at {})",
+                report.max_total,
+                report.max_total_at,
+                MAX_STACK_SIZE,
+                report.max_stack,
+                report.max_stack_at,
+                report.max_altstack,
+                report.max_altstack_at
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StackDepthError {}
+
+/// The possible depths of the stack and the altstack at some point of the script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct DepthRange {
+    stack: (usize, usize),
+    altstack: (usize, usize),
+}
+
+impl DepthRange {
+    fn join(self, other: Self) -> Self {
+        Self {
+            stack: (
+                self.stack.0.min(other.stack.0),
+                self.stack.1.max(other.stack.1),
+            ),
+            altstack: (
+                self.altstack.0.min(other.altstack.0),
+                self.altstack.1.max(other.altstack.1),
+            ),
+        }
+    }
+}
+
+/// Pop `pops` elements and then push `pushes` elements, or return `None` if there are not enough
+/// elements on any path.
+fn apply(range: (usize, usize), pops: usize, pushes: usize) -> Option<(usize, usize)> {
+    if range.1 < pops {
+        return None;
+    }
+    Some((
+        range.0.saturating_sub(pops) + pushes,
+        range.1 - pops + pushes,
+    ))
+}
+
+/// The number of the elements that an opcode pops from the stack and then pushes to it, except
+/// for the pushes and the control flow.
+pub(crate) fn stack_effect(opcode: Opcode) -> Option<(usize, usize)> {
+    Some(match opcode {
+        OP_NOP | OP_CLTV | OP_CSV | OP_CODESEPARATOR => (0, 0),
+        OP_NOP1 | OP_NOP4 | OP_NOP5 | OP_NOP6 | OP_NOP7 | OP_NOP8 | OP_NOP9 | OP_NOP10 => (0, 0),
+
+        OP_VERIFY | OP_DROP => (1, 0),
+        OP_2DROP => (2, 0),
+        OP_DUP => (1, 2),
+        OP_2DUP => (2, 4),
+        OP_3DUP => (3, 6),
+        OP_OVER => (2, 3),
+        OP_2OVER => (4, 6),
+        OP_NIP => (2, 1),
+        OP_TUCK => (2, 3),
+        OP_SWAP => (2, 2),
+        OP_2SWAP => (4, 4),
+        OP_ROT => (3, 3),
+        OP_2ROT => (6, 6),
+        // the element picked or rolled is on the stack below the index
+        OP_PICK => (2, 2),
+        OP_ROLL => (2, 1),
+        OP_DEPTH => (0, 1),
+        OP_SIZE => (1, 2),
+
+        OP_CAT => (2, 1),
+        OP_EQUAL => (2, 1),
+        OP_EQUALVERIFY => (2, 0),
+
+        OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => (1, 1),
+        OP_ADD
+        | OP_SUB
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_NUMEQUAL
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_MIN
+        | OP_MAX => (2, 1),
+        OP_NUMEQUALVERIFY => (2, 0),
+        OP_WITHIN => (3, 1),
+
+        OP_RIPEMD160 | OP_SHA1 | OP_SHA256 | OP_HASH160 | OP_HASH256 => (1, 1),
+        OP_CHECKSIG => (2, 1),
+        OP_CHECKSIGVERIFY => (2, 0),
+        OP_CHECKSIGADD => (3, 1),
+
+        _ => return None,
+    })
+}
+
+/// Analyze the depths of the stack and the altstack over all the paths of the script, which
+/// starts with `n_hints` elements on the stack (the witness), and fail if the limit of
+/// `MAX_STACK_SIZE` elements may be exceeded.
+///
+/// `OP_IFDUP` is taken as always duplicating, and an `OP_RETURN` ends its path. As in the
+/// consensus rules, each `OP_ELSE` switches between the two paths of its conditional, so that a
+/// conditional with several `OP_ELSE` runs its branches alternately on each path.
+pub fn analyze_stack_depth(
+    script: &Script,
+    n_hints: usize,
+) -> Result<StackDepthReport, StackDepthError> {
+    let mut report = StackDepthReport {
+        max_stack: n_hints,
+        max_stack_at: ScriptPosition::default(),
+        max_altstack: 0,
+        max_altstack_at: ScriptPosition::default(),
+        max_total: n_hints,
+        max_total_at: ScriptPosition::default(),
+        final_stack: n_hints..=n_hints,
+    };
+
+    // the depths on the current path, or `None` if the path has ended
+    let mut current = Some(DepthRange {
+        stack: (n_hints, n_hints),
+        altstack: (0, 0),
+    });
+    // for each open conditional, the depths on its other path, which is not being run
+    let mut conditionals: Vec<Option<DepthRange>> = vec![];
+
+    let mut last_position = ScriptPosition::default();
+    for (index, (offset, instruction)) in script.instruction_indices().enumerate() {
+        let position = ScriptPosition { index, offset };
+        last_position = position;
+
+        let instruction = instruction.map_err(|_| StackDepthError::InvalidScript(position))?;
+        let underflow = StackDepthError::StackUnderflow(position);
+        let alt_underflow = StackDepthError::AltstackUnderflow(position);
+
+        match instruction {
+            Instruction::PushBytes(_) => {
+                if let Some(range) = current.as_mut() {
+                    range.stack = apply(range.stack, 0, 1).unwrap();
+                }
+            }
+            Instruction::Op(opcode) => match opcode {
+                OP_PUSHNUM_NEG1 | OP_PUSHNUM_1 | OP_PUSHNUM_2 | OP_PUSHNUM_3 | OP_PUSHNUM_4
+                | OP_PUSHNUM_5 | OP_PUSHNUM_6 | OP_PUSHNUM_7 | OP_PUSHNUM_8 | OP_PUSHNUM_9
+                | OP_PUSHNUM_10 | OP_PUSHNUM_11 | OP_PUSHNUM_12 | OP_PUSHNUM_13 | OP_PUSHNUM_14
+                | OP_PUSHNUM_15 | OP_PUSHNUM_16 => {
+                    if let Some(range) = current.as_mut() {
+                        range.stack = apply(range.stack, 0, 1).unwrap();
+                    }
+                }
+                OP_IF | OP_NOTIF => {
+                    if let Some(range) = current.as_mut() {
+                        range.stack = apply(range.stack, 1, 0).ok_or(underflow)?;
+                    }
+                    // the other path skips the branch until the next `OP_ELSE`
+                    conditionals.push(current);
+                }
+                OP_ELSE => {
+                    let Some(other) = conditionals.last_mut() else {
+                        return Err(StackDepthError::UnbalancedConditional(position));
+                    };
+                    std::mem::swap(&mut current, other);
+                }
+                OP_ENDIF => {
+                    let Some(other) = conditionals.pop() else {
+                        return Err(StackDepthError::UnbalancedConditional(position));
+                    };
+                    current = match (current, other) {
+                        (Some(a), Some(b)) => Some(a.join(b)),
+                        (a, b) => a.or(b),
+                    };
+                }
+                OP_RETURN => current = None,
+                OP_IFDUP => {
+                    if let Some(range) = current.as_mut() {
+                        range.stack = apply(range.stack, 1, 2).ok_or(underflow)?;
+                    }
+                }
+                OP_TOALTSTACK => {
+                    if let Some(range) = current.as_mut() {
+                        range.stack = apply(range.stack, 1, 0).ok_or(underflow)?;
+                        range.altstack = apply(range.altstack, 0, 1).unwrap();
+                    }
+                }
+                OP_FROMALTSTACK => {
+                    if let Some(range) = current.as_mut() {
+                        range.altstack = apply(range.altstack, 1, 0).ok_or(alt_underflow)?;
+                        range.stack = apply(range.stack, 0, 1).unwrap();
+                    }
+                }
+                _ => {
+                    let (pops, pushes) = stack_effect(opcode)
+                        .ok_or(StackDepthError::UnsupportedOpcode(position, opcode))?;
+                    if let Some(range) = current.as_mut() {
+                        range.stack = apply(range.stack, pops, pushes).ok_or(underflow)?;
+                    }
+                }
+            },
+        }
+
+        if let Some(range) = current {
+            if range.stack.1 > report.max_stack {
+                report.max_stack = range.stack.1;
+                report.max_stack_at = position;
+            }
+            if range.altstack.1 > report.max_altstack {
+                report.max_altstack = range.altstack.1;
+                report.max_altstack_at = position;
+            }
+            if range.stack.1 + range.altstack.1 > report.max_total {
+                report.max_total = range.stack.1 + range.altstack.1;
+                report.max_total_at = position;
+            }
+        }
+    }
+
+    if !conditionals.is_empty() {
+        return Err(StackDepthError::UnbalancedConditional(last_position));
+    }
+
+    // a script whose every path ends with `OP_RETURN` leaves nothing
+    report.final_stack = match current {
+        Some(range) => range.stack.0..=range.stack.1,
+        None => 0..=0,
+    };
+
+    if report.max_total > MAX_STACK_SIZE {
+        return Err(StackDepthError::LimitExceeded(report));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::{analyze_stack_depth, ScriptPosition, StackDepthError, MAX_STACK_SIZE};
+    use crate::chunker::{push_state, run_stages};
+    use crate::fibonacci::FibonacciVerifierGadget;
+    use crate::tests_utils::fibonacci::{hint_and_channel, hint_stream};
+    use crate::treepp::*;
+    use crate::verifier::VerifierConfig;
+    use crate::OP_HINT;
+
+    #[test]
+    fn test_analyze_stack_depth() {
+        let script = script! {
+            OP_HINT OP_HINT
+            OP_2DUP OP_TOALTSTACK OP_TOALTSTACK
+            OP_ADD
+            OP_FROMALTSTACK OP_FROMALTSTACK OP_ADD
+            OP_EQUAL
+        };
+        let report = analyze_stack_depth(&script, 2).unwrap();
+        assert_eq!(report.max_stack, 4);
+        assert_eq!(report.max_stack_at.index, 6);
+        assert_eq!(report.max_altstack, 2);
+        assert_eq!(report.max_altstack_at.index, 8);
+        assert_eq!(report.max_total, 4);
+        assert_eq!(report.final_stack, 1..=1);
+
+        let exec_result = execute_script(script! {
+            1 2
+            { script.clone() }
+        });
+        assert!(exec_result.success);
+
+        // branches that leave different depths give a range
+        let script = script! {
+            OP_IF 1 2 3 OP_ELSE 1 OP_ENDIF
+            OP_NOTIF OP_DROP OP_ENDIF
+        };
+        let report = analyze_stack_depth(&script, 1).unwrap();
+        assert_eq!(report.max_stack, 3);
+        assert_eq!(
+            report.max_stack_at,
+            ScriptPosition {
+                index: 3,
+                offset: 3
+            }
+        );
+        assert_eq!(report.final_stack, 0..=2);
+
+        // each `OP_ELSE` switches the path, so that the first and the third branches run together
+        let script = script! {
+            OP_IF 1 OP_ELSE 1 2 3 OP_ELSE 2 OP_ENDIF
+        };
+        let report = analyze_stack_depth(&script, 1).unwrap();
+        assert_eq!(report.max_stack, 3);
+        assert_eq!(report.final_stack, 2..=3);
+
+        assert_eq!(
+            analyze_stack_depth(&script! { OP_ADD }, 1),
+            Err(StackDepthError::StackUnderflow(ScriptPosition::default()))
+        );
+        assert_eq!(
+            analyze_stack_depth(&script! { OP_FROMALTSTACK }, 0),
+            Err(StackDepthError::AltstackUnderflow(ScriptPosition::default()))
+        );
+        assert!(matches!(
+            analyze_stack_depth(&script! { OP_IF }, 1),
+            Err(StackDepthError::UnbalancedConditional(_))
+        ));
+        assert!(matches!(
+            analyze_stack_depth(&script! { OP_CHECKMULTISIG }, 3),
+            Err(StackDepthError::UnsupportedOpcode(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_limit_exceeded() {
+        let n = MAX_STACK_SIZE / 2;
+        let script = script! {
+            for _ in 0..n {
+                OP_DUP OP_DUP OP_TOALTSTACK
+            }
+        };
+
+        let Err(StackDepthError::LimitExceeded(report)) = analyze_stack_depth(&script, 1) else {
+            panic!("the limit must be exceeded");
+        };
+        assert_eq!(report.max_stack, n + 2);
+        assert_eq!(report.max_altstack, n);
+        assert_eq!(report.max_total, MAX_STACK_SIZE + 1);
+        assert_eq!(report.max_total_at.index, 3 * n - 2);
+
+        let exec_result = execute_script(script! {
+            1
+            { script }
+        });
+        assert!(!exec_result.success);
+
+        assert!(analyze_stack_depth(
+            &script! {
+                for _ in 0..n - 1 {
+                    OP_DUP OP_DUP OP_TOALTSTACK
+                }
+            },
+            1
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_verifier_stack_depth() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let hints = hint.to_elements(&hint_stream(5, claim, &channel)).unwrap();
+
+        let verifier =
+            FibonacciVerifierGadget::run_verifier(5, claim, &channel, VerifierConfig::default())
+                .unwrap();
+        let report = analyze_stack_depth(&verifier, hints.len()).unwrap();
+        assert!(report.final_stack.contains(&1));
+
+        // the analysis bounds the depths of the execution
+        let exec_result = execute_script(script! {
+            { push_state(&hints) }
+            { verifier }
+        });
+        assert!(exec_result.success);
+        assert!(report.max_total >= exec_result.stats.max_nb_stack_items);
+
+        let stages = FibonacciVerifierGadget::run_verifier_stages(
+            5,
+            claim,
+            &channel,
+            VerifierConfig::default(),
+        )
+        .unwrap();
+        let stage_witnesses = run_stages(&stages, hints).unwrap();
+        for (stage, stage_witness) in stages.iter().zip(stage_witnesses.iter()) {
+            let report =
+                analyze_stack_depth(&stage.script(), stage_witness.to_elements().len()).unwrap();
+            assert!(report.max_total <= MAX_STACK_SIZE);
+            assert!(report.final_stack.contains(&1));
+        }
+    }
+}
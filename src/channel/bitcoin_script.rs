@@ -1,3 +1,6 @@
+use crate::chunker::StateItem;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
 use crate::utils::{hash_m31_vec_gadget, trim_m31_gadget};
 use rust_bitcoin_m31::MOD;
@@ -58,7 +61,7 @@ impl Sha256ChannelGadget {
         }
     }
 
-    /// Draw a qm31 element using hints.
+    /// Draw a qm31 element using hints, which are pulled through the stream under the label.
     ///
     /// Input:
     /// - old channel digest
@@ -66,24 +69,36 @@ impl Sha256ChannelGadget {
     /// Output:
     /// - new channel digest
     /// - qm31
-    pub fn draw_felt_with_hint() -> Script {
+    ///
+    /// Hint:
+    /// - DrawHints
+    pub fn draw_felt_with_hint(stream: &mut HintStream, label: &str) -> Script {
         script! {
             OP_DUP OP_SHA256 OP_SWAP
             OP_PUSHBYTES_1 OP_PUSHBYTES_0 OP_CAT OP_SHA256
-            { Self::unpack_multi_m31(4) }
+            { Self::unpack_multi_m31(stream, label, 4) }
         }
     }
 
-    /// Draw queries from the channel, each of logn bits, using hints.
+    /// Draw queries from the channel, each of logn bits, using hints, which are pulled through the
+    /// stream under the label.
     ///
     /// Output:
     ///    channel digest
     ///    all the numbers (m)
-    pub fn draw_numbers_with_hint(m: usize, logn: usize) -> Script {
+    ///
+    /// Hint:
+    /// - DrawHints
+    pub fn draw_numbers_with_hint(
+        stream: &mut HintStream,
+        label: &str,
+        m: usize,
+        logn: usize,
+    ) -> Script {
         script! {
             OP_DUP OP_SHA256 OP_SWAP
             OP_PUSHBYTES_1 OP_PUSHBYTES_0 OP_CAT OP_SHA256
-            { Self::unpack_multi_m31(m) }
+            { Self::unpack_multi_m31(stream, label, m) }
             for i in 0..m {
                 { i } OP_ROLL { trim_m31_gadget(logn) }
             }
@@ -130,11 +145,12 @@ impl Sha256ChannelGadget {
         }
     }
 
-    /// Unpack multiple m31 and put them on the stack.
-    pub fn unpack_multi_m31(m: usize) -> Script {
+    /// Unpack multiple m31 and put them on the stack, with the hints pulled through the stream
+    /// under the label.
+    pub fn unpack_multi_m31(stream: &mut HintStream, label: &str, m: usize) -> Script {
         script! {
             for _ in 0..m {
-                OP_DEPTH OP_1SUB OP_ROLL
+                { stream.pull(label, StateItem::Bytes) }
             }
 
             for _ in 0..m {
@@ -147,7 +163,8 @@ impl Sha256ChannelGadget {
             }
 
             if m % 8 != 0 {
-                OP_DEPTH OP_1SUB OP_ROLL OP_CAT
+                { stream.pull(label, StateItem::Bytes) }
+                OP_CAT
             }
 
             OP_EQUALVERIFY
@@ -163,9 +180,90 @@ impl Sha256ChannelGadget {
     }
 }
 
+/// `Sha256ChannelGadget::mix_digest` as a `Gadget`.
+pub struct MixDigest;
+
+impl Gadget for MixDigest {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest, StateItem::Digest]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        Sha256ChannelGadget::mix_digest()
+    }
+}
+
+/// `Sha256ChannelGadget::mix_felt` as a `Gadget`.
+pub struct MixFelt;
+
+impl Gadget for MixFelt {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31, StateItem::Digest]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        Sha256ChannelGadget::mix_felt()
+    }
+}
+
+/// `Sha256ChannelGadget::draw_felt_with_hint` as a `Gadget`, with the hints under "hint".
+pub struct DrawFelt;
+
+impl Gadget for DrawFelt {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest, StateItem::QM31]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        Sha256ChannelGadget::draw_felt_with_hint(stream, "hint")
+    }
+}
+
+/// `Sha256ChannelGadget::draw_numbers_with_hint` as a `Gadget`, with the hints under "hint".
+pub struct DrawNumbers {
+    /// Number of the numbers.
+    pub m: usize,
+    /// Number of the bits of each number.
+    pub logn: usize,
+}
+
+impl Gadget for DrawNumbers {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        let mut outputs = vec![StateItem::Digest];
+        outputs.extend(vec![StateItem::M31; self.m]);
+        outputs
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        Sha256ChannelGadget::draw_numbers_with_hint(stream, "hint", self.m, self.logn)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::channel::{generate_hints, ChannelWithHint, Sha256Channel, Sha256ChannelGadget};
+    use crate::channel::{
+        generate_hints, ChannelWithHint, DrawFelt, DrawNumbers, MixDigest, MixFelt, Sha256Channel,
+        Sha256ChannelGadget,
+    };
+    use crate::chunker::StateValue;
+    use crate::hint_stream::HintStream;
+    use crate::tests_utils::gadget::check_gadget;
     use crate::tests_utils::report::report_bitcoin_script_size;
     use crate::treepp::*;
     use crate::utils::{get_rand_qm31, hash_m31_vec_gadget, hash_qm31};
@@ -174,6 +272,7 @@ mod test {
     use rand_chacha::ChaCha20Rng;
     use rust_bitcoin_m31::qm31_equalverify;
     use stwo_prover::core::channel::Channel;
+    use stwo_prover::core::fields::m31::M31;
     use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
 
     #[test]
@@ -269,6 +368,9 @@ mod test {
     fn test_draw_8_elements() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
+        let mut stream = HintStream::new();
+        let unpack_script = Sha256ChannelGadget::unpack_multi_m31(&mut stream, "hint", 8);
+
         for _ in 0..100 {
             let mut a = [0u8; 32];
             a.iter_mut().for_each(|v| *v = prng.gen());
@@ -279,12 +381,15 @@ mod test {
 
             let c = channel.digest;
 
+            let mut writer = stream.writer();
+            hint.write(&mut writer, "hint").unwrap();
+
             let script = script! {
-                { hint }
+                { writer.finish_script().unwrap() }
                 { a }
                 OP_DUP OP_SHA256 OP_SWAP
                 OP_PUSHBYTES_1 OP_PUSHBYTES_0 OP_CAT OP_SHA256
-                { Sha256ChannelGadget::unpack_multi_m31(8) }
+                { unpack_script.clone() }
                 for i in 0..8 {
                     { b[i] }
                     OP_EQUALVERIFY
@@ -301,7 +406,8 @@ mod test {
     fn test_draw_felt_with_hint() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
-        let channel_script = Sha256ChannelGadget::draw_felt_with_hint();
+        let mut stream = HintStream::new();
+        let channel_script = Sha256ChannelGadget::draw_felt_with_hint(&mut stream, "hint");
         report_bitcoin_script_size("Channel", "draw_felt_with_hint", channel_script.len());
 
         for _ in 0..100 {
@@ -314,8 +420,11 @@ mod test {
 
             let c = channel.digest;
 
+            let mut writer = stream.writer();
+            hint.write(&mut writer, "hint").unwrap();
+
             let script = script! {
-                { hint }
+                { writer.finish_script().unwrap() }
                 { a }
                 { channel_script.clone() }
                 { b }
@@ -332,7 +441,9 @@ mod test {
     fn test_draw_5numbers_with_hint() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
-        let channel_script = Sha256ChannelGadget::draw_numbers_with_hint(5, 15);
+        let mut stream = HintStream::new();
+        let channel_script =
+            Sha256ChannelGadget::draw_numbers_with_hint(&mut stream, "hint", 5, 15);
 
         report_bitcoin_script_size("Channel", "draw_5numbers_with_hint", channel_script.len());
 
@@ -346,8 +457,11 @@ mod test {
 
             let c = channel.digest;
 
+            let mut writer = stream.writer();
+            hint.write(&mut writer, "hint").unwrap();
+
             let script = script! {
-                { hint }
+                { writer.finish_script().unwrap() }
                 { a }
                 { channel_script.clone() }
                 { b[4] } OP_EQUALVERIFY
@@ -363,6 +477,55 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_gadgets() {
+        check_gadget(&MixDigest, 10, |inputs, _| {
+            let [StateValue::Digest(elem), StateValue::Digest(digest)] = inputs else {
+                unreachable!()
+            };
+            let mut channel = Sha256Channel::new(*digest);
+            channel.mix_digest(*elem);
+            vec![StateValue::Digest(channel.digest)]
+        });
+
+        check_gadget(&MixFelt, 10, |inputs, _| {
+            let [StateValue::QM31(elem), StateValue::Digest(digest)] = inputs else {
+                unreachable!()
+            };
+            let mut channel = Sha256Channel::new(*digest);
+            channel.mix_felts(&[*elem]);
+            vec![StateValue::Digest(channel.digest)]
+        });
+
+        check_gadget(&DrawFelt, 10, |inputs, writer| {
+            let [StateValue::Digest(digest)] = inputs else {
+                unreachable!()
+            };
+            let mut channel = Sha256Channel::new(*digest);
+            let (felt, hint) = channel.draw_felt_and_hints();
+            hint.write(writer, "hint").unwrap();
+            vec![StateValue::Digest(channel.digest), StateValue::QM31(felt)]
+        });
+
+        for (m, logn) in [(5, 15), (8, 20), (3, 4)] {
+            check_gadget(&DrawNumbers { m, logn }, 10, |inputs, writer| {
+                let [StateValue::Digest(digest)] = inputs else {
+                    unreachable!()
+                };
+                let mut channel = Sha256Channel::new(*digest);
+                let (numbers, hint) = channel.draw_queries_and_hints(m, logn);
+                hint.write(writer, "hint").unwrap();
+                let mut outputs = vec![StateValue::Digest(channel.digest)];
+                outputs.extend(
+                    numbers
+                        .into_iter()
+                        .map(|number| StateValue::M31(M31::from(number as u32))),
+                );
+                outputs
+            });
+        }
+    }
+
     #[test]
     fn test_hash_felt() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
@@ -405,9 +568,14 @@ mod test {
 
         let (_, hint) = generate_hints(1, &h);
 
+        let mut stream = HintStream::new();
+        let unpack_script = Sha256ChannelGadget::unpack_multi_m31(&mut stream, "hint", 1);
+        let mut writer = stream.writer();
+        hint.write(&mut writer, "hint").unwrap();
+
         let script = script! {
-            { hint }
-            { Sha256ChannelGadget::unpack_multi_m31(1) }
+            { writer.finish_script().unwrap() }
+            { unpack_script }
             OP_NOT
         };
         let exec_result = execute_script(script);
@@ -1,3 +1,6 @@
+use crate::chunker::StateValue;
+use crate::encoding::integer_to_bytes;
+use crate::hint_stream::{HintStreamError, HintWriter};
 use crate::utils::trim_m31;
 use bitcoin::script::PushBytesBuf;
 use sha2::{Digest, Sha256};
@@ -91,7 +94,7 @@ fn generate_hints(m: usize, extract: &[u8]) -> (Vec<M31>, DrawHints) {
 }
 
 /// Basic hint structure for extracting a single qm31 element.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BitcoinIntegerEncodedData {
     /// negative zero (will be represented by 0x80).
     NegativeZero,
@@ -99,6 +102,16 @@ pub enum BitcoinIntegerEncodedData {
     Other(i64),
 }
 
+impl BitcoinIntegerEncodedData {
+    /// Return the stack element of the integer, minimally encoded.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            BitcoinIntegerEncodedData::NegativeZero => vec![0x80],
+            BitcoinIntegerEncodedData::Other(v) => integer_to_bytes(*v),
+        }
+    }
+}
+
 impl Default for BitcoinIntegerEncodedData {
     fn default() -> Self {
         Self::Other(0)
@@ -116,34 +129,19 @@ impl Pushable for BitcoinIntegerEncodedData {
     }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
 /// Hints for drawing m31 elements.
 pub struct DrawHints(pub Vec<BitcoinIntegerEncodedData>, pub Vec<u8>);
 
-impl Pushable for DrawHints {
-    fn bitcoin_script_push(self, builder: Builder) -> Builder {
-        (&self).bitcoin_script_push(builder)
-    }
-}
-
-impl Pushable for &DrawHints {
-    fn bitcoin_script_push(self, mut builder: Builder) -> Builder {
-        let n = self.0.len();
-
-        if n % 8 == 0 {
-            assert!(self.1.is_empty());
-        } else {
-            assert_eq!(self.1.len(), 32 - (n % 8) * 4);
+impl DrawHints {
+    /// Write the hints as pulled by `Sha256ChannelGadget::unpack_multi_m31`, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        for v in self.0.iter() {
+            writer.write(label, StateValue::Bytes(v.to_bytes()))?;
         }
-
-        for i in 0..n {
-            builder = self.0[i].bitcoin_script_push(builder);
+        if self.0.len() % 8 != 0 {
+            writer.write(label, StateValue::Bytes(self.1.clone()))?;
         }
-
-        if n % 8 != 0 {
-            builder = self.1.clone().bitcoin_script_push(builder);
-        }
-
-        builder
+        Ok(())
     }
 }
@@ -0,0 +1,380 @@
+use crate::chunker::{n_state_elements, StateItem};
+use crate::treepp::*;
+use crate::utils::{hash_m31_vec_gadget, m31_vec_from_bottom_gadget};
+
+/// Gadget for committing the live stack of a stage and reopening it in the next stage.
+pub struct StateCommitmentGadget;
+
+impl StateCommitmentGadget {
+    /// Commit the top n stack elements, from the top to the bottom, by chaining `OP_CAT` and
+    /// `OP_SHA256`.
+    ///
+    /// Input: the state (n elements)
+    /// Output: the commitment
+    pub fn commit(n: usize) -> Script {
+        assert!(n > 0);
+        hash_m31_vec_gadget(n)
+    }
+
+    /// Check the commitment on the top of the stack against the n stack elements below it.
+    ///
+    /// The commitment is not bound to anything else here, see the doc of `Stage`.
+    ///
+    /// Input: the state (n elements), the commitment
+    /// Output: the state (n elements)
+    pub fn reopen(n: usize) -> Script {
+        assert!(n > 0);
+        script! {
+            OP_TOALTSTACK
+            for _ in 0..n {
+                { n - 1 } OP_PICK
+            }
+            { Self::commit(n) }
+            OP_FROMALTSTACK OP_EQUALVERIFY
+        }
+    }
+
+    /// Commit the top items of a state, given from the bottom to the top, which is the commitment
+    /// of their stack elements.
+    ///
+    /// Input: the state (the stack elements of the items)
+    /// Output: the commitment
+    pub fn commit_items(items: &[StateItem]) -> Script {
+        Self::commit(n_state_elements(items))
+    }
+
+    /// Pull the items of a state, given from the bottom to the top, from the hints and check them
+    /// against the commitment on the top of the stack.
+    ///
+    /// Input: the commitment
+    /// Output: the state (the stack elements of the items)
+    ///
+    /// Hint: the state (the stack elements of the items)
+    pub fn reopen_items_from_hints(items: &[StateItem]) -> Script {
+        let n = n_state_elements(items);
+        script! {
+            OP_TOALTSTACK
+            for item in items.iter() {
+                { m31_vec_from_bottom_gadget(item.n_elements()) }
+            }
+            for _ in 0..n {
+                { n - 1 } OP_PICK
+            }
+            { Self::commit(n) }
+            OP_FROMALTSTACK OP_EQUALVERIFY
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::{
+        commit_state, commit_values, push_state, run_stages, Stage, StageFailure,
+        StateCommitmentGadget, StateItem, StateValue,
+    };
+    use crate::fibonacci::FibonacciVerifierGadget;
+    use crate::tests_utils::fibonacci::{hint_and_channel, hint_stream};
+    use crate::tests_utils::report::report_bitcoin_script_size;
+    use crate::treepp::*;
+    use crate::utils::{get_rand_qm31, hash_qm31, num_to_bytes};
+    use crate::verifier::VerifierConfig;
+    use itertools::Itertools;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use stwo_prover::core::fields::m31::M31;
+    use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+
+    // a state that mixes m31 elements and digests
+    fn random_state<R: RngCore>(prng: &mut R, n: usize) -> Vec<Vec<u8>> {
+        (0..n)
+            .map(|_| {
+                if prng.gen_bool(0.5) {
+                    num_to_bytes(M31::reduce(prng.next_u64()))
+                } else {
+                    let mut digest = [0u8; 32];
+                    prng.fill_bytes(&mut digest);
+                    digest.to_vec()
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_commit() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in [1, 2, 10, 50] {
+            let commit_script = StateCommitmentGadget::commit(n);
+            report_bitcoin_script_size(
+                "StateCommitment",
+                &format!("commit({})", n),
+                commit_script.len(),
+            );
+
+            let state = random_state(&mut prng, n);
+            let commitment = commit_state(&state);
+
+            let script = script! {
+                { push_state(&state) }
+                { commit_script.clone() }
+                { commitment.to_vec() }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_reopen() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in [1, 2, 10, 50] {
+            let reopen_script = StateCommitmentGadget::reopen(n);
+            report_bitcoin_script_size(
+                "StateCommitment",
+                &format!("reopen({})", n),
+                reopen_script.len(),
+            );
+
+            let state = random_state(&mut prng, n);
+            let commitment = commit_state(&state);
+
+            let script = script! {
+                { push_state(&state) }
+                { commitment.to_vec() }
+                { reopen_script.clone() }
+                for elem in state.iter().rev() {
+                    { push_state(&[elem.clone()]) } OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // a state that differs in one element must be rejected
+            let mut tampered_state = state.clone();
+            tampered_state[prng.gen_range(0..n)].push(1);
+
+            let script = script! {
+                { push_state(&tampered_state) }
+                { commitment.to_vec() }
+                { reopen_script.clone() }
+                for _ in 0..n {
+                    OP_DROP
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    // a state of typed values that mixes digests, m31 and qm31 elements
+    fn random_values<R: RngCore>(prng: &mut R, n: usize) -> Vec<StateValue> {
+        (0..n)
+            .map(|_| match prng.gen_range(0..3) {
+                0 => {
+                    let mut digest = [0u8; 32];
+                    prng.fill_bytes(&mut digest);
+                    StateValue::Digest(BWSSha256Hash::from(digest.to_vec()))
+                }
+                1 => StateValue::M31(M31::reduce(prng.next_u64())),
+                _ => StateValue::QM31(get_rand_qm31(prng)),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_commit_items() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        // a single qm31 element is committed as its hash
+        let v = get_rand_qm31(&mut prng);
+        assert_eq!(commit_values(&[StateValue::QM31(v)]), hash_qm31(&v));
+
+        for n in [1, 2, 10] {
+            let values = random_values(&mut prng, n);
+            let items = values.iter().map(StateValue::item).collect::<Vec<_>>();
+            let commitment = commit_values(&values);
+
+            let script = script! {
+                for value in values.iter() {
+                    { value }
+                }
+                { StateCommitmentGadget::commit_items(&items) }
+                { commitment.to_vec() }
+                OP_EQUAL
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_reopen_items_from_hints() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in [1, 2, 10] {
+            let values = random_values(&mut prng, n);
+            let items = values.iter().map(StateValue::item).collect::<Vec<_>>();
+            let commitment = commit_values(&values);
+
+            let reopen_script = StateCommitmentGadget::reopen_items_from_hints(&items);
+            report_bitcoin_script_size(
+                "StateCommitment",
+                &format!("reopen_items_from_hints({})", n),
+                reopen_script.len(),
+            );
+
+            let elements = values
+                .iter()
+                .flat_map(StateValue::to_elements)
+                .collect::<Vec<_>>();
+
+            let script = script! {
+                for value in values.iter() {
+                    { value }
+                }
+                { commitment.to_vec() }
+                { reopen_script.clone() }
+                for elem in elements.iter().rev() {
+                    { push_state(&[elem.clone()]) } OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+
+            // a hint that differs from the committed state must be rejected
+            let mut tampered_values = values.clone();
+            tampered_values[prng.gen_range(0..n)] = random_values(&mut prng, 1).pop().unwrap();
+
+            let script = script! {
+                for value in tampered_values.iter() {
+                    { value }
+                }
+                { commitment.to_vec() }
+                { reopen_script.clone() }
+                for _ in 0..elements.len() {
+                    OP_DROP
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_verifier_stages() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let stages = FibonacciVerifierGadget::run_verifier_stages(
+            5,
+            claim,
+            &channel,
+            VerifierConfig::default(),
+        )
+        .unwrap();
+
+        let hints = hint.to_elements(&hint_stream(5, claim, &channel)).unwrap();
+        let witnesses = run_stages(&stages, hints).unwrap();
+        assert_eq!(witnesses.len(), stages.len());
+
+        for (stage, witness) in stages.iter().zip(witnesses.iter()) {
+            report_bitcoin_script_size("FibonacciStages", &stage.name, stage.script().len());
+
+            // each stage accepts its witness, and leaves the commitment of its output state
+            let exec_result = execute_script(script! {
+                { push_state(&witness.to_elements()) }
+                { stage.script() }
+            });
+            assert!(exec_result.success);
+            assert_eq!(exec_result.final_stack.len(), 1);
+            if let Some(output_state) = &witness.output_state {
+                assert_eq!(
+                    exec_result.final_stack.get(0),
+                    commit_state(output_state).to_vec()
+                );
+            }
+
+            // the stage must reject a state that differs from the committed one
+            if stage.input_state_size > 0 {
+                let mut witness_state = witness.input_state.clone();
+                witness_state[0].push(1);
+                let exec_result = execute_script(script! {
+                    { push_state(&witness.hints) }
+                    { push_state(&witness_state) }
+                    { commit_state(&witness.input_state).to_vec() }
+                    { stage.script() }
+                });
+                assert!(!exec_result.success);
+            }
+        }
+    }
+
+    #[test]
+    fn test_run_stages_errors() {
+        let stage = |input_state_size, output_state_size| Stage {
+            name: "stage".to_string(),
+            input_state_size,
+            output_state_size,
+            body: script! { OP_DUP },
+        };
+        let failure = |stages: &[Stage]| run_stages(stages, vec![vec![5]]).unwrap_err().failure;
+
+        // the first stage has no state handed off to it
+        assert_eq!(failure(&[stage(1, None)]), StageFailure::InputStateSize);
+        assert_eq!(
+            failure(&[stage(0, Some(2)), stage(1, None)]),
+            StageFailure::InputStateSize
+        );
+
+        // an empty state cannot be handed off
+        assert_eq!(
+            failure(&[stage(0, Some(0)), stage(0, None)]),
+            StageFailure::EmptyOutputState
+        );
+
+        // the body leaves fewer elements than the output state
+        assert_eq!(
+            failure(&[stage(0, Some(3)), stage(3, None)]),
+            StageFailure::Execution
+        );
+    }
+
+    #[test]
+    fn test_verifier_stage_witnesses() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let stream = hint_stream(5, claim, &channel);
+        let n_hints = hint.to_elements(&stream).unwrap().len();
+
+        let stages = FibonacciVerifierGadget::run_verifier_stages(
+            5,
+            claim,
+            &channel,
+            VerifierConfig::default(),
+        )
+        .unwrap();
+        let witnesses = hint.into_stage_witnesses(&stream, &stages).unwrap();
+        assert_eq!(witnesses.len(), stages.len());
+
+        let mut n_stage_hints = 0;
+        for (stage, witness) in stages.iter().zip(witnesses.iter()) {
+            // the input state and its commitment follow the hints of the stage
+            n_stage_hints += witness.len() - stage.input_state_size;
+            if stage.input_state_size > 0 {
+                n_stage_hints -= 1;
+            }
+
+            let elements = witness.iter().map(|elem| elem.to_vec()).collect_vec();
+            let exec_result = execute_script(script! {
+                { push_state(&elements) }
+                { stage.script() }
+            });
+            assert!(exec_result.success);
+            assert_eq!(exec_result.final_stack.len(), 1);
+        }
+        assert_eq!(n_stage_hints, n_hints);
+    }
+}
@@ -0,0 +1,389 @@
+mod bitcoin_script;
+pub use bitcoin_script::*;
+
+use crate::treepp::pushable::{Builder, Pushable};
+use crate::treepp::*;
+use crate::utils::num_to_bytes;
+use bitcoin::opcodes::all::{OP_PUSHNUM_1, OP_PUSHNUM_16, OP_PUSHNUM_NEG1};
+use bitcoin::script::{Instruction, PushBytesBuf};
+use bitcoin::Witness;
+use bitcoin_scriptexec::execute_script;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use stwo_prover::core::circle::CirclePoint;
+use stwo_prover::core::fields::m31::M31;
+use stwo_prover::core::fields::qm31::QM31;
+use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+
+/// A stage of a script that is split into multiple tapscripts.
+///
+/// Each stage, except the first one, starts by reopening the commitment of the live stack left by
+/// the previous stage, and each stage, except the last one, ends by committing its live stack.
+///
+/// The commitment that a stage reopens comes from its witness, so a stage on its own only checks
+/// the input state against a commitment chosen by the spender, and nothing binds it to the output
+/// state of the previous stage. Chunking is only sound inside a `CovenantChain`, which checks that
+/// the spent output comes from a transaction carrying this commitment.
+#[derive(Clone, Debug)]
+pub struct Stage {
+    /// Name of the stage.
+    pub name: String,
+    /// Number of the stack elements handed off by the previous stage.
+    pub input_state_size: usize,
+    /// Number of the stack elements handed off to the next stage, or `None` for the last stage.
+    ///
+    /// It must not be `Some(0)`, as an empty state cannot be committed, see `run_stages`.
+    pub output_state_size: Option<usize>,
+    /// The script of the stage, without the reopening and the commitment of the state.
+    pub body: Script,
+}
+
+impl Stage {
+    /// Return the tapscript of the stage.
+    ///
+    /// Input: the hints of the stage, the input state, and its commitment (if the input state is
+    /// not empty).
+    ///
+    /// Output: the commitment of the output state, or the result of the body for the last stage.
+    ///
+    /// The stage fails if any of its hints is left when the output state is committed.
+    pub fn script(&self) -> Script {
+        let commit = match self.output_state_size {
+            Some(output_state_size) => script! {
+                OP_DEPTH { output_state_size } OP_EQUALVERIFY
+                { StateCommitmentGadget::commit(output_state_size) }
+            },
+            None => script! {},
+        };
+
+        script! {
+            if self.input_state_size > 0 {
+                { StateCommitmentGadget::reopen(self.input_state_size) }
+            }
+            { self.body.clone() }
+            { commit }
+        }
+    }
+}
+
+/// Compute the commitment of a state, given as the stack elements from the bottom to the top,
+/// as `StateCommitmentGadget::commit` does.
+///
+/// The state must not be empty.
+pub(crate) fn commit_state(state: &[Vec<u8>]) -> [u8; 32] {
+    assert!(!state.is_empty());
+
+    let mut res = [0u8; 32];
+
+    let mut hasher = Sha256::new();
+    Digest::update(&mut hasher, state.last().unwrap());
+    res.copy_from_slice(hasher.finalize().as_slice());
+
+    for elem in state.iter().rev().skip(1) {
+        let mut hasher = Sha256::new();
+        Digest::update(&mut hasher, elem);
+        Digest::update(&mut hasher, res);
+        res.copy_from_slice(hasher.finalize().as_slice());
+    }
+
+    res
+}
+
+/// The type of an item of a state, which takes one or more stack elements.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateItem {
+    /// A SHA256 digest.
+    Digest,
+    /// A m31 element.
+    M31,
+    /// A qm31 element, as its four m31 limbs.
+    QM31,
+    /// A point on the circle curve over the qm31 field, as its x and y coordinates.
+    CirclePoint,
+    /// Any single stack element, such as a part of a draw hint.
+    Bytes,
+}
+
+impl StateItem {
+    /// Return the number of the stack elements of the item.
+    pub fn n_elements(&self) -> usize {
+        match self {
+            StateItem::Digest | StateItem::M31 | StateItem::Bytes => 1,
+            StateItem::QM31 => 4,
+            StateItem::CirclePoint => 8,
+        }
+    }
+}
+
+/// Return the number of the stack elements of the items.
+pub fn n_state_elements(items: &[StateItem]) -> usize {
+    items.iter().map(StateItem::n_elements).sum()
+}
+
+/// A value of an item of a state.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateValue {
+    /// A SHA256 digest.
+    Digest(BWSSha256Hash),
+    /// A m31 element.
+    M31(M31),
+    /// A qm31 element.
+    QM31(QM31),
+    /// A point on the circle curve over the qm31 field.
+    CirclePoint(CirclePoint<QM31>),
+    /// Any single stack element.
+    Bytes(Vec<u8>),
+}
+
+impl StateValue {
+    /// Return the type of the value.
+    pub fn item(&self) -> StateItem {
+        match self {
+            StateValue::Digest(_) => StateItem::Digest,
+            StateValue::M31(_) => StateItem::M31,
+            StateValue::QM31(_) => StateItem::QM31,
+            StateValue::CirclePoint(_) => StateItem::CirclePoint,
+            StateValue::Bytes(_) => StateItem::Bytes,
+        }
+    }
+
+    /// Return the stack elements of the value, from the bottom to the top, as it is pushed.
+    pub fn to_elements(&self) -> Vec<Vec<u8>> {
+        match self {
+            StateValue::Digest(v) => vec![v.as_ref().to_vec()],
+            StateValue::M31(v) => vec![num_to_bytes(*v)],
+            StateValue::QM31(v) => [v.1 .1, v.1 .0, v.0 .1, v.0 .0]
+                .into_iter()
+                .map(num_to_bytes)
+                .collect(),
+            StateValue::CirclePoint(p) => [p.x, p.y]
+                .into_iter()
+                .flat_map(|v| StateValue::QM31(v).to_elements())
+                .collect(),
+            StateValue::Bytes(v) => vec![v.clone()],
+        }
+    }
+}
+
+impl Pushable for StateValue {
+    fn bitcoin_script_push(self, builder: Builder) -> Builder {
+        (&self).bitcoin_script_push(builder)
+    }
+}
+
+impl Pushable for &StateValue {
+    fn bitcoin_script_push(self, builder: Builder) -> Builder {
+        match self {
+            StateValue::Digest(v) => (*v).bitcoin_script_push(builder),
+            StateValue::M31(v) => v.bitcoin_script_push(builder),
+            StateValue::QM31(v) => v.bitcoin_script_push(builder),
+            StateValue::CirclePoint(p) => (*p).bitcoin_script_push(builder),
+            // with the minimal push, as `push_state` does
+            StateValue::Bytes(v) => match v.as_slice() {
+                [v @ 1..=16] => builder.push_int(*v as i64),
+                [0x81] => builder.push_int(-1),
+                _ => builder.push_slice(PushBytesBuf::try_from(v.clone()).unwrap()),
+            },
+        }
+    }
+}
+
+/// Compute the commitment of a state of typed values, from the bottom to the top, as
+/// `StateCommitmentGadget::commit_items` does.
+///
+/// The state must not be empty.
+pub(crate) fn commit_values(values: &[StateValue]) -> [u8; 32] {
+    commit_state(
+        &values
+            .iter()
+            .flat_map(StateValue::to_elements)
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Push the elements of a state, from the bottom to the top, each with its minimal push.
+pub fn push_state(state: &[Vec<u8>]) -> Script {
+    script! {
+        for elem in state.iter() {
+            { push_state_element(elem) }
+        }
+    }
+}
+
+fn push_state_element(elem: &[u8]) -> Script {
+    match elem {
+        [v @ 1..=16] => script! { { *v as u32 } },
+        [0x81] => script! { OP_1NEGATE },
+        _ => script! { { elem.to_vec() } },
+    }
+}
+
+/// Return the stack elements left by a script made only of pushes, from the bottom to the top,
+/// such as the script of the hints.
+///
+/// The script must be made only of pushes.
+pub(crate) fn script_to_elements(script: &Script) -> Vec<Vec<u8>> {
+    script
+        .instructions()
+        .map(|instruction| match instruction.unwrap() {
+            Instruction::PushBytes(bytes) => bytes.as_bytes().to_vec(),
+            Instruction::Op(op) if op == OP_PUSHNUM_NEG1 => vec![0x81],
+            Instruction::Op(op)
+                if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&op.to_u8()) =>
+            {
+                vec![op.to_u8() - OP_PUSHNUM_1.to_u8() + 1]
+            }
+            Instruction::Op(op) => panic!("{} is not a push", op),
+        })
+        .collect()
+}
+
+/// The witness of a stage, found by running the stages one after another.
+#[derive(Clone, Debug)]
+pub struct StageWitness {
+    /// The hints consumed by the stage.
+    pub hints: Vec<Vec<u8>>,
+    /// The state handed off by the previous stage.
+    pub input_state: Vec<Vec<u8>>,
+    /// The state handed off to the next stage, or `None` for the last stage.
+    pub output_state: Option<Vec<Vec<u8>>>,
+}
+
+impl StageWitness {
+    /// Return the stack elements of the witness, from the bottom to the top: the hints, the input
+    /// state, and its commitment (if the input state is not empty).
+    pub fn to_elements(&self) -> Vec<Vec<u8>> {
+        let mut elements = self.hints.clone();
+        if !self.input_state.is_empty() {
+            elements.extend(self.input_state.iter().cloned());
+            elements.push(commit_state(&self.input_state).to_vec());
+        }
+        elements
+    }
+
+    /// Return the witness of the stage, without the script and the control block of its leaf.
+    pub fn to_witness(&self) -> Witness {
+        Witness::from_slice(&self.to_elements())
+    }
+}
+
+/// The reason why a stage cannot be run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StageFailure {
+    /// The state handed off by the previous stage, which is empty for the first stage, does not
+    /// have the input state size of the stage.
+    InputStateSize,
+    /// The output state size is `Some(0)`, and an empty state cannot be committed.
+    EmptyOutputState,
+    /// The stage fails on the hints.
+    Execution,
+}
+
+/// An error when running a stage.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageError {
+    /// Index of the stage that fails.
+    pub stage_index: usize,
+    /// Name of the stage that fails.
+    pub stage_name: String,
+    /// The reason of the failure.
+    pub failure: StageFailure,
+}
+
+impl Display for StageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self.failure {
+            StageFailure::InputStateSize => "does not take the state handed off to it",
+            StageFailure::EmptyOutputState => "hands off an empty state",
+            StageFailure::Execution => "fails on the hints",
+        };
+        write!(
+            f,
+            "stage {} ({}) {}",
+            self.stage_index, self.stage_name, reason
+        )
+    }
+}
+
+impl std::error::Error for StageError {}
+
+/// Run the stages one after another on the hints, given as the stack elements from the bottom to
+/// the top, and split the hints and the states among the stages.
+///
+/// Each stage is run first on all the remaining hints to find how many of them it consumes and the
+/// state it hands off, and then checked on its own witness.
+///
+/// An error is returned if a stage does not take the state handed off to it, hands off an empty
+/// state, or fails on the hints.
+pub fn run_stages(
+    stages: &[Stage],
+    mut hints: Vec<Vec<u8>>,
+) -> Result<Vec<StageWitness>, StageError> {
+    let mut witnesses = vec![];
+    let mut state: Vec<Vec<u8>> = vec![];
+
+    for (stage_index, stage) in stages.iter().enumerate() {
+        let err = |failure| StageError {
+            stage_index,
+            stage_name: stage.name.clone(),
+            failure,
+        };
+
+        if state.len() != stage.input_state_size {
+            return Err(err(StageFailure::InputStateSize));
+        }
+        if stage.output_state_size == Some(0) {
+            return Err(err(StageFailure::EmptyOutputState));
+        }
+
+        let witness = match stage.output_state_size {
+            None => StageWitness {
+                hints: std::mem::take(&mut hints),
+                input_state: std::mem::take(&mut state),
+                output_state: None,
+            },
+            Some(output_state_size) => {
+                let exec_result = execute_script(script! {
+                    { push_state(&hints) }
+                    { push_state(&state) }
+                    if stage.input_state_size > 0 {
+                        { commit_state(&state).to_vec() }
+                        { StateCommitmentGadget::reopen(stage.input_state_size) }
+                    }
+                    { stage.body.clone() }
+                });
+
+                let depth = exec_result.final_stack.len();
+                if exec_result.error.is_some()
+                    || depth < output_state_size
+                    || depth > hints.len() + output_state_size
+                {
+                    return Err(err(StageFailure::Execution));
+                }
+
+                let output_state = (depth - output_state_size..depth)
+                    .map(|i| exec_result.final_stack.get(i))
+                    .collect::<Vec<_>>();
+                let n_consumed_hints = hints.len() + output_state_size - depth;
+
+                StageWitness {
+                    hints: hints.drain(..n_consumed_hints).collect(),
+                    input_state: std::mem::replace(&mut state, output_state.clone()),
+                    output_state: Some(output_state),
+                }
+            }
+        };
+
+        let exec_result = execute_script(script! {
+            { push_state(&witness.to_elements()) }
+            { stage.script() }
+        });
+        if !exec_result.success || exec_result.final_stack.len() != 1 {
+            return Err(err(StageFailure::Execution));
+        }
+
+        witnesses.push(witness);
+    }
+
+    Ok(witnesses)
+}
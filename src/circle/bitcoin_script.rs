@@ -1,3 +1,6 @@
+use crate::chunker::StateItem;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
 use rust_bitcoin_m31::{
     push_qm31_one, qm31_add, qm31_complex_conjugate, qm31_copy, qm31_double, qm31_dup,
@@ -253,6 +256,40 @@ impl CirclePointGadget {
     }
 }
 
+/// `CirclePointGadget::add` as a `Gadget`.
+pub struct CirclePointAdd;
+
+impl Gadget for CirclePointAdd {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::CirclePoint, StateItem::CirclePoint]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::CirclePoint]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        CirclePointGadget::add()
+    }
+}
+
+/// `CirclePointGadget::double_x` as a `Gadget`.
+pub struct CirclePointDoubleX;
+
+impl Gadget for CirclePointDoubleX {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        CirclePointGadget::double_x()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use num_traits::One;
@@ -267,7 +304,9 @@ mod test {
     use stwo_prover::core::fields::qm31::QM31;
     use stwo_prover::core::fields::{ComplexConjugate, Field, FieldExpOps};
 
-    use crate::circle::CirclePointGadget;
+    use crate::chunker::StateValue;
+    use crate::circle::{CirclePointAdd, CirclePointDoubleX, CirclePointGadget};
+    use crate::tests_utils::gadget::check_gadget;
     use crate::utils::get_rand_qm31;
 
     #[test]
@@ -321,6 +360,23 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_gadgets() {
+        check_gadget(&CirclePointAdd, 20, |inputs, _| {
+            let [StateValue::CirclePoint(p), StateValue::CirclePoint(q)] = inputs else {
+                unreachable!()
+            };
+            vec![StateValue::CirclePoint(*p + *q)]
+        });
+
+        check_gadget(&CirclePointDoubleX, 20, |inputs, _| {
+            let [StateValue::QM31(x)] = inputs else {
+                unreachable!()
+            };
+            vec![StateValue::QM31(x.square().double() - QM31::one())]
+        });
+    }
+
     #[test]
     fn test_add_constant_point() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
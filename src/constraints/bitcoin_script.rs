@@ -1,3 +1,6 @@
+use crate::chunker::StateItem;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
 use crate::{circle::CirclePointGadget, treepp::*};
 use rust_bitcoin_m31::{
     cm31_add, cm31_copy, cm31_double, cm31_drop, cm31_fromaltstack, cm31_mul, cm31_mul_m31,
@@ -159,9 +162,45 @@ impl ConstraintsGadget {
     }
 }
 
+/// `ConstraintsGadget::coset_vanishing` of a coset as a `Gadget`.
+pub struct CosetVanishing(pub Coset);
+
+impl Gadget for CosetVanishing {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::CirclePoint]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        ConstraintsGadget::coset_vanishing(self.0)
+    }
+}
+
+/// `ConstraintsGadget::fast_pair_vanishing` as a `Gadget`.
+pub struct FastPairVanishing;
+
+impl Gadget for FastPairVanishing {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::CirclePoint, StateItem::M31, StateItem::M31]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        ConstraintsGadget::fast_pair_vanishing()
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::constraints::fast_pair_vanishing;
+    use crate::chunker::StateValue;
+    use crate::constraints::{fast_pair_vanishing, CosetVanishing, FastPairVanishing};
+    use crate::tests_utils::gadget::check_gadget;
     use crate::utils::get_rand_qm31;
     use crate::{
         constraints::ConstraintsGadget, tests_utils::report::report_bitcoin_script_size, treepp::*,
@@ -283,6 +322,28 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_gadgets() {
+        check_gadget(&FastPairVanishing, 20, |inputs, _| {
+            let [StateValue::CirclePoint(e0), StateValue::M31(x), StateValue::M31(y)] = inputs
+            else {
+                unreachable!()
+            };
+            let p = CirclePoint { x: *x, y: *y };
+            vec![StateValue::QM31(fast_pair_vanishing(*e0, p))]
+        });
+
+        for log_size in 5..10 {
+            let coset = Coset::subgroup(log_size);
+            check_gadget(&CosetVanishing(coset), 5, |inputs, _| {
+                let [StateValue::CirclePoint(z)] = inputs else {
+                    unreachable!()
+                };
+                vec![StateValue::QM31(coset_vanishing(coset, *z))]
+            });
+        }
+    }
+
     #[test]
     fn test_column_line_coeffs() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
@@ -0,0 +1,419 @@
+use crate::covenant::{
+    challenge_tag_prefix, commitment_output_prefix, output_script_with_length,
+    tap_sighash_tag_prefix, GENERATOR_X,
+};
+use crate::treepp::*;
+use bitcoin::Amount;
+
+/// Gadget for the `OP_CAT` covenant, which introspects the spending transaction through the
+/// Schnorr signature with both the secret key and the nonce set to one.
+///
+/// The signature is `G.x || (e + 1)`, where `e` is the BIP-340 challenge of the sighash. The
+/// spender grinds the lock time until the last byte of `e` is zero, so that the script can compute
+/// `e + 1` by replacing that byte with one, and `OP_CHECKSIG` then checks that the sighash built by
+/// the script is the one of the transaction.
+pub struct CovenantGadget;
+
+impl CovenantGadget {
+    /// Pull the parts of the transaction from the top of the stack and keep the ones needed by
+    /// `epilogue` in the altstack.
+    ///
+    /// If the stage has an input state, the previous transaction is rebuilt from its parts and the
+    /// commitment of the input state (on the top of the stack below the parts), so that the spent
+    /// output must be the first output of a transaction whose second output carries this
+    /// commitment.
+    ///
+    /// The first output is fixed to pay the spent value minus `fee` to `output_script_pubkey`,
+    /// which is the script pubkey of the next stage or the payout one.
+    ///
+    /// Input:
+    /// - spent value, as a number
+    /// - tapleaf hash (32 bytes)
+    /// - either sha_prevouts (32 bytes) for the first stage, or the lock time (4 bytes), the
+    ///   serialized inputs, and the version (4 bytes) of the previous transaction
+    /// - spent script pubkey, with its length (34 bytes)
+    /// - sequence (4 bytes)
+    /// - lock time (4 bytes)
+    /// - version (4 bytes)
+    /// - the challenge without its last byte (31 bytes)
+    ///
+    /// Output: (nothing)
+    pub fn prologue(has_input_state: bool, fee: Amount, output_script_pubkey: &Script) -> Script {
+        script! {
+            // keep the challenge prefix until the signature is built
+            OP_TOALTSTACK
+
+            // epoch, hash type, version, lock time
+            OP_SWAP OP_CAT
+            { vec![0x00u8, 0x00u8] } OP_SWAP OP_CAT
+
+            // sha_sequences
+            OP_SWAP OP_SHA256 OP_TOALTSTACK
+
+            // sha_scriptpubkeys
+            OP_SWAP OP_DUP OP_SHA256 OP_TOALTSTACK
+
+            // sha_amounts, with the spent value from the bottom of the parts
+            if has_input_state {
+                6 OP_PICK
+            } else {
+                4 OP_PICK
+            }
+            { Self::value_to_bytes() }
+            OP_DUP OP_SHA256 OP_TOALTSTACK
+
+            if has_input_state {
+                // the first output of the previous transaction, the spent one
+                OP_SWAP OP_CAT
+                2 OP_SWAP OP_CAT
+
+                // the second output of the previous transaction, with the input state commitment
+                7 OP_PICK
+                { commitment_output_prefix() } OP_SWAP OP_CAT
+                OP_CAT
+
+                // version || inputs || outputs || lock time
+                OP_SWAP OP_TOALTSTACK
+                OP_SWAP OP_ROT OP_CAT
+                OP_SWAP OP_CAT
+                OP_SWAP OP_CAT
+
+                // sha_prevouts for the only input, which spends the first output
+                OP_SHA256 OP_SHA256
+                { vec![0x00u8; 4] } OP_CAT
+                OP_SHA256
+
+                OP_FROMALTSTACK OP_SWAP OP_CAT
+            } else {
+                OP_2DROP
+                OP_SWAP OP_CAT
+            }
+
+            OP_FROMALTSTACK OP_CAT
+            OP_FROMALTSTACK OP_CAT
+            OP_FROMALTSTACK OP_CAT
+
+            // spend type, input index, tapleaf hash, key version, codesep position
+            OP_SWAP
+            { vec![0x02u8, 0x00, 0x00, 0x00, 0x00] } OP_SWAP OP_CAT
+            { vec![0x00u8, 0xff, 0xff, 0xff, 0xff] } OP_CAT
+
+            OP_SWAP OP_TOALTSTACK OP_TOALTSTACK
+
+            // the first output, with the spent value minus the fee
+            { fee.to_sat() as i64 } OP_SUB
+            { Self::value_to_bytes() }
+            { output_script_with_length(output_script_pubkey) } OP_CAT
+            OP_TOALTSTACK
+        }
+    }
+
+    /// Serialize a value as in an output, as 8 bytes in little endian.
+    ///
+    /// Input: the value, as a number, which must not be negative
+    /// Output: the serialized value (8 bytes)
+    pub fn value_to_bytes() -> Script {
+        script! {
+            // with the minimal encoding, so that the bytes are the ones of the value
+            0 OP_ADD
+            OP_DUP 0 OP_GREATERTHANOREQUAL OP_VERIFY
+
+            // pad the bytes of the number, which has at most four, with zeros
+            OP_SIZE
+            for n_bytes in 0..=4 {
+                OP_DUP { n_bytes } OP_EQUAL
+                OP_IF
+                    OP_SWAP { vec![0x00u8; 8 - n_bytes] } OP_CAT OP_SWAP
+                OP_ENDIF
+            }
+            OP_DROP
+        }
+    }
+
+    /// Complete the sighash with the outputs and check it with `OP_CHECKSIG`.
+    ///
+    /// If there is a next stage, the second output must carry the commitment of the output state
+    /// in an `OP_RETURN`; otherwise, the first output is the only one, and the result of the stage
+    /// is verified first.
+    ///
+    /// Input: the commitment of the output state, or the result of the last stage
+    /// Output: the result of `OP_CHECKSIG`
+    pub fn epilogue(has_next_stage: bool) -> Script {
+        script! {
+            if has_next_stage {
+                { commitment_output_prefix() } OP_SWAP OP_CAT
+                OP_FROMALTSTACK
+                OP_SWAP OP_CAT
+                OP_SHA256
+            } else {
+                OP_VERIFY
+                OP_FROMALTSTACK OP_SHA256
+            }
+
+            // the sighash message
+            OP_FROMALTSTACK OP_FROMALTSTACK
+            OP_ROT OP_CAT OP_SWAP OP_CAT
+
+            { tap_sighash_tag_prefix().to_vec() } OP_SWAP OP_CAT OP_SHA256
+
+            // the challenge, whose last byte must be zero
+            { challenge_tag_prefix() } OP_SWAP OP_CAT OP_SHA256
+            OP_FROMALTSTACK
+            OP_DUP { vec![0x00u8] } OP_CAT OP_ROT OP_EQUALVERIFY
+
+            // the signature G.x || (e + 1) under the public key G.x
+            { GENERATOR_X.to_vec() } OP_SWAP OP_CAT 1 OP_CAT
+            { GENERATOR_X.to_vec() } OP_CHECKSIG
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::{commit_state, push_state, run_stages, StateCommitmentGadget};
+    use crate::covenant::{CovenantChain, CovenantError, CovenantGadget, MAX_COVENANT_VALUE};
+    use crate::fibonacci::FibonacciVerifierGadget;
+    use crate::taproot::{execute_spend, unspendable_internal_key};
+    use crate::tests_utils::fibonacci::{hint_and_channel, hint_stream};
+    use crate::tests_utils::report::report_bitcoin_script_size;
+    use crate::treepp::*;
+    use crate::verifier::VerifierConfig;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, Transaction, TxOut, Txid};
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn test_value_to_bytes() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut values = vec![0u64, 1, 0x7f, 0x80, 0xff, 0x8000, 0x7fff_ffff];
+        values.extend((0..10).map(|_| prng.gen_range(0..=0x7fff_ffff)));
+
+        for value in values {
+            let exec_result = execute_script(script! {
+                { value as i64 }
+                { CovenantGadget::value_to_bytes() }
+                { value.to_le_bytes().to_vec() }
+                OP_EQUAL
+            });
+            assert!(exec_result.success);
+        }
+
+        // a negative value is rejected
+        let exec_result = execute_script(script! {
+            { vec![0x01u8, 0x80] }
+            { CovenantGadget::value_to_bytes() }
+            OP_DROP OP_TRUE
+        });
+        assert!(!exec_result.success);
+
+        // a negative zero is zero
+        let exec_result = execute_script(script! {
+            { vec![0x80u8] }
+            { CovenantGadget::value_to_bytes() }
+            { vec![0x00u8; 8] }
+            OP_EQUAL
+        });
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_verifier_covenant_chain() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let stages = FibonacciVerifierGadget::run_verifier_stages(
+            5,
+            claim,
+            &channel,
+            VerifierConfig::default(),
+        )
+        .unwrap();
+        let n_stages = stages.len();
+
+        let fee = Amount::from_sat(10_000);
+        let payout_script_pubkey = Script::from_bytes(vec![0x51]);
+        let chain = CovenantChain::new(
+            stages,
+            unspendable_internal_key(),
+            fee,
+            payout_script_pubkey.clone(),
+        )
+        .unwrap();
+
+        let funding = TxOut {
+            value: Amount::from_sat(100_000_000),
+            script_pubkey: chain.script_pubkey(),
+        };
+
+        let hints = hint.to_elements(&hint_stream(5, claim, &channel)).unwrap();
+        let txs = chain
+            .build_transactions(
+                OutPoint::new(Txid::all_zeros(), 0),
+                funding.value,
+                hints.clone(),
+            )
+            .unwrap();
+        assert_eq!(txs.len(), n_stages);
+
+        let stage_witnesses = run_stages(&chain.stages, hints).unwrap();
+
+        let mut spent = funding;
+        for (i, tx) in txs.iter().enumerate() {
+            report_bitcoin_script_size(
+                "FibonacciCovenant",
+                &chain.outputs[i].leaves[0].name,
+                chain.outputs[i].leaves[0].script.len(),
+            );
+
+            let exec_result = execute_spend(tx, &[spent.clone()], 0).unwrap();
+            assert!(exec_result.success);
+            assert_eq!(exec_result.final_stack_len, 1);
+            assert_eq!(tx.output[0].value, spent.value - fee);
+
+            let previous_tx = i.checked_sub(1).map(|j| &txs[j]);
+            // a transaction changed as the spender wishes, with the lock time ground and the
+            // witness built again, which must be rejected
+            let tampered_spend_fails = |tamper: &dyn Fn(&mut Transaction)| {
+                let mut tampered_tx = tx.clone();
+                tamper(&mut tampered_tx);
+                chain
+                    .complete_transaction(
+                        i,
+                        &mut tampered_tx,
+                        &stage_witnesses[i],
+                        &spent,
+                        previous_tx,
+                    )
+                    .unwrap();
+                !execute_spend(&tampered_tx, &[spent.clone()], 0)
+                    .unwrap()
+                    .success
+            };
+
+            // the first output must keep the spent value minus the fee
+            assert!(tampered_spend_fails(&|tx: &mut Transaction| tx.output
+                [0]
+            .value -=
+                Amount::from_sat(1)));
+
+            if i + 1 < n_stages {
+                assert_eq!(
+                    tx.output[0].script_pubkey,
+                    chain.outputs[i + 1].script_pubkey
+                );
+
+                // the output must go to the next stage with the committed state
+                assert!(tampered_spend_fails(&|tx: &mut Transaction| {
+                    tx.output[1].script_pubkey = Script::new_op_return([0u8; 32])
+                }));
+                assert!(tampered_spend_fails(&|tx: &mut Transaction| {
+                    tx.output[0].script_pubkey = chain.script_pubkey()
+                }));
+            } else {
+                assert_eq!(
+                    tx.output,
+                    vec![TxOut {
+                        value: spent.value - fee,
+                        script_pubkey: payout_script_pubkey.clone(),
+                    }]
+                );
+
+                // the payout must go to the payout script pubkey, without another output
+                assert!(tampered_spend_fails(&|tx: &mut Transaction| {
+                    tx.output[0].script_pubkey = Script::from_bytes(vec![0x52])
+                }));
+                assert!(tampered_spend_fails(&|tx: &mut Transaction| {
+                    tx.output.push(TxOut {
+                        value: Amount::ZERO,
+                        script_pubkey: Script::new_op_return([0u8; 32]),
+                    })
+                }));
+            }
+
+            spent = tx.output[0].clone();
+        }
+
+        // the chain must start with a stage
+        assert!(matches!(
+            CovenantChain::new(
+                vec![],
+                unspendable_internal_key(),
+                Amount::from_sat(10_000),
+                payout_script_pubkey.clone(),
+            ),
+            Err(CovenantError::NoStages)
+        ));
+
+        // the fee and the values must be numbers in Bitcoin script
+        assert!(matches!(
+            CovenantChain::new(
+                vec![],
+                unspendable_internal_key(),
+                Amount::from_sat(MAX_COVENANT_VALUE + 1),
+                payout_script_pubkey,
+            ),
+            Err(CovenantError::ValueOutOfRange)
+        ));
+    }
+
+    #[test]
+    fn test_verifier_covenant_forged_state() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let stages = FibonacciVerifierGadget::run_verifier_stages(
+            5,
+            claim,
+            &channel,
+            VerifierConfig::default(),
+        )
+        .unwrap();
+        let chain = CovenantChain::new(
+            stages,
+            unspendable_internal_key(),
+            Amount::from_sat(10_000),
+            Script::from_bytes(vec![0x51]),
+        )
+        .unwrap();
+
+        let hints = hint.to_elements(&hint_stream(5, claim, &channel)).unwrap();
+        let txs = chain
+            .build_transactions(
+                OutPoint::new(Txid::all_zeros(), 0),
+                Amount::from_sat(100_000_000),
+                hints.clone(),
+            )
+            .unwrap();
+        let stage_witnesses = run_stages(&chain.stages, hints).unwrap();
+
+        // a forged input state of the second stage, with its own commitment in the witness
+        let mut forged_witness = stage_witnesses[1].clone();
+        forged_witness.input_state[0].push(1);
+        let n = chain.stages[1].input_state_size;
+
+        // on its own, the reopening only checks the state against the commitment in the witness
+        let exec_result = execute_script(script! {
+            { push_state(&forged_witness.input_state) }
+            { commit_state(&forged_witness.input_state).to_vec() }
+            { StateCommitmentGadget::reopen(n) }
+            for _ in 0..n {
+                OP_DROP
+            }
+            OP_TRUE
+        });
+        assert!(exec_result.success);
+
+        // the chain binds the commitment to the output of the previous transaction
+        let spent = txs[0].output[0].clone();
+        let mut tx = txs[1].clone();
+        chain
+            .complete_transaction(1, &mut tx, &forged_witness, &spent, Some(&txs[0]))
+            .unwrap();
+        assert!(!execute_spend(&tx, &[spent.clone()], 0).unwrap().success);
+
+        // while the honest witness is accepted
+        let mut tx = txs[1].clone();
+        chain
+            .complete_transaction(1, &mut tx, &stage_witnesses[1], &spent, Some(&txs[0]))
+            .unwrap();
+        assert!(execute_spend(&tx, &[spent], 0).unwrap().success);
+    }
+}
@@ -0,0 +1,395 @@
+mod bitcoin_script;
+pub use bitcoin_script::*;
+
+use crate::chunker::{
+    commit_state, run_stages, script_to_elements, Stage, StageError, StageFailure, StageWitness,
+};
+use crate::taproot::{TaprootLeaf, TaprootOutput};
+use crate::treepp::*;
+use bitcoin::absolute::LockTime;
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TaprootBuilderError};
+use bitcoin::transaction::Version;
+use bitcoin::{Amount, OutPoint, Sequence, Transaction, TxIn, TxOut, Witness};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+
+/// The x coordinate of the generator of secp256k1, which is the public key and the nonce of the
+/// covenant signature.
+pub const GENERATOR_X: [u8; 32] = [
+    0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+    0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+];
+
+/// The maximum value, in satoshis, of the outputs of the covenant chain and of its fee, which is
+/// the largest number in Bitcoin script.
+pub const MAX_COVENANT_VALUE: u64 = i32::MAX as u64;
+
+/// Maximum number of lock times to try when grinding the challenge of a transaction.
+const MAX_GRINDING_ATTEMPTS: u32 = 1 << 16;
+
+/// Return `SHA256(tag) || SHA256(tag)`, which prefixes a tagged hash.
+fn tag_prefix(tag: &[u8]) -> [u8; 64] {
+    let mut hasher = Sha256::new();
+    Digest::update(&mut hasher, tag);
+    let tag_hash = hasher.finalize();
+
+    let mut prefix = [0u8; 64];
+    prefix[..32].copy_from_slice(tag_hash.as_slice());
+    prefix[32..].copy_from_slice(tag_hash.as_slice());
+    prefix
+}
+
+/// The prefix of the tagged hash of a taproot sighash.
+pub(crate) fn tap_sighash_tag_prefix() -> [u8; 64] {
+    tag_prefix(b"TapSighash")
+}
+
+/// The prefix of the BIP-340 challenge of a signature with nonce G under the public key G, before
+/// the message.
+pub(crate) fn challenge_tag_prefix() -> Vec<u8> {
+    let mut prefix = tag_prefix(b"BIP0340/challenge").to_vec();
+    prefix.extend_from_slice(&GENERATOR_X);
+    prefix.extend_from_slice(&GENERATOR_X);
+    prefix
+}
+
+/// The value (zero) and the script pubkey length of the output carrying a commitment, and the
+/// `OP_RETURN` pushing it, all of which come before the commitment in the serialized output.
+pub(crate) fn commitment_output_prefix() -> Vec<u8> {
+    let mut prefix = vec![0u8; 8];
+    prefix.extend_from_slice(&[0x22, 0x6a, 0x20]);
+    prefix
+}
+
+/// A script pubkey prefixed with its length, as in a serialized output.
+pub(crate) fn output_script_with_length(script_pubkey: &Script) -> Vec<u8> {
+    let mut bytes = vec![script_pubkey.len() as u8];
+    bytes.extend_from_slice(script_pubkey.as_bytes());
+    bytes
+}
+
+/// Compute the BIP-340 challenge of the covenant signature for a sighash.
+fn challenge(sighash: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    Digest::update(&mut hasher, challenge_tag_prefix());
+    Digest::update(&mut hasher, sighash);
+
+    let mut res = [0u8; 32];
+    res.copy_from_slice(hasher.finalize().as_slice());
+    res
+}
+
+/// An error when building the covenant chain.
+#[derive(Debug)]
+pub enum CovenantError {
+    /// The chain has no stage.
+    NoStages,
+    /// The taproot tree of a stage could not be built.
+    Taproot(TaprootBuilderError),
+    /// A stage cannot be run on the hints, see `StageError`.
+    Stage(StageError),
+    /// The value of the outputs does not cover the fees.
+    InsufficientValue,
+    /// A value or the fee is larger than `MAX_COVENANT_VALUE`.
+    ValueOutOfRange,
+    /// No lock time gives a challenge that ends with a zero byte.
+    Grinding,
+}
+
+impl Display for CovenantError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CovenantError::NoStages => write!(f, "the chain has no stage"),
+            CovenantError::Taproot(err) => write!(f, "taproot tree error: {}", err),
+            CovenantError::Stage(err) => write!(f, "{}", err),
+            CovenantError::InsufficientValue => {
+                write!(f, "the value of the outputs does not cover the fees")
+            }
+            CovenantError::ValueOutOfRange => {
+                write!(
+                    f,
+                    "the value is larger than {} satoshis",
+                    MAX_COVENANT_VALUE
+                )
+            }
+            CovenantError::Grinding => write!(f, "the challenge could not be ground"),
+        }
+    }
+}
+
+impl std::error::Error for CovenantError {}
+
+impl From<TaprootBuilderError> for CovenantError {
+    fn from(err: TaprootBuilderError) -> Self {
+        CovenantError::Taproot(err)
+    }
+}
+
+impl From<StageError> for CovenantError {
+    fn from(err: StageError) -> Self {
+        CovenantError::Stage(err)
+    }
+}
+
+/// A chain of transactions that runs the stages of a script, one transaction per stage.
+///
+/// The transaction of each stage spends the first output of the previous one, and must create a
+/// first output of the spent value minus the fee. Except for the last stage, this output is locked
+/// to the next stage, and a second `OP_RETURN` output carries the commitment of the output state,
+/// which the next stage reopens. The last stage pays to the payout script pubkey, with this output
+/// only.
+///
+/// Only the chain starting from an output locked to the first stage is meaningful: anyone can lock
+/// their own coins to a later stage with any state.
+#[derive(Clone, Debug)]
+pub struct CovenantChain {
+    /// The stages.
+    pub stages: Vec<Stage>,
+    /// The taproot output of each stage, with a single leaf.
+    pub outputs: Vec<TaprootOutput>,
+    /// The fee paid by the transaction of each stage.
+    pub fee: Amount,
+    /// The script pubkey that the last stage pays to.
+    pub payout_script_pubkey: Script,
+}
+
+impl CovenantChain {
+    /// Build the leaves of the stages, from the last one to the first one, each of which enforces
+    /// the first output, paying to the script pubkey of the next one or to
+    /// `payout_script_pubkey`, under the internal key.
+    ///
+    /// An error is returned if there is no stage, if a stage hands off an empty state, or if the
+    /// fee is out of range.
+    pub fn new(
+        stages: Vec<Stage>,
+        internal_key: XOnlyPublicKey,
+        fee: Amount,
+        payout_script_pubkey: Script,
+    ) -> Result<Self, CovenantError> {
+        if fee.to_sat() > MAX_COVENANT_VALUE {
+            return Err(CovenantError::ValueOutOfRange);
+        }
+        if stages.is_empty() {
+            return Err(CovenantError::NoStages);
+        }
+        if let Some((stage_index, stage)) = stages
+            .iter()
+            .enumerate()
+            .find(|(_, stage)| stage.output_state_size == Some(0))
+        {
+            return Err(CovenantError::Stage(StageError {
+                stage_index,
+                stage_name: stage.name.clone(),
+                failure: StageFailure::EmptyOutputState,
+            }));
+        }
+
+        let mut outputs: Vec<TaprootOutput> = vec![];
+        for stage in stages.iter().rev() {
+            let next_script_pubkey = outputs.last().map(|output| &output.script_pubkey);
+
+            let script = script! {
+                {
+                    CovenantGadget::prologue(
+                        stage.input_state_size > 0,
+                        fee,
+                        next_script_pubkey.unwrap_or(&payout_script_pubkey),
+                    )
+                }
+                { stage.script() }
+                { CovenantGadget::epilogue(next_script_pubkey.is_some()) }
+            };
+
+            outputs.push(TaprootOutput::from_scripts(
+                vec![(stage.name.clone(), script)],
+                internal_key,
+            )?);
+        }
+        outputs.reverse();
+
+        Ok(Self {
+            stages,
+            outputs,
+            fee,
+            payout_script_pubkey,
+        })
+    }
+
+    /// Return the script pubkey to lock the coins to, which is the one of the first stage.
+    pub fn script_pubkey(&self) -> Script {
+        self.outputs[0].script_pubkey.clone()
+    }
+
+    /// Build the transactions of the chain, which spend the output `funding` of value
+    /// `funding_value`, pay `fee` each, and finally pay the rest to `payout_script_pubkey`.
+    ///
+    /// The hints are the stack elements pulled by the stages, from the bottom to the top, such as
+    /// the ones of `VerifierHints::to_elements`, and they are split among the stages by running
+    /// them.
+    pub fn build_transactions(
+        &self,
+        funding: OutPoint,
+        funding_value: Amount,
+        hints: Vec<Vec<u8>>,
+    ) -> Result<Vec<Transaction>, CovenantError> {
+        if funding_value.to_sat() > MAX_COVENANT_VALUE {
+            return Err(CovenantError::ValueOutOfRange);
+        }
+
+        let stage_witnesses = run_stages(&self.stages, hints)?;
+
+        let mut txs: Vec<Transaction> = vec![];
+        let mut spent = TxOut {
+            value: funding_value,
+            script_pubkey: self.script_pubkey(),
+        };
+        let mut previous_output = funding;
+
+        for (i, stage_witness) in stage_witnesses.iter().enumerate() {
+            let value = spent
+                .value
+                .checked_sub(self.fee)
+                .ok_or(CovenantError::InsufficientValue)?;
+
+            let output = match &stage_witness.output_state {
+                Some(output_state) => vec![
+                    TxOut {
+                        value,
+                        script_pubkey: self.outputs[i + 1].script_pubkey.clone(),
+                    },
+                    TxOut {
+                        value: Amount::ZERO,
+                        script_pubkey: commitment_output(&commit_state(output_state)),
+                    },
+                ],
+                None => vec![TxOut {
+                    value,
+                    script_pubkey: self.payout_script_pubkey.clone(),
+                }],
+            };
+
+            let mut tx = Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output,
+                    sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+                    ..Default::default()
+                }],
+                output,
+            };
+            self.complete_transaction(i, &mut tx, stage_witness, &spent, txs.last())?;
+
+            previous_output = OutPoint::new(tx.compute_txid(), 0);
+            spent = tx.output[0].clone();
+            txs.push(tx);
+        }
+
+        Ok(txs)
+    }
+
+    /// Grind the lock time of the transaction of a stage, which spends `spent`, the first output
+    /// of `previous_tx` (or the funding output for the first stage), and set its witness.
+    pub(crate) fn complete_transaction(
+        &self,
+        stage_index: usize,
+        tx: &mut Transaction,
+        stage_witness: &StageWitness,
+        spent: &TxOut,
+        previous_tx: Option<&Transaction>,
+    ) -> Result<(), CovenantError> {
+        let leaf = &self.outputs[stage_index].leaves[0];
+        let e = grind(tx, spent, leaf)?;
+
+        let mut witness = stage_witness.to_elements();
+        witness.extend(covenant_witness(tx, spent, previous_tx, leaf, &e));
+        witness.push(leaf.script.to_bytes());
+        witness.push(leaf.control_block.serialize());
+        tx.input[0].witness = Witness::from_slice(&witness);
+
+        Ok(())
+    }
+}
+
+/// The `OP_RETURN` script pubkey carrying a commitment.
+fn commitment_output(commitment: &[u8; 32]) -> Script {
+    Script::new_op_return(commitment)
+}
+
+/// Find the lock time for which the challenge of the covenant signature ends with a zero byte,
+/// and return the challenge.
+fn grind(
+    tx: &mut Transaction,
+    spent: &TxOut,
+    leaf: &TaprootLeaf,
+) -> Result<[u8; 32], CovenantError> {
+    let leaf_hash = TapLeafHash::from_script(&leaf.script, LeafVersion::TapScript);
+
+    for lock_time in 0..MAX_GRINDING_ATTEMPTS {
+        tx.lock_time = LockTime::from_consensus(lock_time);
+
+        let sighash = SighashCache::new(&*tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&[spent]),
+                leaf_hash,
+                TapSighashType::Default,
+            )
+            .expect("the input and the prevouts are consistent");
+
+        let e = challenge(sighash.as_byte_array());
+        if e[31] == 0 {
+            return Ok(e);
+        }
+    }
+
+    Err(CovenantError::Grinding)
+}
+
+/// The stack elements pulled by `CovenantGadget::prologue`, from the bottom to the top.
+fn covenant_witness(
+    tx: &Transaction,
+    spent: &TxOut,
+    previous_tx: Option<&Transaction>,
+    leaf: &TaprootLeaf,
+    e: &[u8; 32],
+) -> Vec<Vec<u8>> {
+    let mut elements = script_to_elements(&script! { { spent.value.to_sat() as i64 } });
+
+    elements.push(
+        TapLeafHash::from_script(&leaf.script, LeafVersion::TapScript)
+            .to_byte_array()
+            .to_vec(),
+    );
+
+    match previous_tx {
+        None => {
+            let mut hasher = Sha256::new();
+            Digest::update(&mut hasher, serialize(&tx.input[0].previous_output));
+            elements.push(hasher.finalize().to_vec());
+        }
+        Some(previous_tx) => {
+            elements.push(
+                previous_tx
+                    .lock_time
+                    .to_consensus_u32()
+                    .to_le_bytes()
+                    .to_vec(),
+            );
+            elements.push(serialize(&previous_tx.input));
+            elements.push(previous_tx.version.0.to_le_bytes().to_vec());
+        }
+    }
+
+    elements.push(output_script_with_length(&spent.script_pubkey));
+    elements.push(tx.input[0].sequence.0.to_le_bytes().to_vec());
+    elements.push(tx.lock_time.to_consensus_u32().to_le_bytes().to_vec());
+    elements.push(tx.version.0.to_le_bytes().to_vec());
+    elements.push(e[..31].to_vec());
+
+    elements
+}
@@ -0,0 +1,363 @@
+use crate::treepp::*;
+use bitcoin::absolute::LockTime;
+use bitcoin::hex::DisplayHex;
+use bitcoin::opcodes::all::{OP_DROP, OP_ELSE, OP_ENDIF, OP_IF, OP_NOTIF};
+use bitcoin::script::Instruction;
+use bitcoin::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::transaction::Version;
+use bitcoin::Transaction;
+use bitcoin_scriptexec::{Exec, ExecCtx, ExecError, Options, TxTemplate};
+use std::cell::Cell;
+use std::fmt::{Display, Formatter};
+
+/// The prefix of the labels in a script, which tells them apart from the other pushes.
+pub const LABEL_PREFIX: &[u8] = b"label:";
+
+thread_local! {
+    static LABELS_ENABLED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Restores the previous state of the labels when dropped, even if the closure panics.
+struct LabelsGuard {
+    enabled: bool,
+}
+
+impl Drop for LabelsGuard {
+    fn drop(&mut self) {
+        LABELS_ENABLED.with(|cell| cell.set(self.enabled));
+    }
+}
+
+/// Run the closure with the labels enabled on this thread, so that the scripts built by it carry
+/// the labels of the hints they pull.
+pub fn with_labels<T>(f: impl FnOnce() -> T) -> T {
+    let _guard = LabelsGuard {
+        enabled: LABELS_ENABLED.with(|cell| cell.replace(true)),
+    };
+    f()
+}
+
+/// Return whether the labels are enabled on this thread.
+pub fn labels_enabled() -> bool {
+    LABELS_ENABLED.with(|cell| cell.get())
+}
+
+/// Gadget for labeling the hints pulled next, which is empty unless the labels are enabled.
+///
+/// The label is pushed and dropped right away, so the stack is left unchanged.
+pub fn label(label: &str) -> Script {
+    if !labels_enabled() {
+        return script! {};
+    }
+
+    let mut data = LABEL_PREFIX.to_vec();
+    data.extend_from_slice(label.as_bytes());
+    assert!(data.len() <= 520, "the label must fit in a stack element");
+
+    script! {
+        { data }
+        OP_DROP
+    }
+}
+
+/// The result of executing a script with labels.
+#[derive(Clone, Debug)]
+pub struct LabeledExecResult {
+    /// Whether the execution succeeds.
+    pub success: bool,
+    /// The error, if the execution fails.
+    pub error: Option<ExecError>,
+    /// The last label passed by the execution, which names the hints pulled last.
+    pub last_label: Option<String>,
+    /// The elements left on the stack, from the bottom to the top.
+    pub final_stack: Vec<Vec<u8>>,
+}
+
+impl Display for LabeledExecResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.success {
+            writeln!(f, "success")?;
+        } else {
+            writeln!(
+                f,
+                "failed after the hint {} with {:?}",
+                self.last_label.as_deref().unwrap_or("(no label)"),
+                self.error
+            )?;
+        }
+        writeln!(f, "stack (from the top):")?;
+        for (i, elem) in self.final_stack.iter().rev().enumerate() {
+            writeln!(f, "  {}: {}", i, elem.to_lower_hex_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// Error when a script cannot be executed with labels.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LabeledExecError {
+    /// The executor rejects the script before running it, such as when a push is truncated.
+    InvalidScript,
+    /// The execution stops without a result.
+    NoResult,
+}
+
+impl Display for LabeledExecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LabeledExecError::InvalidScript => write!(f, "the script is rejected by the executor"),
+            LabeledExecError::NoResult => write!(f, "the execution stops without a result"),
+        }
+    }
+}
+
+impl std::error::Error for LabeledExecError {}
+
+/// Execute a script, built with the labels enabled, and keep track of the last label passed, so
+/// that a failure can be traced back to the hints pulled last.
+///
+/// A failing execution is reported in the result, while an error is returned if the script cannot
+/// be executed at all.
+pub fn execute_script_with_labels(script: Script) -> Result<LabeledExecResult, LabeledExecError> {
+    let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+    let mut exec = Exec::new(
+        ExecCtx::Tapscript,
+        Options::default(),
+        TxTemplate {
+            tx: Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![],
+                output: vec![],
+            },
+            prevouts: vec![],
+            input_idx: 0,
+            taproot_annex_scriptleaf: Some((leaf_hash, None)),
+        },
+        script,
+        vec![],
+    )
+    .map_err(|_| LabeledExecError::InvalidScript)?;
+
+    // whether each open conditional executes its current branch, so that the labels of the
+    // branches that are skipped are not recorded
+    let mut conditions: Vec<bool> = vec![];
+    let mut last_label = None;
+    loop {
+        let executing = conditions.iter().all(|&condition| condition);
+        let mut instructions = exec.remaining_script().instructions();
+        match (instructions.next(), instructions.next()) {
+            (Some(Ok(Instruction::PushBytes(data))), Some(Ok(Instruction::Op(OP_DROP))))
+                if executing =>
+            {
+                if let Some(label) = data.as_bytes().strip_prefix(LABEL_PREFIX) {
+                    last_label = Some(String::from_utf8_lossy(label).into_owned());
+                }
+            }
+            (Some(Ok(Instruction::Op(opcode))), _) => match opcode {
+                // in tapscript, the argument of a conditional is either empty or 1, and the
+                // execution fails otherwise
+                OP_IF | OP_NOTIF => {
+                    let stack = exec.stack();
+                    let is_one = stack
+                        .len()
+                        .checked_sub(1)
+                        .is_some_and(|i| stack.get(i) == [1]);
+                    conditions.push(executing && is_one == (opcode == OP_IF));
+                }
+                OP_ELSE => {
+                    if let Some(condition) = conditions.last_mut() {
+                        *condition = !*condition;
+                    }
+                }
+                OP_ENDIF => {
+                    conditions.pop();
+                }
+                _ => {}
+            },
+            _ => {}
+        }
+
+        if exec.exec_next().is_err() {
+            break;
+        }
+    }
+    let res = exec.result().ok_or(LabeledExecError::NoResult)?;
+    Ok(LabeledExecResult {
+        success: res.success,
+        error: res.error.clone(),
+        last_label,
+        final_stack: (0..res.final_stack.len())
+            .map(|i| res.final_stack.get(i))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::push_state;
+    use crate::debug::{
+        execute_script_with_labels, label, labels_enabled, with_labels, LabeledExecError,
+    };
+    use crate::fibonacci::FibonacciVerifierGadget;
+    use crate::tests_utils::fibonacci::{hint_and_channel, hint_stream};
+    use crate::treepp::*;
+    use crate::verifier::VerifierConfig;
+    use crate::OP_HINT;
+    use num_traits::One;
+    use stwo_prover::core::fields::qm31::QM31;
+
+    fn gadget() -> Script {
+        script! {
+            { label("a") }
+            OP_HINT 1 OP_EQUALVERIFY
+            { label("b") }
+            OP_HINT OP_HINT OP_ADD 5 OP_EQUALVERIFY
+            OP_TRUE
+        }
+    }
+
+    #[test]
+    fn test_labels() {
+        assert!(!labels_enabled());
+        assert_eq!(label("a"), script! {});
+
+        let script = with_labels(gadget);
+        assert!(!labels_enabled());
+        assert!(script.len() > gadget().len());
+
+        // the labels do not change the execution
+        for script in [gadget(), script.clone()] {
+            let exec_result = execute_script(script! {
+                1 2 3
+                { script }
+            });
+            assert!(exec_result.success);
+        }
+
+        let exec_result = execute_script_with_labels(script! {
+            1 2 3
+            { script.clone() }
+        })
+        .unwrap();
+        assert!(exec_result.success);
+        assert_eq!(exec_result.last_label.as_deref(), Some("b"));
+
+        // a wrong hint is reported with the label of the hints pulled last
+        let exec_result = execute_script_with_labels(script! {
+            1 2 4
+            { script.clone() }
+        })
+        .unwrap();
+        assert!(!exec_result.success);
+        assert_eq!(exec_result.last_label.as_deref(), Some("b"));
+        assert!(exec_result.to_string().contains("after the hint b"));
+
+        let exec_result = execute_script_with_labels(script! {
+            2 2 3
+            { script }
+        })
+        .unwrap();
+        assert!(!exec_result.success);
+        assert_eq!(exec_result.last_label.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_labels_in_skipped_branch() {
+        let script = with_labels(|| {
+            script! {
+                { label("a") }
+                OP_HINT
+                OP_IF
+                    { label("b") }
+                    OP_HINT OP_DROP
+                OP_ENDIF
+                OP_HINT 1 OP_EQUALVERIFY
+                OP_TRUE
+            }
+        });
+
+        // the label of the branch that is taken is recorded
+        let exec_result = execute_script_with_labels(script! {
+            1 5 1
+            { script.clone() }
+        })
+        .unwrap();
+        assert!(exec_result.success);
+        assert_eq!(exec_result.last_label.as_deref(), Some("b"));
+
+        // the label of the branch that is not taken is not
+        let exec_result = execute_script_with_labels(script! {
+            0 2
+            { script }
+        })
+        .unwrap();
+        assert!(!exec_result.success);
+        assert_eq!(exec_result.last_label.as_deref(), Some("a"));
+    }
+
+    #[test]
+    fn test_labels_invalid_script() {
+        // a push of two bytes with only one of them is rejected before the execution
+        assert_eq!(
+            execute_script_with_labels(Script::from_bytes(vec![0x02, 0x01])).unwrap_err(),
+            LabeledExecError::InvalidScript
+        );
+    }
+
+    #[test]
+    fn test_labels_after_panic() {
+        let res = std::panic::catch_unwind(|| {
+            with_labels(|| {
+                assert!(labels_enabled());
+                panic!("the script cannot be built");
+            })
+        });
+        assert!(res.is_err());
+        assert!(!labels_enabled());
+    }
+
+    #[test]
+    fn test_verifier_labels() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let stream = hint_stream(5, claim, &channel);
+        let verifier = with_labels(|| {
+            FibonacciVerifierGadget::run_verifier(5, claim, &channel, VerifierConfig::default())
+                .unwrap()
+        });
+
+        // a wrong PoW nonce is traced back to the PoW hint
+        let mut wrong_hint = hint.clone();
+        wrong_hint.pow_hint.nonce ^= 1;
+
+        let exec_result = execute_script_with_labels(script! {
+            { push_state(&hint.to_elements(&stream).unwrap()) }
+            { verifier.clone() }
+        })
+        .unwrap();
+        assert!(exec_result.success);
+
+        let exec_result = execute_script_with_labels(script! {
+            { push_state(&wrong_hint.to_elements(&stream).unwrap()) }
+            { verifier.clone() }
+        })
+        .unwrap();
+        assert!(!exec_result.success);
+        assert_eq!(exec_result.last_label.as_deref(), Some("pow_hint"));
+
+        // a wrong FRI hint is traced back to its query
+        let mut wrong_hint = hint.clone();
+        wrong_hint.fri_query_hints[1].inner_layer_proofs[0].twin += QM31::one();
+
+        let exec_result = execute_script_with_labels(script! {
+            { push_state(&wrong_hint.to_elements(&stream).unwrap()) }
+            { verifier }
+        })
+        .unwrap();
+        assert!(!exec_result.success);
+        assert_eq!(
+            exec_result.last_label.as_deref(),
+            Some("fri_query_hints[1]")
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use crate::chunker::{commit_state, StateCommitmentGadget};
+use crate::treepp::*;
+
+/// Gadget for the disprove leaves, which succeed only if a step does not give what is asserted.
+pub struct DisproveGadget;
+
+impl DisproveGadget {
+    /// Check that the inputs on the stack are the asserted ones.
+    ///
+    /// Input: the inputs
+    /// Output: the inputs
+    pub fn open_inputs(inputs: &[Vec<u8>]) -> Script {
+        if inputs.is_empty() {
+            return script! {};
+        }
+
+        script! {
+            { commit_state(inputs).to_vec() }
+            { StateCommitmentGadget::reopen(inputs.len()) }
+        }
+    }
+
+    /// Check that all the hints have been consumed and that the outputs on the stack are not the
+    /// asserted ones.
+    ///
+    /// Input: the outputs
+    /// Output: true if the outputs are not the asserted ones, false otherwise
+    pub fn outputs_mismatch(outputs: &[Vec<u8>]) -> Script {
+        assert!(!outputs.is_empty());
+
+        script! {
+            OP_DEPTH { outputs.len() } OP_EQUALVERIFY
+            { StateCommitmentGadget::commit(outputs.len()) }
+            { commit_state(outputs).to_vec() }
+            OP_EQUAL OP_NOT
+        }
+    }
+
+    /// Check that all the hints have been consumed and that the check on the stack fails.
+    ///
+    /// Input: the result of the check
+    /// Output: true if the check fails, false otherwise
+    pub fn check_fails() -> Script {
+        script! {
+            OP_DEPTH 1 OP_EQUALVERIFY
+            OP_NOT
+        }
+    }
+}
@@ -0,0 +1,653 @@
+mod bitcoin_script;
+pub use bitcoin_script::*;
+
+use crate::chunker::push_state;
+use crate::hint_stream::HintStream;
+use crate::taproot::TaprootOutput;
+use crate::treepp::*;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::taproot::TaprootBuilderError;
+use bitcoin_scriptexec::execute_script;
+use std::fmt::{Display, Formatter};
+
+/// What a step asserts about its inputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepKind {
+    /// The step computes the given number of outputs from its inputs.
+    Computation {
+        /// Number of the outputs.
+        n_outputs: usize,
+    },
+    /// The step checks its inputs and leaves the result of the check.
+    Check,
+}
+
+/// A step of a script, usually a single gadget, whose assertion can be disproved by running it.
+///
+/// The script of the step must succeed on any inputs when given the right hints, so that any wrong
+/// assertion can be disproved. A step whose script fails on some inputs, for example with a
+/// `OP_VERIFY` on its inputs, must instead be split into a computation and a check.
+///
+/// The steps of a script form a chain: the inputs of each step are the outputs of the last
+/// computation before it, so that the assertions cannot be unrelated to each other.
+#[derive(Clone, Debug)]
+pub struct DisprovableStep {
+    /// Name of the step.
+    pub name: String,
+    /// Number of the inputs.
+    pub n_inputs: usize,
+    /// Kind of the step.
+    pub kind: StepKind,
+    /// Script of the step, which pulls its hints from the bottom of the stack.
+    pub script: Script,
+    /// The hints pulled by the script, in order.
+    pub stream: HintStream,
+}
+
+/// The inputs and the outputs of a step asserted by the prover, as stack elements from the bottom
+/// to the top. A check has no outputs, as it asserts that the check passes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Assertion {
+    /// The asserted inputs.
+    pub inputs: Vec<Vec<u8>>,
+    /// The asserted outputs.
+    pub outputs: Vec<Vec<u8>>,
+}
+
+impl DisprovableStep {
+    /// Create a step, whose script pulls the hints declared by the stream.
+    pub fn new(
+        name: &str,
+        n_inputs: usize,
+        kind: StepKind,
+        script: Script,
+        stream: HintStream,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            n_inputs,
+            kind,
+            script,
+            stream,
+        }
+    }
+
+    /// Return the disprove leaf for the assertion, which succeeds only if the step, run on the
+    /// asserted inputs, does not give the asserted outputs or, for a check, fails.
+    ///
+    /// The leaf commits to the asserted inputs, which are the outputs asserted by the previous
+    /// step in the chain, and to the asserted outputs, which are the inputs of the next step, and
+    /// checks the stack against both commitments.
+    ///
+    /// Input: the hints of the step, the asserted inputs
+    ///
+    /// An error is returned if the assertion does not have the numbers of the inputs and the
+    /// outputs of the step.
+    pub fn disprove_script(&self, assertion: &Assertion) -> Result<Script, DisproveError> {
+        let expected = match self.kind {
+            StepKind::Computation { n_outputs } => (self.n_inputs, n_outputs),
+            StepKind::Check => (self.n_inputs, 0),
+        };
+        let got = (assertion.inputs.len(), assertion.outputs.len());
+        if got != expected {
+            return Err(DisproveError::AssertionArity {
+                step: self.name.clone(),
+                expected,
+                got,
+            });
+        }
+
+        let outcome = match self.kind {
+            StepKind::Computation { .. } => DisproveGadget::outputs_mismatch(&assertion.outputs),
+            StepKind::Check => DisproveGadget::check_fails(),
+        };
+
+        Ok(script! {
+            { DisproveGadget::open_inputs(&assertion.inputs) }
+            { self.script.clone() }
+            { outcome }
+        })
+    }
+
+    /// Return the witness of the disprove leaf, as stack elements from the bottom to the top: the
+    /// hints, which the disprover computes from the asserted inputs, and the asserted inputs.
+    pub fn disprove_witness(&self, hints: &[Vec<u8>], assertion: &Assertion) -> Vec<Vec<u8>> {
+        let mut witness = hints.to_vec();
+        witness.extend(assertion.inputs.iter().cloned());
+        witness
+    }
+
+    /// Run the step on the inputs with the hints and return the honest assertion, or `None` if the
+    /// step fails or, for a check, if the check does not pass.
+    pub fn assert(&self, hints: &[Vec<u8>], inputs: Vec<Vec<u8>>) -> Option<Assertion> {
+        let exec_result = execute_script(script! {
+            { push_state(hints) }
+            { push_state(&inputs) }
+            { self.script.clone() }
+        });
+        if exec_result.error.is_some() {
+            return None;
+        }
+
+        let depth = exec_result.final_stack.len();
+        match self.kind {
+            StepKind::Computation { n_outputs } => {
+                if depth != n_outputs {
+                    return None;
+                }
+                Some(Assertion {
+                    inputs,
+                    outputs: (0..depth).map(|i| exec_result.final_stack.get(i)).collect(),
+                })
+            }
+            StepKind::Check => {
+                if depth != 1 || exec_result.final_stack.get(0) != vec![1] {
+                    return None;
+                }
+                Some(Assertion {
+                    inputs,
+                    outputs: vec![],
+                })
+            }
+        }
+    }
+}
+
+/// Run the chain of the steps from the initial state, with the hints of each step, and return
+/// the honest assertions.
+///
+/// Each computation gives the state for the steps after it, while a check leaves it as it is.
+///
+/// An error is returned if the number of the hints is not the number of the steps, or if a step
+/// fails or a check does not pass.
+pub fn assert_steps(
+    steps: &[DisprovableStep],
+    hints: &[Vec<Vec<u8>>],
+    initial_state: Vec<Vec<u8>>,
+) -> Result<Vec<Assertion>, DisproveError> {
+    if steps.len() != hints.len() {
+        return Err(DisproveError::LengthMismatch {
+            n_steps: steps.len(),
+            n_given: hints.len(),
+        });
+    }
+
+    let mut state = initial_state;
+    let mut assertions = vec![];
+    for (i, (step, hints)) in steps.iter().zip(hints.iter()).enumerate() {
+        let assertion = step
+            .assert(hints, state.clone())
+            .ok_or(DisproveError::StepFails { step: i })?;
+        if let StepKind::Computation { .. } = step.kind {
+            state = assertion.outputs.clone();
+        }
+        assertions.push(assertion);
+    }
+    Ok(assertions)
+}
+
+/// An error when building the disprove output.
+#[derive(Debug)]
+pub enum DisproveError {
+    /// The inputs of the assertion of the step are not the outputs asserted by the last
+    /// computation before it.
+    BrokenChain {
+        /// Index of the step.
+        step: usize,
+    },
+    /// The number of the assertions, or of the hints of the steps, is not the number of the steps.
+    LengthMismatch {
+        /// Number of the steps.
+        n_steps: usize,
+        /// Number of the assertions or of the hints.
+        n_given: usize,
+    },
+    /// The assertion of the step does not have the numbers of its inputs and outputs.
+    AssertionArity {
+        /// Name of the step.
+        step: String,
+        /// Numbers of the inputs and the outputs of the step.
+        expected: (usize, usize),
+        /// Numbers of the inputs and the outputs of the assertion.
+        got: (usize, usize),
+    },
+    /// The step fails on its inputs with its hints, or its check does not pass.
+    StepFails {
+        /// Index of the step.
+        step: usize,
+    },
+    /// The taproot tree could not be built, such as when there is no step.
+    Taproot(TaprootBuilderError),
+}
+
+impl Display for DisproveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisproveError::BrokenChain { step } => write!(
+                f,
+                "the inputs of step #{} are not the outputs of the previous computation",
+                step
+            ),
+            DisproveError::LengthMismatch { n_steps, n_given } => {
+                write!(
+                    f,
+                    "{} assertions or hints are given for {} steps",
+                    n_given, n_steps
+                )
+            }
+            DisproveError::AssertionArity {
+                step,
+                expected,
+                got,
+            } => write!(
+                f,
+                "the assertion of step {} has {} inputs and {} outputs instead of {} and {}",
+                step, got.0, got.1, expected.0, expected.1
+            ),
+            DisproveError::StepFails { step } => write!(f, "step #{} fails", step),
+            DisproveError::Taproot(err) => write!(f, "taproot tree error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DisproveError {}
+
+impl From<TaprootBuilderError> for DisproveError {
+    fn from(err: TaprootBuilderError) -> Self {
+        DisproveError::Taproot(err)
+    }
+}
+
+/// Build the taproot output with one disprove leaf for each step and its assertion, which the
+/// prover locks its assertion to, so that anyone can take the coins if an assertion is wrong.
+///
+/// The assertions must form a chain, in which the inputs of each step are the outputs of the last
+/// computation before it, so that the leaf of each step commits its input state to the output
+/// state committed by the leaf of that computation.
+pub fn disprove_output(
+    steps: &[DisprovableStep],
+    assertions: &[Assertion],
+    internal_key: XOnlyPublicKey,
+) -> Result<TaprootOutput, DisproveError> {
+    if steps.len() != assertions.len() {
+        return Err(DisproveError::LengthMismatch {
+            n_steps: steps.len(),
+            n_given: assertions.len(),
+        });
+    }
+
+    let mut state: Option<&Vec<Vec<u8>>> = None;
+    let mut leaves = vec![];
+    for (i, (step, assertion)) in steps.iter().zip(assertions.iter()).enumerate() {
+        if state.map_or(false, |state| *state != assertion.inputs) {
+            return Err(DisproveError::BrokenChain { step: i });
+        }
+        if let StepKind::Computation { .. } = step.kind {
+            state = Some(&assertion.outputs);
+        }
+        leaves.push((step.name.clone(), step.disprove_script(assertion)?));
+    }
+
+    Ok(TaprootOutput::from_scripts(leaves, internal_key)?)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::{push_state, StateItem, StateValue};
+    use crate::disprove::{
+        assert_steps, disprove_output, Assertion, DisprovableStep, DisproveError, StepKind,
+    };
+    use crate::fibonacci::FibonacciVerifierGadget;
+    use crate::hint_stream::HintStream;
+    use crate::pow::PoWCheckHint;
+    use crate::taproot::unspendable_internal_key;
+    use crate::tests_utils::fibonacci::hint_and_channel;
+    use crate::tests_utils::report::report_bitcoin_script_size;
+    use crate::treepp::*;
+    use crate::verifier::{disprove_initial_state, VerifierConfig, VerifierHints};
+    use itertools::Itertools;
+    use num_traits::One;
+    use stwo_prover::core::fields::qm31::QM31;
+    use stwo_prover::examples::fibonacci::Fibonacci;
+
+    #[test]
+    fn test_disprove() {
+        // a step adding a hint to its input, and a check that its input is a hint
+        let mut stream = HintStream::new();
+        let script = script! { { stream.pull("a", StateItem::M31) } OP_ADD };
+        let add = DisprovableStep::new(
+            "add",
+            1,
+            StepKind::Computation { n_outputs: 1 },
+            script,
+            stream,
+        );
+        let mut stream = HintStream::new();
+        let script = script! { { stream.pull("b", StateItem::M31) } OP_EQUAL };
+        let check = DisprovableStep::new("check", 1, StepKind::Check, script, stream);
+
+        let hints = vec![vec![5]];
+
+        let honest = add.assert(&hints, vec![vec![3]]).unwrap();
+        assert_eq!(honest.outputs, vec![vec![8]]);
+        let wrong = Assertion {
+            inputs: vec![vec![3]],
+            outputs: vec![vec![9]],
+        };
+
+        let honest_check = check.assert(&hints, vec![vec![5]]).unwrap();
+        assert!(check.assert(&hints, vec![vec![6]]).is_none());
+        let wrong_check = Assertion {
+            inputs: vec![vec![6]],
+            outputs: vec![],
+        };
+
+        for (step, assertion, disprovable) in [
+            (&add, &honest, false),
+            (&add, &wrong, true),
+            (&check, &honest_check, false),
+            (&check, &wrong_check, true),
+        ] {
+            let exec_result = execute_script(script! {
+                { push_state(&step.disprove_witness(&hints, assertion)) }
+                { step.disprove_script(assertion).unwrap() }
+            });
+            assert_eq!(exec_result.success, disprovable);
+        }
+
+        // the asserted inputs cannot be replaced in the witness
+        let exec_result = execute_script(script! {
+            { push_state(&add.disprove_witness(&hints, &Assertion {
+                inputs: vec![vec![4]],
+                outputs: vec![vec![9]],
+            })) }
+            { add.disprove_script(&honest).unwrap() }
+        });
+        assert!(!exec_result.success);
+
+        // the check takes the output of the addition
+        let steps = [add.clone(), check.clone()];
+        let chain = assert_steps(&steps, &[vec![vec![5]], vec![vec![8]]], vec![vec![3]]).unwrap();
+        assert_eq!(
+            chain,
+            vec![
+                honest.clone(),
+                Assertion {
+                    inputs: vec![vec![8]],
+                    outputs: vec![],
+                }
+            ]
+        );
+        assert!(matches!(
+            assert_steps(&steps, &[vec![vec![5]], vec![vec![5]]], vec![vec![3]]),
+            Err(DisproveError::StepFails { step: 1 })
+        ));
+
+        // each step has exactly one vector of hints
+        assert!(matches!(
+            assert_steps(&steps, &[vec![vec![5]]], vec![vec![3]]),
+            Err(DisproveError::LengthMismatch {
+                n_steps: 2,
+                n_given: 1
+            })
+        ));
+
+        let output = disprove_output(&steps, &chain, unspendable_internal_key()).unwrap();
+        assert_eq!(output.leaves.len(), 2);
+
+        // the assertions of the steps cannot be unrelated
+        assert!(matches!(
+            disprove_output(&steps, &[honest, honest_check], unspendable_internal_key()),
+            Err(DisproveError::BrokenChain { step: 1 })
+        ));
+
+        // each step has exactly one assertion
+        assert!(matches!(
+            disprove_output(&steps, &chain[..1], unspendable_internal_key()),
+            Err(DisproveError::LengthMismatch {
+                n_steps: 2,
+                n_given: 1
+            })
+        ));
+
+        // each assertion has the numbers of the inputs and the outputs of its step
+        let mut malformed = chain.clone();
+        malformed[0].outputs.push(vec![1]);
+        assert!(matches!(
+            add.disprove_script(&malformed[0]),
+            Err(DisproveError::AssertionArity {
+                expected: (1, 1),
+                got: (1, 2),
+                ..
+            })
+        ));
+        let mut malformed = chain.clone();
+        malformed[1].outputs.push(vec![1]);
+        assert!(matches!(
+            disprove_output(&steps, &malformed, unspendable_internal_key()),
+            Err(DisproveError::AssertionArity {
+                expected: (1, 0),
+                got: (1, 1),
+                ..
+            })
+        ));
+    }
+
+    /// The disprovable steps of the verifier of a Fibonacci proof, with the honest hints of each
+    /// step, see `VerifierHints::step_hints`, and the initial state.
+    struct VerifierSteps {
+        hint: VerifierHints,
+        config: VerifierConfig,
+        steps: Vec<DisprovableStep>,
+        hints: Vec<Vec<Vec<u8>>>,
+        initial_state: Vec<StateValue>,
+    }
+
+    fn verifier_steps() -> VerifierSteps {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let config = VerifierConfig::default();
+        let steps = FibonacciVerifierGadget::disprovable_steps(5, claim, &channel, config).unwrap();
+        let initial_state =
+            disprove_initial_state(&Fibonacci::new(5, claim).air, &channel, &hint, config).unwrap();
+
+        let hints = hint.step_hints(&steps, config).unwrap();
+        assert_eq!(hints.len(), steps.len());
+
+        // the hints are written against the streams of all the steps
+        assert!(hint.step_hints(&steps[..steps.len() - 1], config).is_err());
+
+        VerifierSteps {
+            hint,
+            config,
+            steps,
+            hints,
+            initial_state,
+        }
+    }
+
+    #[test]
+    fn test_verifier_disprove() {
+        let VerifierSteps {
+            hint,
+            config,
+            steps,
+            hints,
+            initial_state,
+        } = verifier_steps();
+        let initial_state = initial_state
+            .iter()
+            .flat_map(StateValue::to_elements)
+            .collect_vec();
+
+        let assertions = assert_steps(&steps, &hints, initial_state).unwrap();
+        for ((step, hints), honest) in steps.iter().zip(hints.iter()).zip(assertions.iter()) {
+            report_bitcoin_script_size("FibonacciDisprove", &step.name, step.script.len());
+
+            // the honest assertion cannot be disproved
+            let exec_result = execute_script(script! {
+                { push_state(&step.disprove_witness(hints, honest)) }
+                { step.disprove_script(honest).unwrap() }
+            });
+            assert!(!exec_result.success);
+
+            // the checked values of the decommitments are in the initial state, see
+            // `test_verifier_disprove_decommitments`
+            if step.name.contains("decommitment") {
+                continue;
+            }
+
+            // a wrong output, or a wrong input for a check, can be disproved with honest hints,
+            // where the last input of a check is its checked value or the channel for the PoW
+            let mut wrong = honest.clone();
+            let mut wrong_hints = hints.clone();
+            if step.name == "pow" {
+                *wrong.inputs.last_mut().unwrap() = vec![0x42; 32];
+                let mut writer = step.stream.writer();
+                PoWCheckHint::new(
+                    wrong.inputs.last().unwrap(),
+                    &hint.pow_hint.nonce.to_le_bytes(),
+                    config.pow_bits,
+                )
+                .write(&mut writer, "pow_hint")
+                .unwrap();
+                wrong_hints = writer.finish().unwrap();
+            } else if wrong.outputs.is_empty() {
+                wrong.inputs.last_mut().unwrap().push(1);
+            } else {
+                wrong.outputs.last_mut().unwrap().push(1);
+            }
+            if wrong.outputs.is_empty() {
+                assert!(step.assert(&wrong_hints, wrong.inputs.clone()).is_none());
+            }
+            let exec_result = execute_script(script! {
+                { push_state(&step.disprove_witness(&wrong_hints, &wrong)) }
+                { step.disprove_script(&wrong).unwrap() }
+            });
+            assert!(exec_result.success);
+        }
+
+        let output = disprove_output(&steps, &assertions, unspendable_internal_key()).unwrap();
+        assert_eq!(output.leaves.len(), steps.len());
+
+        // a step cannot start from a state other than the one of the previous step
+        let mut unchained = assertions.clone();
+        unchained[1].inputs[0].push(1);
+        assert!(matches!(
+            disprove_output(&steps, &unchained, unspendable_internal_key()),
+            Err(DisproveError::BrokenChain { step: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_verifier_disprove_decommitments() {
+        let VerifierSteps {
+            hint,
+            steps,
+            hints,
+            initial_state,
+            ..
+        } = verifier_steps();
+
+        // the folded circle polynomials and the twins of each query are at the top of the initial
+        // state
+        let n_query_values = 2 + hint.fri_commitment_and_folding_hints.len();
+        let first_query_value = initial_state.len() - hint.fri_query_hints.len() * n_query_values;
+
+        // a wrong folded circle polynomial or twin in the initial state fails the check of its
+        // decommitment first, which can then be disproved with honest hints
+        for (index, name) in [
+            (first_query_value + 1, "trace_decommitment[0]"),
+            (
+                first_query_value + n_query_values,
+                "composition_decommitment[1]",
+            ),
+            (
+                first_query_value + 2 * n_query_values + 3,
+                "fri_twin_decommitment[2][1]",
+            ),
+        ] {
+            let mut wrong_state = initial_state.clone();
+            let StateValue::QM31(value) = wrong_state[index] else {
+                panic!("the value at {} is not a QM31", index);
+            };
+            wrong_state[index] = StateValue::QM31(value + QM31::one());
+
+            let mut state = wrong_state
+                .iter()
+                .flat_map(StateValue::to_elements)
+                .collect_vec();
+            let (step, hints, wrong) = steps
+                .iter()
+                .zip(hints.iter())
+                .find_map(|(step, hints)| match step.assert(hints, state.clone()) {
+                    Some(assertion) => {
+                        if let StepKind::Computation { .. } = step.kind {
+                            state = assertion.outputs;
+                        }
+                        None
+                    }
+                    None => Some((
+                        step,
+                        hints,
+                        Assertion {
+                            inputs: state.clone(),
+                            outputs: vec![],
+                        },
+                    )),
+                })
+                .unwrap();
+            assert_eq!(step.name, name);
+
+            let exec_result = execute_script(script! {
+                { push_state(&step.disprove_witness(hints, &wrong)) }
+                { step.disprove_script(&wrong).unwrap() }
+            });
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_verifier_disprove_last_layer() {
+        let VerifierSteps {
+            hint,
+            steps,
+            hints,
+            initial_state,
+            ..
+        } = verifier_steps();
+
+        // the last layer is only copied by the steps, so it stays where it is in the initial state
+        let first_coeff = 2
+            + hint.trace_oods_values.len()
+            + hint.composition_oods_values.len()
+            + hint.fri_commitment_and_folding_hints.len();
+        let offset = initial_state[..first_coeff]
+            .iter()
+            .flat_map(StateValue::to_elements)
+            .count();
+        let wrong_coeff = StateValue::QM31(hint.last_layer[0] + QM31::one()).to_elements();
+
+        // a wrong coefficient of the last layer does not match the folded queries, which can then
+        // be disproved with honest hints
+        let initial_state = initial_state
+            .iter()
+            .flat_map(StateValue::to_elements)
+            .collect_vec();
+        let assertions = assert_steps(&steps, &hints, initial_state).unwrap();
+        for ((step, hints), honest) in steps.iter().zip(hints.iter()).zip(assertions.iter()) {
+            if !step.name.starts_with("last_layer_check") {
+                continue;
+            }
+
+            let mut wrong = honest.clone();
+            wrong.inputs[offset..offset + wrong_coeff.len()].clone_from_slice(&wrong_coeff);
+            assert!(step.assert(hints, wrong.inputs.clone()).is_none());
+
+            let exec_result = execute_script(script! {
+                { push_state(&step.disprove_witness(hints, &wrong)) }
+                { step.disprove_script(&wrong).unwrap() }
+            });
+            assert!(exec_result.success);
+        }
+    }
+}
@@ -0,0 +1,742 @@
+use crate::air::CompositionHint;
+use crate::channel::{BitcoinIntegerEncodedData, DrawHints};
+use crate::fri::FriQueryHint;
+use crate::merkle_tree::{MerkleTreeSiblingProof, MerkleTreeTwinProof};
+use crate::oods::OODSHint;
+use crate::pow::PoWHint;
+use crate::precomputed_merkle_tree::TwiddleMerkleTreeProof;
+use crate::quotients::{FriAnswerHint, FriAnswerPairHint};
+use crate::verifier::VerifierHints;
+use std::fmt::{Display, Formatter};
+use stwo_prover::core::circle::CirclePoint;
+use stwo_prover::core::fields::cm31::CM31;
+use stwo_prover::core::fields::m31::{M31, P};
+use stwo_prover::core::fields::qm31::QM31;
+use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+
+/// The magic bytes at the start of an encoded `VerifierHints`.
+pub const HINTS_MAGIC: [u8; 4] = *b"BCSH";
+
+/// The version of the encoding of `VerifierHints`, to be increased whenever the encoding of any
+/// hint changes.
+pub const HINTS_VERSION: u8 = 2;
+
+/// An error when decoding hints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The input ends before the hints.
+    UnexpectedEnd,
+    /// The input does not start with `HINTS_MAGIC`.
+    InvalidMagic,
+    /// The version is not `HINTS_VERSION`.
+    UnsupportedVersion(u8),
+    /// The value is not a reduced m31 element.
+    InvalidM31(u32),
+    /// The bytes are not a minimally encoded Bitcoin integer of at most 8 bytes.
+    InvalidInteger(Vec<u8>),
+    /// The tag of an optional value is neither zero nor one.
+    InvalidTag(u8),
+    /// The remaining bytes of the draw hints do not match the number of the m31 elements.
+    InvalidDrawHints,
+    /// A Merkle proof for the twiddle tree has no element or no sibling.
+    InvalidTwiddleProof,
+    /// The input has bytes after the hints.
+    TrailingBytes(usize),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "unexpected end of the input"),
+            DecodeError::InvalidMagic => write!(f, "invalid magic bytes"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported version {}", version)
+            }
+            DecodeError::InvalidM31(v) => write!(f, "{} is not a reduced m31 element", v),
+            DecodeError::InvalidInteger(bytes) => {
+                write!(f, "{:?} is not a minimally encoded integer", bytes)
+            }
+            DecodeError::InvalidTag(tag) => write!(f, "invalid tag {}", tag),
+            DecodeError::InvalidDrawHints => write!(f, "invalid draw hints"),
+            DecodeError::InvalidTwiddleProof => write!(f, "invalid twiddle tree proof"),
+            DecodeError::TrailingBytes(n) => write!(f, "{} trailing bytes", n),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// A compact binary encoding of a hint.
+///
+/// Integers are little-endian, and a vector is prefixed with its length as a `u32`.
+pub trait Encoding: Sized {
+    /// Append the encoding of the hint to the output.
+    fn encode(&self, out: &mut Vec<u8>);
+
+    /// Decode a hint from the start of the input and advance the input past it.
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError>;
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], DecodeError> {
+    if input.len() < n {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    let (bytes, rest) = input.split_at(n);
+    *input = rest;
+    Ok(bytes)
+}
+
+fn take_array<const N: usize>(input: &mut &[u8]) -> Result<[u8; N], DecodeError> {
+    let mut res = [0u8; N];
+    res.copy_from_slice(take(input, N)?);
+    Ok(res)
+}
+
+impl Encoding for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(take_array::<1>(input)?[0])
+    }
+}
+
+impl Encoding for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(u32::from_le_bytes(take_array(input)?))
+    }
+}
+
+impl Encoding for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(u64::from_le_bytes(take_array(input)?))
+    }
+}
+
+impl<T: Encoding> Encoding for Vec<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        (self.len() as u32).encode(out);
+        for elem in self.iter() {
+            elem.encode(out);
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let len = u32::decode(input)? as usize;
+        // each element takes at least one byte, which bounds the allocation
+        if len > input.len() {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        (0..len).map(|_| T::decode(input)).collect()
+    }
+}
+
+impl<T: Encoding, const N: usize> Encoding for [T; N] {
+    fn encode(&self, out: &mut Vec<u8>) {
+        for elem in self.iter() {
+            elem.encode(out);
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let elems = (0..N)
+            .map(|_| T::decode(input))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(elems.try_into().ok().unwrap())
+    }
+}
+
+impl<T: Encoding> Encoding for Option<T> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            None => out.push(0),
+            Some(v) => {
+                out.push(1);
+                v.encode(out);
+            }
+        }
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        match u8::decode(input)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(input)?)),
+            tag => Err(DecodeError::InvalidTag(tag)),
+        }
+    }
+}
+
+impl<A: Encoding, B: Encoding> Encoding for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok((A::decode(input)?, B::decode(input)?))
+    }
+}
+
+impl Encoding for M31 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let v = u32::decode(input)?;
+        if v >= P {
+            return Err(DecodeError::InvalidM31(v));
+        }
+        Ok(M31::reduce(v as u64))
+    }
+}
+
+impl Encoding for CM31 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(CM31(M31::decode(input)?, M31::decode(input)?))
+    }
+}
+
+impl Encoding for QM31 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(QM31(CM31::decode(input)?, CM31::decode(input)?))
+    }
+}
+
+impl Encoding for CirclePoint<M31> {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.x.encode(out);
+        self.y.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(CirclePoint {
+            x: M31::decode(input)?,
+            y: M31::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for BWSSha256Hash {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_ref());
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(BWSSha256Hash::from(take(input, 32)?.to_vec()))
+    }
+}
+
+/// The stack element of a Bitcoin integer, minimally encoded.
+pub(crate) fn integer_to_bytes(v: i64) -> Vec<u8> {
+    let mut res = vec![];
+    let mut abs = v.unsigned_abs();
+    while abs > 0 {
+        res.push((abs & 0xff) as u8);
+        abs >>= 8;
+    }
+    if let Some(last) = res.last_mut() {
+        if *last & 0x80 != 0 {
+            res.push(if v < 0 { 0x80 } else { 0x00 });
+        } else if v < 0 {
+            *last |= 0x80;
+        }
+    }
+    res
+}
+
+fn integer_from_bytes(bytes: &[u8]) -> Option<i64> {
+    if bytes.len() > 8 {
+        return None;
+    }
+    let mut abs = 0u64;
+    for (i, byte) in bytes.iter().enumerate() {
+        let byte = if i == bytes.len() - 1 {
+            byte & 0x7f
+        } else {
+            *byte
+        };
+        abs |= (byte as u64) << (8 * i);
+    }
+    let abs = i64::try_from(abs).ok()?;
+    let v = match bytes.last() {
+        Some(last) if last & 0x80 != 0 => -abs,
+        _ => abs,
+    };
+    // reject the encodings that are not minimal
+    (integer_to_bytes(v) == bytes).then_some(v)
+}
+
+/// Encoded as the stack element it pushes, so that the negative zero stays `0x80`.
+impl Encoding for BitcoinIntegerEncodedData {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.to_bytes().encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let bytes = Vec::<u8>::decode(input)?;
+        if bytes == [0x80] {
+            return Ok(BitcoinIntegerEncodedData::NegativeZero);
+        }
+        integer_from_bytes(&bytes)
+            .map(BitcoinIntegerEncodedData::Other)
+            .ok_or(DecodeError::InvalidInteger(bytes))
+    }
+}
+
+impl Encoding for DrawHints {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let res = DrawHints(Vec::decode(input)?, Vec::decode(input)?);
+
+        // as asserted when the hints are pushed
+        let n = res.0.len();
+        let expected_len = if n % 8 == 0 { 0 } else { 32 - (n % 8) * 4 };
+        if res.1.len() != expected_len {
+            return Err(DecodeError::InvalidDrawHints);
+        }
+        Ok(res)
+    }
+}
+
+impl Encoding for OODSHint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.x.encode(out);
+        self.y.encode(out);
+        self.hint.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(OODSHint {
+            x: QM31::decode(input)?,
+            y: QM31::decode(input)?,
+            hint: DrawHints::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for PoWHint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.nonce.encode(out);
+        self.prefix.encode(out);
+        self.msb.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(PoWHint {
+            nonce: u64::decode(input)?,
+            prefix: Vec::decode(input)?,
+            msb: Option::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for CompositionHint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.constraint_eval_quotients_by_mask.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(CompositionHint {
+            constraint_eval_quotients_by_mask: Vec::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for MerkleTreeTwinProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.left.encode(out);
+        self.right.encode(out);
+        self.siblings.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(MerkleTreeTwinProof {
+            left: Vec::decode(input)?,
+            right: Vec::decode(input)?,
+            siblings: Vec::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for MerkleTreeSiblingProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.twin.encode(out);
+        self.siblings.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(MerkleTreeSiblingProof {
+            twin: QM31::decode(input)?,
+            siblings: Vec::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for TwiddleMerkleTreeProof {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.elements.encode(out);
+        self.siblings.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        let res = TwiddleMerkleTreeProof {
+            elements: Vec::decode(input)?,
+            siblings: Vec::decode(input)?,
+        };
+
+        // the proof is pushed from its last element and its last sibling
+        if res.elements.is_empty() || res.siblings.is_empty() {
+            return Err(DecodeError::InvalidTwiddleProof);
+        }
+        Ok(res)
+    }
+}
+
+impl Encoding for FriAnswerHint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.denominator_inverses.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(FriAnswerHint {
+            denominator_inverses: Vec::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for FriAnswerPairHint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.domain_point.encode(out);
+        self.even.encode(out);
+        self.odd.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(FriAnswerPairHint {
+            domain_point: CirclePoint::decode(input)?,
+            even: FriAnswerHint::decode(input)?,
+            odd: FriAnswerHint::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for FriQueryHint {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.twiddle_proofs.encode(out);
+        self.inner_layer_proofs.encode(out);
+        self.last_layer_x.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(FriQueryHint {
+            twiddle_proofs: Vec::decode(input)?,
+            inner_layer_proofs: Vec::decode(input)?,
+            last_layer_x: Option::decode(input)?,
+        })
+    }
+}
+
+impl Encoding for VerifierHints {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.commitments.encode(out);
+        self.random_coeff_hint.encode(out);
+        self.oods_hint.encode(out);
+        self.trace_oods_values.encode(out);
+        self.composition_oods_values.encode(out);
+        self.composition_hint.encode(out);
+        self.random_coeff_hint2.encode(out);
+        self.circle_poly_alpha_hint.encode(out);
+        self.fri_commitment_and_folding_hints.encode(out);
+        self.last_layer.encode(out);
+        self.pow_hint.encode(out);
+        self.queries_hints.encode(out);
+        self.merkle_proofs_traces.encode(out);
+        self.merkle_proofs_compositions.encode(out);
+        self.fri_answer_hints.encode(out);
+        self.fri_query_hints.encode(out);
+    }
+
+    fn decode(input: &mut &[u8]) -> Result<Self, DecodeError> {
+        Ok(VerifierHints {
+            commitments: Encoding::decode(input)?,
+            random_coeff_hint: Encoding::decode(input)?,
+            oods_hint: Encoding::decode(input)?,
+            trace_oods_values: Encoding::decode(input)?,
+            composition_oods_values: Encoding::decode(input)?,
+            composition_hint: Encoding::decode(input)?,
+            random_coeff_hint2: Encoding::decode(input)?,
+            circle_poly_alpha_hint: Encoding::decode(input)?,
+            fri_commitment_and_folding_hints: Encoding::decode(input)?,
+            last_layer: Encoding::decode(input)?,
+            pow_hint: Encoding::decode(input)?,
+            queries_hints: Encoding::decode(input)?,
+            merkle_proofs_traces: Encoding::decode(input)?,
+            merkle_proofs_compositions: Encoding::decode(input)?,
+            fri_answer_hints: Encoding::decode(input)?,
+            fri_query_hints: Encoding::decode(input)?,
+        })
+    }
+}
+
+impl VerifierHints {
+    /// Encode the hints, after `HINTS_MAGIC` and `HINTS_VERSION`, for example to save them to a
+    /// file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = HINTS_MAGIC.to_vec();
+        out.push(HINTS_VERSION);
+        self.encode(&mut out);
+        out
+    }
+
+    /// Decode the hints encoded by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        let mut input = bytes;
+        if take(&mut input, HINTS_MAGIC.len())? != HINTS_MAGIC {
+            return Err(DecodeError::InvalidMagic);
+        }
+        let version = u8::decode(&mut input)?;
+        if version != HINTS_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let res = Self::decode(&mut input)?;
+        if !input.is_empty() {
+            return Err(DecodeError::TrailingBytes(input.len()));
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::channel::{BitcoinIntegerEncodedData, DrawHints};
+    use crate::encoding::{DecodeError, Encoding, HINTS_MAGIC, HINTS_VERSION};
+    use crate::merkle_tree::{MerkleTreeSiblingProof, MerkleTreeTwinProof};
+    use crate::oods::OODSHint;
+    use crate::pow::PoWHint;
+    use crate::tests_utils::fibonacci::{hint_and_channel, hint_stream};
+    use crate::treepp::*;
+    use crate::utils::get_rand_qm31;
+    use crate::verifier::VerifierHints;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use stwo_prover::core::fields::m31::{M31, P};
+    use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+
+    fn round_trip<T: Encoding>(v: &T) -> T {
+        let mut bytes = vec![];
+        v.encode(&mut bytes);
+
+        let mut input = bytes.as_slice();
+        let res = T::decode(&mut input).unwrap();
+        assert!(input.is_empty());
+
+        // every truncation must be rejected
+        for len in 0..bytes.len() {
+            assert!(T::decode(&mut &bytes[..len]).is_err());
+        }
+
+        let mut res_bytes = vec![];
+        res.encode(&mut res_bytes);
+        assert_eq!(res_bytes, bytes);
+
+        res
+    }
+
+    fn random_digest<R: RngCore>(prng: &mut R) -> BWSSha256Hash {
+        let mut digest = [0u8; 32];
+        prng.fill_bytes(&mut digest);
+        BWSSha256Hash::from(digest.to_vec())
+    }
+
+    #[test]
+    fn test_integer_encoding() {
+        for v in [
+            BitcoinIntegerEncodedData::NegativeZero,
+            BitcoinIntegerEncodedData::Other(0),
+            BitcoinIntegerEncodedData::Other(1),
+            BitcoinIntegerEncodedData::Other(-1),
+            BitcoinIntegerEncodedData::Other(16),
+            BitcoinIntegerEncodedData::Other(127),
+            BitcoinIntegerEncodedData::Other(128),
+            BitcoinIntegerEncodedData::Other(-128),
+            BitcoinIntegerEncodedData::Other(-255),
+            BitcoinIntegerEncodedData::Other(i32::MAX as i64),
+            BitcoinIntegerEncodedData::Other(i32::MIN as i64),
+            BitcoinIntegerEncodedData::Other(i64::MAX),
+            BitcoinIntegerEncodedData::Other(-i64::MAX),
+        ] {
+            assert_eq!(round_trip(&v), v);
+
+            // the encoding holds the element pushed to the stack
+            let mut bytes = vec![];
+            v.encode(&mut bytes);
+            let exec_result = execute_script(script! { { v } });
+            assert_eq!(bytes[4..].to_vec(), exec_result.final_stack.get(0));
+        }
+
+        // the negative zero is not the zero
+        let mut bytes = vec![];
+        BitcoinIntegerEncodedData::NegativeZero.encode(&mut bytes);
+        assert_eq!(bytes, vec![1, 0, 0, 0, 0x80]);
+
+        // integers must be minimally encoded
+        for bytes in [
+            vec![0x00],
+            vec![0x01, 0x00],
+            vec![0x7f, 0x80],
+            vec![0xff; 9],
+        ] {
+            let mut encoded = vec![];
+            bytes.encode(&mut encoded);
+            assert_eq!(
+                BitcoinIntegerEncodedData::decode(&mut encoded.as_slice()),
+                Err(DecodeError::InvalidInteger(bytes))
+            );
+        }
+    }
+
+    #[test]
+    fn test_hints_encoding() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n in [0, 3, 8, 13] {
+            let draw_hints = DrawHints(
+                (0..n)
+                    .map(|_| {
+                        if prng.gen_bool(0.2) {
+                            BitcoinIntegerEncodedData::NegativeZero
+                        } else {
+                            BitcoinIntegerEncodedData::Other(prng.gen_range(-(1 << 31)..1 << 31))
+                        }
+                    })
+                    .collect(),
+                if n % 8 == 0 {
+                    vec![]
+                } else {
+                    (0..32 - (n % 8) * 4).map(|_| prng.gen()).collect()
+                },
+            );
+            assert_eq!(round_trip(&draw_hints), draw_hints);
+
+            let oods_hint = OODSHint {
+                x: get_rand_qm31(&mut prng),
+                y: get_rand_qm31(&mut prng),
+                hint: draw_hints.clone(),
+            };
+            let res = round_trip(&oods_hint);
+            assert_eq!(
+                (res.x, res.y, res.hint),
+                (oods_hint.x, oods_hint.y, draw_hints)
+            );
+        }
+
+        // the remaining bytes of the draw hints must match the number of the elements
+        let mut bytes = vec![];
+        DrawHints(vec![BitcoinIntegerEncodedData::Other(1)], vec![]).encode(&mut bytes);
+        assert_eq!(
+            DrawHints::decode(&mut bytes.as_slice()),
+            Err(DecodeError::InvalidDrawHints)
+        );
+
+        for msb in [None, Some(prng.gen())] {
+            let pow_hint = PoWHint {
+                nonce: prng.next_u64(),
+                prefix: (0..prng.gen_range(28..32)).map(|_| prng.gen()).collect(),
+                msb,
+            };
+            let res = round_trip(&pow_hint);
+            assert_eq!(
+                (res.nonce, res.prefix, res.msb),
+                (pow_hint.nonce, pow_hint.prefix, pow_hint.msb)
+            );
+        }
+
+        let twin_proof = MerkleTreeTwinProof {
+            left: (0..3).map(|_| M31::reduce(prng.next_u64())).collect(),
+            right: (0..3).map(|_| M31::reduce(prng.next_u64())).collect(),
+            siblings: (0..5).map(|_| random_digest(&mut prng)).collect(),
+        };
+        let res = round_trip(&twin_proof);
+        assert_eq!(res.left, twin_proof.left);
+        assert_eq!(res.right, twin_proof.right);
+        assert_eq!(res.siblings, twin_proof.siblings);
+
+        let sibling_proof = MerkleTreeSiblingProof {
+            twin: get_rand_qm31(&mut prng),
+            siblings: (0..5).map(|_| random_digest(&mut prng)).collect(),
+        };
+        let res = round_trip(&sibling_proof);
+        assert_eq!(res.twin, sibling_proof.twin);
+        assert_eq!(res.siblings, sibling_proof.siblings);
+
+        // m31 elements must be reduced
+        let mut bytes = vec![];
+        P.encode(&mut bytes);
+        assert_eq!(
+            M31::decode(&mut bytes.as_slice()),
+            Err(DecodeError::InvalidM31(P))
+        );
+    }
+
+    #[test]
+    fn test_verifier_hints_encoding() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let stream = hint_stream(5, claim, &channel);
+
+        let bytes = hint.to_bytes();
+        let decoded = VerifierHints::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(
+            decoded.to_elements(&stream).unwrap(),
+            hint.to_elements(&stream).unwrap()
+        );
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[HINTS_MAGIC.len()] += 1;
+        assert_eq!(
+            VerifierHints::from_bytes(&wrong_version).err(),
+            Some(DecodeError::UnsupportedVersion(HINTS_VERSION + 1))
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            VerifierHints::from_bytes(&trailing).err(),
+            Some(DecodeError::TrailingBytes(1))
+        );
+
+        assert_eq!(
+            VerifierHints::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            VerifierHints::from_bytes(&bytes[1..]).err(),
+            Some(DecodeError::InvalidMagic)
+        );
+    }
+}
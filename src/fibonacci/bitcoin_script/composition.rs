@@ -1,9 +1,11 @@
+use crate::chunker::StateItem;
 use crate::constraints::ConstraintsGadget;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
 use num_traits::One;
 use rust_bitcoin_m31::{
-    qm31_add, qm31_copy, qm31_dup, qm31_equalverify, qm31_from_bottom, qm31_fromaltstack, qm31_mul,
-    qm31_mul_m31, qm31_over, qm31_rot, qm31_square, qm31_sub, qm31_swap, qm31_toaltstack,
+    qm31_add, qm31_copy, qm31_dup, qm31_equalverify, qm31_fromaltstack, qm31_mul, qm31_mul_m31,
+    qm31_over, qm31_rot, qm31_square, qm31_sub, qm31_swap, qm31_toaltstack,
 };
 use stwo_prover::core::circle::{CirclePoint, Coset};
 use stwo_prover::core::fields::m31::M31;
@@ -30,7 +32,11 @@ impl FibonacciCompositionGadget {
     /// - num/denom
     ///
     #[allow(dead_code)]
-    fn step_constraint_eval_quotient_by_mask(log_size: u32) -> Script {
+    fn step_constraint_eval_quotient_by_mask(
+        stream: &mut HintStream,
+        label: &str,
+        log_size: u32,
+    ) -> Script {
         let constraint_zero_domain = Coset::subgroup(log_size);
 
         script! {
@@ -70,7 +76,7 @@ impl FibonacciCompositionGadget {
             qm31_fromaltstack
             { ConstraintsGadget::coset_vanishing(constraint_zero_domain) } // denom
 
-            qm31_from_bottom // num/denom
+            { stream.pull(label, StateItem::QM31) } // num/denom
             qm31_dup
             qm31_toaltstack
 
@@ -94,7 +100,12 @@ impl FibonacciCompositionGadget {
     /// Output:
     /// - num/denom
     ///
-    fn boundary_constraint_eval_quotient_by_mask(log_size: u32, claim: M31) -> Script {
+    fn boundary_constraint_eval_quotient_by_mask(
+        stream: &mut HintStream,
+        label: &str,
+        log_size: u32,
+        claim: M31,
+    ) -> Script {
         let constraint_zero_domain = Coset::subgroup(log_size);
         let p = constraint_zero_domain.at(constraint_zero_domain.size() - 1);
         script! {
@@ -115,7 +126,7 @@ impl FibonacciCompositionGadget {
             qm31_fromaltstack // bring back z.y from altstack
             { ConstraintsGadget::pair_vanishing_with_constant_m31_points(p, CirclePoint::zero())} // denom
 
-            qm31_from_bottom // pull num/denom from hint
+            { stream.pull(label, StateItem::QM31) } // pull num/denom from hint
 
             qm31_dup
             qm31_toaltstack // store num/denom in altstack
@@ -130,7 +141,7 @@ impl FibonacciCompositionGadget {
 
     /// Computes the composition polynomial of Fibonacci
     ///
-    /// Hint:
+    /// Hint (pulled through the stream under the label):
     /// - boundary result
     /// - step result
     ///
@@ -145,15 +156,20 @@ impl FibonacciCompositionGadget {
     /// Output:
     /// - alpha * step_constraint(f(z),f(Gz),f(G^2 z),z) + boundary_constraint(f(z),z,claim)
     ///
-    pub(crate) fn eval_composition_polynomial_at_point(log_size: u32, claim: M31) -> Script {
+    pub(crate) fn eval_composition_polynomial_at_point(
+        stream: &mut HintStream,
+        label: &str,
+        log_size: u32,
+        claim: M31,
+    ) -> Script {
         script! {
             { qm31_copy(4) }
             { qm31_copy(2) }
             { qm31_copy(2) }
-            { Self::boundary_constraint_eval_quotient_by_mask(log_size,claim) }
+            { Self::boundary_constraint_eval_quotient_by_mask(stream, label, log_size, claim) }
             qm31_toaltstack
 
-            { Self::step_constraint_eval_quotient_by_mask(log_size) }
+            { Self::step_constraint_eval_quotient_by_mask(stream, label, log_size) }
             qm31_mul
 
             qm31_fromaltstack
@@ -164,7 +180,9 @@ impl FibonacciCompositionGadget {
 
 #[cfg(test)]
 mod test {
-    use crate::air::CompositionHint;
+    use crate::air::{CompositionAtPoint, CompositionHint, ScriptAir};
+    use crate::chunker::StateValue;
+    use crate::tests_utils::gadget::check_gadget;
     use itertools::Itertools;
     use rand::SeedableRng;
     use rand_chacha::ChaCha20Rng;
@@ -182,6 +200,7 @@ mod test {
     };
 
     use crate::fibonacci::bitcoin_script::composition::FibonacciCompositionGadget;
+    use crate::hint_stream::HintStream;
     use crate::tests_utils::report::report_bitcoin_script_size;
     use crate::treepp::*;
     use crate::utils::get_rand_qm31;
@@ -202,8 +221,14 @@ mod test {
 
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
+        let mut stream = HintStream::new();
         let composition_polynomial_script =
-            FibonacciCompositionGadget::eval_composition_polynomial_at_point(log_size, claim);
+            FibonacciCompositionGadget::eval_composition_polynomial_at_point(
+                &mut stream,
+                "composition_hint",
+                log_size,
+                claim,
+            );
         report_bitcoin_script_size(
             "Fibonacci",
             format!(
@@ -252,8 +277,13 @@ mod test {
                 ],
             };
 
+            let mut writer = stream.writer();
+            composition_hint
+                .write(&mut writer, "composition_hint")
+                .unwrap();
+
             let script = script! {
-                { composition_hint } // hint
+                { writer.finish_script().unwrap() } // hint
                 { random_coeff }
                 { comp[0][0] }
                 { comp[0][1] }
@@ -269,4 +299,27 @@ mod test {
             assert!(exec_result.success);
         }
     }
+
+    #[test]
+    fn test_gadgets() {
+        let fib = Fibonacci::new(5, M31::from_u32_unchecked(443693538));
+
+        check_gadget(&CompositionAtPoint(&fib.air), 10, |inputs, writer| {
+            let [StateValue::QM31(random_coeff), StateValue::QM31(a), StateValue::QM31(b), StateValue::QM31(c), StateValue::CirclePoint(z)] =
+                inputs
+            else {
+                unreachable!()
+            };
+            let mask_values = ComponentVec(vec![vec![vec![*a, *b, *c]]]);
+
+            fib.air
+                .composition_hint(*z, &mask_values)
+                .write(writer, "composition_hint")
+                .unwrap();
+            vec![StateValue::QM31(
+                fib.air
+                    .eval_composition_polynomial_at_point(*z, &mask_values, *random_coeff),
+            )]
+        });
+    }
 }
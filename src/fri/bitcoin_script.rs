@@ -1,7 +1,18 @@
+use crate::chunker::StateItem;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
+use crate::layout::{Input, LayoutBuilder, LayoutError};
+use crate::merkle_tree::MerkleTreeTwinGadget;
+use crate::precomputed_merkle_tree::{PrecomputedMerkleTree, PrecomputedMerkleTreeGadget};
 use crate::treepp::*;
+use crate::utils::qm31_pick_gadget;
+use itertools::Itertools;
+use num_traits::One;
 use rust_bitcoin_m31::{
-    qm31_add, qm31_fromaltstack, qm31_mul_m31, qm31_over, qm31_sub, qm31_toaltstack,
+    m31_add, m31_mul, m31_sub, qm31_add, qm31_drop, qm31_equalverify, qm31_fromaltstack, qm31_mul,
+    qm31_mul_m31, qm31_over, qm31_square, qm31_sub, qm31_toaltstack,
 };
+use stwo_prover::core::fields::m31::M31;
 
 /// Gadget for FFT.
 pub struct FFTGadget;
@@ -36,16 +47,425 @@ impl FFTGadget {
     }
 }
 
+/// Gadget for FRI.
+pub struct FriGadget;
+
+impl FriGadget {
+    /// Fold a pair of evaluations.
+    ///
+    /// Input:
+    /// - v_even (qm31)
+    /// - v_odd (qm31)
+    /// - itwid (m31)
+    /// - alpha (qm31)
+    ///
+    /// Output:
+    /// - v_even + v_odd + alpha * (v_even - v_odd) * itwid (qm31)
+    pub fn fold() -> Script {
+        script! {
+            qm31_toaltstack
+            { FFTGadget::ibutterfly() }
+            qm31_fromaltstack
+            qm31_mul
+            qm31_add
+        }
+    }
+
+    /// Evaluate the last layer polynomial, whose coefficients are in the bit-reversed order as in
+    /// stwo's `LinePoly`.
+    ///
+    /// Input:
+    /// - c_0, c_1, ..., c_{2^log_size - 1} (qm31)
+    /// - x (m31)
+    ///
+    /// Output:
+    /// - the evaluation at x (qm31)
+    pub fn eval_last_layer_poly(log_size: usize) -> Script {
+        let n_doublings = log_size.max(1);
+        script! {
+            // compute x, 2x^2 - 1, ...
+            for _ in 1..log_size {
+                OP_DUP OP_DUP m31_mul
+                OP_DUP m31_add
+                1 m31_sub
+            }
+
+            { Self::fold_coeffs(n_doublings, 1 << log_size, 0, 1 << log_size, 0) }
+
+            qm31_toaltstack
+            for _ in 0..n_doublings {
+                OP_DROP
+            }
+            for _ in 0..(1 << log_size) {
+                qm31_drop
+            }
+            qm31_fromaltstack
+        }
+    }
+
+    // Fold the coefficients in [lo, hi) as stwo's `fold` does, where the doublings of x are on the
+    // top of the stack, followed by the coefficients, and `extra` elements have been pushed since.
+    fn fold_coeffs(
+        n_doublings: usize,
+        n_coeffs: usize,
+        lo: usize,
+        hi: usize,
+        extra: usize,
+    ) -> Script {
+        if hi - lo == 1 {
+            qm31_pick_gadget(n_doublings + 4 * (n_coeffs - 1 - lo) + extra)
+        } else {
+            let mid = (lo + hi) / 2;
+            script! {
+                { Self::fold_coeffs(n_doublings, n_coeffs, lo, mid, extra) }
+                { Self::fold_coeffs(n_doublings, n_coeffs, mid, hi, extra + 4) }
+                { (hi - lo).trailing_zeros() as usize - 1 + extra + 8 } OP_PICK
+                qm31_mul_m31
+                qm31_add
+            }
+        }
+    }
+
+    /// Check that x times the inverse twiddle factor is 1, or -1 if the position is odd, where the
+    /// parity is the difference of the position and twice the position shifted by one, if given.
+    fn check_x(
+        builder: &mut LayoutBuilder,
+        x: Input,
+        itwid: Input,
+        position: &str,
+        shifted_position: Option<&str>,
+    ) -> Result<(), LayoutError> {
+        let mut inputs = vec![
+            (x, StateItem::M31),
+            (itwid, StateItem::M31),
+            (Input::Copy(position), StateItem::M31),
+        ];
+        inputs.extend(shifted_position.map(|name| (Input::Copy(name), StateItem::M31)));
+        builder.call(
+            &inputs,
+            script! {
+                if shifted_position.is_some() {
+                    OP_DUP OP_ADD OP_SUB
+                }
+                OP_TOALTSTACK
+                m31_mul
+                OP_FROMALTSTACK
+                OP_IF { -M31::one() } OP_ELSE 1 OP_ENDIF
+                OP_EQUALVERIFY
+            },
+            &[],
+        )
+    }
+
+    /// Check the FRI folding of a query, from the circle polynomials down to the last layer, on
+    /// the slots of the builder.
+    ///
+    /// Slots:
+    /// - circle_poly_alpha (qm31)
+    /// - fri_commitment[j] (digest) and folding_alpha[j] (qm31) for each of the `n_layers` inner
+    ///   layers
+    /// - last_layer[i] (qm31) for each of the 2^log_last_layer_size coefficients
+    /// - query >> k (m31) for k in 0..n (where n is the largest column log size), which are
+    ///   consumed
+    ///
+    /// Altstack input (for each column log size in the decreasing order, the first on the top):
+    /// - v_even, v_odd (qm31, the evaluations of the circle polynomials on the queried pair)
+    /// - z.x, z.y (m31, the domain point at the even position)
+    ///
+    /// Hint (pulled through the stream under the label):
+    /// - FriQueryHint
+    pub fn verify_query(
+        builder: &mut LayoutBuilder,
+        stream: &mut HintStream,
+        label: &str,
+        column_log_sizes: &[u32],
+        n_layers: usize,
+        log_last_layer_size: usize,
+    ) -> Result<(), LayoutError> {
+        let n = column_log_sizes[0] as usize;
+        assert!(column_log_sizes.iter().all(|&c| c >= 3));
+
+        let shift = |k: usize| format!("query >> {}", k);
+        let itwid = |k: usize, i: usize| format!("itwid[{}][{}]", k, i);
+        let twiddle_trees = column_log_sizes
+            .iter()
+            .map(|&c| PrecomputedMerkleTree::new(c as usize - 1))
+            .collect_vec();
+
+        // fold the circle polynomials of each size, the largest into the running folded value
+        for (k, &c) in column_log_sizes.iter().enumerate() {
+            let c = c as usize;
+
+            // the inverse twiddle factors on the path of query >> (n - c) in the twiddle tree, the
+            // one of the circle on the top, followed by the ones of the line layers
+            let itwids = (0..c - 1).map(|i| itwid(k, i)).collect_vec();
+            let position = shift(n - c);
+            builder.push(
+                "twiddle_root",
+                StateItem::Digest,
+                script! { { twiddle_trees[k].root_hash.to_vec() } },
+            )?;
+            builder.call(
+                &[
+                    (Input::Move("twiddle_root"), StateItem::Digest),
+                    (
+                        if k == 0 {
+                            Input::Move(&position)
+                        } else {
+                            Input::Copy(&position)
+                        },
+                        StateItem::M31,
+                    ),
+                ],
+                PrecomputedMerkleTreeGadget::query_and_verify(stream, label, c),
+                &itwids
+                    .iter()
+                    .rev()
+                    .map(|name| (name.as_str(), StateItem::M31))
+                    .collect_vec(),
+            )?;
+
+            // only the largest size goes through the line layers
+            if k > 0 {
+                for name in itwids[2..].iter() {
+                    builder.drop(name)?;
+                }
+            }
+
+            // the twin on the queried pair and the domain point, whose y is the inverse of the
+            // circle twiddle factor, and whose x is the inverse of the line twiddle factor up to
+            // the sign given by the parity of query >> (n - c + 1)
+            for name in ["twin_even", "twin_odd"] {
+                builder.push(name, StateItem::QM31, script! { qm31_fromaltstack })?;
+            }
+            for name in ["z.x", "z.y"] {
+                builder.push(name, StateItem::M31, script! { OP_FROMALTSTACK })?;
+            }
+            builder.call(
+                &[
+                    (Input::Move("z.y"), StateItem::M31),
+                    (Input::Copy(&itwids[0]), StateItem::M31),
+                ],
+                script! { m31_mul 1 OP_EQUALVERIFY },
+                &[],
+            )?;
+            Self::check_x(
+                builder,
+                Input::Move("z.x"),
+                if k == 0 {
+                    Input::Copy(&itwids[1])
+                } else {
+                    Input::Move(&itwids[1])
+                },
+                &shift(n - c + 1),
+                Some(&shift(n - c + 2)),
+            )?;
+
+            let folded = if k == 0 {
+                "folded".to_string()
+            } else {
+                format!("folded_circle[{}]", k)
+            };
+            builder.call(
+                &[
+                    (Input::Move("twin_even"), StateItem::QM31),
+                    (Input::Move("twin_odd"), StateItem::QM31),
+                    (Input::Move(&itwids[0]), StateItem::M31),
+                    (Input::Copy("circle_poly_alpha"), StateItem::QM31),
+                ],
+                Self::fold(),
+                &[(folded.as_str(), StateItem::QM31)],
+            )?;
+        }
+
+        for j in 0..n_layers {
+            // the folded circle polynomials of the smaller sizes join the layer of their size
+            for (k, &c) in column_log_sizes.iter().enumerate().skip(1) {
+                if n - j == c as usize {
+                    builder.call(
+                        &[
+                            (Input::Move("folded"), StateItem::QM31),
+                            (Input::Copy("circle_poly_alpha"), StateItem::QM31),
+                            (
+                                Input::Move(&format!("folded_circle[{}]", k)),
+                                StateItem::QM31,
+                            ),
+                        ],
+                        script! {
+                            qm31_toaltstack
+                            qm31_square
+                            qm31_mul
+                            qm31_fromaltstack
+                            qm31_add
+                        },
+                        &[("folded", StateItem::QM31)],
+                    )?;
+                }
+            }
+
+            // check the twin against the commitment, as the sibling of the folded value at the
+            // position query >> (j + 1), and fold it with the inverse twiddle factor of the layer
+            builder.call(
+                &[
+                    (Input::Move("folded"), StateItem::QM31),
+                    (
+                        Input::Copy(&format!("fri_commitment[{}]", j)),
+                        StateItem::Digest,
+                    ),
+                    (Input::Move(&shift(j + 1)), StateItem::M31),
+                ],
+                MerkleTreeTwinGadget::query_and_verify_sibling(stream, label, n - 1 - j),
+                &[
+                    ("twin_even", StateItem::QM31),
+                    ("twin_odd", StateItem::QM31),
+                ],
+            )?;
+            builder.call(
+                &[
+                    (Input::Move("twin_even"), StateItem::QM31),
+                    (Input::Move("twin_odd"), StateItem::QM31),
+                    (Input::Move(&itwid(0, j + 1)), StateItem::M31),
+                    (
+                        Input::Copy(&format!("folding_alpha[{}]", j)),
+                        StateItem::QM31,
+                    ),
+                ],
+                Self::fold(),
+                &[("folded", StateItem::QM31)],
+            )?;
+        }
+
+        // check against the last layer
+        if log_last_layer_size == 0 {
+            builder.call(
+                &[
+                    (Input::Move("folded"), StateItem::QM31),
+                    (Input::Copy("last_layer[0]"), StateItem::QM31),
+                ],
+                script! { qm31_equalverify },
+                &[],
+            )?;
+        } else {
+            // pull the x-coordinate of the folded query in the last layer domain, and check it
+            // against the inverse twiddle factor (which is a constant if the domain has only one
+            // pair)
+            builder.push("x", StateItem::M31, stream.pull(label, StateItem::M31))?;
+            let has_pairs = n - 1 - n_layers >= 2;
+            let last_itwid = itwid(0, n_layers + 1);
+            if !has_pairs {
+                builder.push(
+                    &last_itwid,
+                    StateItem::M31,
+                    script! { { twiddle_trees[0].twiddles_inverse[n_layers + 1][0] } },
+                )?;
+            }
+            let shifted_position = shift(n_layers + 2);
+            Self::check_x(
+                builder,
+                Input::Copy("x"),
+                if has_pairs {
+                    Input::Copy(&last_itwid)
+                } else {
+                    Input::Move(&last_itwid)
+                },
+                &shift(n_layers + 1),
+                has_pairs.then_some(shifted_position.as_str()),
+            )?;
+
+            // evaluate the last layer polynomial
+            let coeffs = (0..(1 << log_last_layer_size))
+                .map(|i| format!("last_layer[{}]", i))
+                .collect_vec();
+            let mut inputs = coeffs
+                .iter()
+                .map(|coeff| (Input::Copy(coeff), StateItem::QM31))
+                .collect_vec();
+            inputs.push((Input::Move("x"), StateItem::M31));
+            builder.call(
+                &inputs,
+                Self::eval_last_layer_poly(log_last_layer_size),
+                &[("last_layer_value", StateItem::QM31)],
+            )?;
+            builder.call(
+                &[
+                    (Input::Move("last_layer_value"), StateItem::QM31),
+                    (Input::Move("folded"), StateItem::QM31),
+                ],
+                script! { qm31_equalverify },
+                &[],
+            )?;
+        }
+
+        // drop the unused twiddle factors and positions
+        for i in (n_layers + 1)..(n - 1) {
+            builder.drop(&itwid(0, i))?;
+        }
+        for k in (n_layers + 1)..n {
+            builder.drop(&shift(k))?;
+        }
+        Ok(())
+    }
+}
+
+/// `FriGadget::fold` as a `Gadget`.
+pub struct FriFold;
+
+impl Gadget for FriFold {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![
+            StateItem::QM31,
+            StateItem::QM31,
+            StateItem::M31,
+            StateItem::QM31,
+        ]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        FriGadget::fold()
+    }
+}
+
+/// `FriGadget::eval_last_layer_poly` as a `Gadget`.
+pub struct EvalLastLayerPoly {
+    /// Log of the number of the coefficients.
+    pub log_size: usize,
+}
+
+impl Gadget for EvalLastLayerPoly {
+    fn inputs(&self) -> Vec<StateItem> {
+        let mut inputs = vec![StateItem::QM31; 1 << self.log_size];
+        inputs.push(StateItem::M31);
+        inputs
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn script(&self, _: &mut HintStream) -> Script {
+        FriGadget::eval_last_layer_poly(self.log_size)
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use crate::fri::FFTGadget;
+    use crate::chunker::StateValue;
+    use crate::fri::{fold, EvalLastLayerPoly, FFTGadget, FriFold, FriGadget};
+    use crate::tests_utils::gadget::check_gadget;
+    use crate::tests_utils::report::report_bitcoin_script_size;
     use crate::treepp::*;
     use crate::utils::get_rand_qm31;
+    use itertools::Itertools;
     use rand::{RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
     use rust_bitcoin_m31::qm31_equalverify;
     use stwo_prover::core::fft::ibutterfly;
     use stwo_prover::core::fields::m31::M31;
+    use stwo_prover::core::poly::line::LinePoly;
 
     #[test]
     fn test_ibutterfly() {
@@ -77,4 +497,95 @@ mod test {
         let exec_result = execute_script(script);
         assert!(exec_result.success);
     }
+
+    #[test]
+    fn test_fold() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let v_even = get_rand_qm31(&mut prng);
+        let v_odd = get_rand_qm31(&mut prng);
+        let itwid = M31::reduce(prng.next_u64());
+        let alpha = get_rand_qm31(&mut prng);
+
+        let fold_script = FriGadget::fold();
+        report_bitcoin_script_size("FRI", "fold", fold_script.len());
+
+        let script = script! {
+            { v_even }
+            { v_odd }
+            { itwid }
+            { alpha }
+            { fold_script.clone() }
+            { fold(v_even, v_odd, itwid, alpha) }
+            qm31_equalverify
+            OP_TRUE
+        };
+
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_eval_last_layer_poly() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for log_size in 0..=4 {
+            let coeffs = (0..(1 << log_size))
+                .map(|_| get_rand_qm31(&mut prng))
+                .collect_vec();
+            let x = M31::reduce(prng.next_u64());
+            let poly = LinePoly::new(coeffs.clone());
+
+            let eval_script = FriGadget::eval_last_layer_poly(log_size);
+            report_bitcoin_script_size(
+                "FRI",
+                format!("eval_last_layer_poly(2^{})", log_size).as_str(),
+                eval_script.len(),
+            );
+
+            let script = script! {
+                for coeff in coeffs.iter() {
+                    { coeff }
+                }
+                { x }
+                { eval_script.clone() }
+                { poly.eval_at_point(x.into()) }
+                qm31_equalverify
+                OP_TRUE
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_gadgets() {
+        check_gadget(&FriFold, 20, |inputs, _| {
+            let [StateValue::QM31(v_even), StateValue::QM31(v_odd), StateValue::M31(itwid), StateValue::QM31(alpha)] =
+                inputs
+            else {
+                unreachable!()
+            };
+            vec![StateValue::QM31(fold(*v_even, *v_odd, *itwid, *alpha))]
+        });
+
+        for log_size in 0..=3 {
+            check_gadget(&EvalLastLayerPoly { log_size }, 10, |inputs, _| {
+                let (StateValue::M31(x), coeffs) = inputs.split_last().unwrap() else {
+                    unreachable!()
+                };
+                let coeffs = coeffs
+                    .iter()
+                    .map(|coeff| match coeff {
+                        StateValue::QM31(coeff) => *coeff,
+                        _ => unreachable!(),
+                    })
+                    .collect_vec();
+                vec![StateValue::QM31(
+                    LinePoly::new(coeffs).eval_at_point((*x).into()),
+                )]
+            });
+        }
+    }
 }
@@ -1,5 +1,19 @@
 use crate::channel::{ChannelWithHint, DrawHints};
+use crate::chunker::StateValue;
+use crate::hint_stream::{HintStreamError, HintWriter};
+use crate::merkle_tree::{MerkleTreeSiblingProof, SparseMerkleTree};
+use crate::precomputed_merkle_tree::{PrecomputedMerkleTree, TwiddleMerkleTreeProof};
+use crate::utils::bit_reverse_index;
+use itertools::Itertools;
+use num_traits::Zero;
+use std::collections::BTreeMap;
+use stwo_prover::core::fft::ibutterfly;
+use stwo_prover::core::fields::m31::M31;
+use stwo_prover::core::fields::qm31::QM31;
+use stwo_prover::core::fri::{FriLayerVerifier, FriVerificationError};
+use stwo_prover::core::poly::line::{LineDomain, LinePoly};
 use stwo_prover::core::queries::Queries;
+use stwo_prover::core::vcs::bws_sha256_merkle::BWSSha256MerkleHasher;
 
 mod bitcoin_script;
 pub use bitcoin_script::*;
@@ -30,3 +44,187 @@ impl QueriesWithHint for Queries {
         )
     }
 }
+
+/// Hints for checking the FRI folding of a single query.
+#[derive(Clone, Debug)]
+pub struct FriQueryHint {
+    /// Twiddle tree proofs, one for each column log size (in the decreasing order).
+    pub twiddle_proofs: Vec<TwiddleMerkleTreeProof>,
+    /// Sibling elements and Merkle paths of the inner layers.
+    pub inner_layer_proofs: Vec<MerkleTreeSiblingProof>,
+    /// The x-coordinate of the folded query in the last layer domain, which is only needed when
+    /// the last layer polynomial is not a constant.
+    pub last_layer_x: Option<M31>,
+}
+
+impl FriQueryHint {
+    /// Write the hint as pulled by `FriGadget::verify_query`, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        for twiddle_proof in self.twiddle_proofs.iter() {
+            twiddle_proof.write(writer, label)?;
+        }
+        for proof in self.inner_layer_proofs.iter() {
+            proof.write(writer, label)?;
+        }
+        if let Some(x) = self.last_layer_x {
+            writer.write(label, StateValue::M31(x))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fold a pair of evaluations with the inverse twiddle factor and the folding parameter.
+pub fn fold(v_even: QM31, v_odd: QM31, itwid: M31, alpha: QM31) -> QM31 {
+    let (mut f0, mut f1) = (v_even, v_odd);
+    ibutterfly(&mut f0, &mut f1, itwid);
+    f0 + alpha * f1
+}
+
+/// Generate the FRI hints for the queries (in the order they are drawn), while checking the
+/// inner layers and the last layer as stwo's FRI verifier does.
+///
+/// `first_layer_evals` contains the evaluations of the circle polynomials at the opening
+/// positions, one for each column log size (in the decreasing order).
+pub fn generate_fri_query_hints(
+    queries: &[usize],
+    column_log_sizes: &[u32],
+    first_layer_evals: &[BTreeMap<usize, QM31>],
+    circle_poly_alpha: QM31,
+    inner_layers: &[FriLayerVerifier<BWSSha256MerkleHasher>],
+    last_layer_domain: LineDomain,
+    last_layer_poly: &LinePoly,
+) -> Result<Vec<FriQueryHint>, FriVerificationError> {
+    let n = column_log_sizes[0] as usize;
+    let circle_poly_alpha_sq = circle_poly_alpha * circle_poly_alpha;
+
+    let twiddle_trees = column_log_sizes
+        .iter()
+        .map(|&log_size| PrecomputedMerkleTree::new(log_size as usize - 1))
+        .collect_vec();
+
+    let sorted_queries = queries.iter().copied().sorted().dedup().collect_vec();
+
+    let mut layer_evals = BTreeMap::<usize, QM31>::new();
+    let mut layer_values = Vec::with_capacity(inner_layers.len());
+    let mut layer_trees = Vec::with_capacity(inner_layers.len());
+
+    for (layer_index, layer) in inner_layers.iter().enumerate() {
+        let positions = sorted_queries
+            .iter()
+            .map(|q| q >> (layer_index + 1))
+            .dedup()
+            .collect_vec();
+
+        // fold the circle polynomials of the matching size into this layer
+        if let Some(k) = column_log_sizes
+            .iter()
+            .position(|&log_size| log_size as usize == n - layer_index)
+        {
+            for &pos in positions.iter() {
+                let folded = fold(
+                    first_layer_evals[k][&(pos << 1)],
+                    first_layer_evals[k][&((pos << 1) | 1)],
+                    twiddle_trees[k].twiddles_inverse[0][pos],
+                    circle_poly_alpha,
+                );
+                let eval = layer_evals.entry(pos).or_insert(QM31::zero());
+                *eval = *eval * circle_poly_alpha_sq + folded;
+            }
+        }
+
+        // complete the pairs with the evaluations from the proof
+        let mut evals_subset = layer.proof.evals_subset.iter().copied();
+        let mut values = BTreeMap::<usize, QM31>::new();
+        for pair in positions.iter().map(|pos| pos >> 1).dedup() {
+            for pos in [pair << 1, (pair << 1) | 1] {
+                let value = match layer_evals.get(&pos) {
+                    Some(&value) => value,
+                    None => evals_subset.next().ok_or(
+                        FriVerificationError::InnerLayerEvaluationsInvalid { layer: layer_index },
+                    )?,
+                };
+                values.insert(pos, value);
+            }
+        }
+        if evals_subset.next().is_some() {
+            return Err(FriVerificationError::InnerLayerEvaluationsInvalid { layer: layer_index });
+        }
+
+        let leaves = values
+            .iter()
+            .map(|(&pos, value)| (pos, value.to_m31_array().to_vec()))
+            .collect();
+        let tree = SparseMerkleTree::from_hash_witness(
+            n - 1 - layer_index,
+            &leaves,
+            &layer.proof.decommitment.hash_witness,
+        )
+        .filter(|tree| tree.root_hash() == layer.proof.commitment)
+        .ok_or(FriVerificationError::InnerLayerEvaluationsInvalid { layer: layer_index })?;
+
+        layer_evals = values
+            .iter()
+            .tuples()
+            .map(|((&pos, &v_even), (_, &v_odd))| {
+                (
+                    pos >> 1,
+                    fold(
+                        v_even,
+                        v_odd,
+                        twiddle_trees[0].twiddles_inverse[layer_index + 1][pos >> 1],
+                        layer.folding_alpha,
+                    ),
+                )
+            })
+            .collect();
+
+        layer_values.push(values);
+        layer_trees.push(tree);
+    }
+
+    let last_layer_x = |pos: usize| {
+        last_layer_domain.at(bit_reverse_index(
+            pos,
+            last_layer_domain.log_size() as usize,
+        ))
+    };
+    if layer_evals
+        .iter()
+        .any(|(&pos, &eval)| eval != last_layer_poly.eval_at_point(last_layer_x(pos).into()))
+    {
+        return Err(FriVerificationError::LastLayerEvaluationsInvalid);
+    }
+
+    Ok(queries
+        .iter()
+        .map(|&query| {
+            let twiddle_proofs = column_log_sizes
+                .iter()
+                .zip(twiddle_trees.iter())
+                .map(|(&log_size, twiddle_tree)| {
+                    twiddle_tree.query(query >> (n - log_size as usize))
+                })
+                .collect();
+
+            let inner_layer_proofs = layer_values
+                .iter()
+                .zip(layer_trees.iter())
+                .enumerate()
+                .map(|(layer_index, (values, tree))| {
+                    let pos = query >> (layer_index + 1);
+                    MerkleTreeSiblingProof {
+                        twin: values[&(pos ^ 1)],
+                        siblings: tree.siblings(pos),
+                    }
+                })
+                .collect();
+
+            FriQueryHint {
+                twiddle_proofs,
+                inner_layer_proofs,
+                last_layer_x: (last_layer_poly.len() > 1)
+                    .then(|| last_layer_x(query >> (inner_layers.len() + 1))),
+            }
+        })
+        .collect())
+}
@@ -0,0 +1,90 @@
+use crate::chunker::{n_state_elements, StateItem};
+use crate::hint_stream::HintStream;
+use crate::treepp::*;
+
+/// A gadget with typed inputs, outputs and hints, whose contract can be checked against a
+/// reference implementation with `tests_utils::gadget::check_gadget`.
+pub trait Gadget {
+    /// Return the types of the inputs, from the deepest one to the one on the top of the stack.
+    fn inputs(&self) -> Vec<StateItem>;
+
+    /// Return the types of the outputs, from the deepest one to the one on the top of the stack.
+    fn outputs(&self) -> Vec<StateItem>;
+
+    /// Return the script of the gadget, which pulls its hints through the stream.
+    fn script(&self, stream: &mut HintStream) -> Script;
+
+    /// Return the types of the hints, in the order they are pulled, as declared by the script.
+    fn hints(&self) -> Vec<StateItem> {
+        let mut stream = HintStream::new();
+        let _ = self.script(&mut stream);
+        stream.decls().iter().map(|decl| decl.item).collect()
+    }
+
+    /// Return the number of the stack elements of the inputs.
+    fn n_input_elements(&self) -> usize {
+        n_state_elements(&self.inputs())
+    }
+
+    /// Return the number of the stack elements of the outputs.
+    fn n_output_elements(&self) -> usize {
+        n_state_elements(&self.outputs())
+    }
+
+    /// Return the number of the stack elements of the hints.
+    fn n_hint_elements(&self) -> usize {
+        n_state_elements(&self.hints())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::{StateItem, StateValue};
+    use crate::gadget::Gadget;
+    use crate::hint_stream::HintStream;
+    use crate::tests_utils::gadget::check_gadget;
+    use crate::treepp::*;
+    use rust_bitcoin_m31::{push_qm31_one, qm31_dup, qm31_equalverify, qm31_mul, qm31_rot};
+    use stwo_prover::core::fields::FieldExpOps;
+
+    // a gadget that computes the inverse of a qm31 element from a hint
+    struct Inverse;
+
+    impl Gadget for Inverse {
+        fn inputs(&self) -> Vec<StateItem> {
+            vec![StateItem::QM31]
+        }
+
+        fn outputs(&self) -> Vec<StateItem> {
+            vec![StateItem::QM31]
+        }
+
+        fn script(&self, stream: &mut HintStream) -> Script {
+            script! {
+                { stream.pull("inverse", StateItem::QM31) }
+                qm31_dup
+                qm31_rot
+                qm31_mul
+                push_qm31_one
+                qm31_equalverify
+            }
+        }
+    }
+
+    #[test]
+    fn test_gadget() {
+        assert_eq!(Inverse.n_input_elements(), 4);
+        assert_eq!(Inverse.n_output_elements(), 4);
+        assert_eq!(Inverse.n_hint_elements(), 4);
+        assert_eq!(Inverse.hints(), vec![StateItem::QM31]);
+
+        check_gadget(&Inverse, 20, |inputs, writer| {
+            let [StateValue::QM31(a)] = inputs else {
+                unreachable!()
+            };
+            let inverse = StateValue::QM31(a.inverse());
+            writer.write("inverse", inverse.clone()).unwrap();
+            vec![inverse]
+        });
+    }
+}
@@ -0,0 +1,273 @@
+use crate::chunker::{push_state, StateItem, StateValue};
+use crate::debug::label;
+use crate::treepp::*;
+use crate::utils::m31_vec_from_bottom_gadget;
+use std::fmt::{Display, Formatter};
+
+/// A hint pulled by a script, with its label and its type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HintDecl {
+    /// Label of the hint, such as "fri_commitment[3]".
+    pub label: String,
+    /// Type of the hint.
+    pub item: StateItem,
+}
+
+/// The hints pulled by a script from the bottom of the stack, in the order of the pulls.
+///
+/// Gadgets declare each hint when they emit its pull, and the values of the hints are then
+/// written in the same order through a `HintWriter`, so that the order of the hints cannot drift
+/// from the one of the pulls.
+#[derive(Clone, Debug, Default)]
+pub struct HintStream {
+    decls: Vec<HintDecl>,
+}
+
+impl HintStream {
+    /// Create an empty stream.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare a hint and return the script that pulls it, which carries its label if the labels
+    /// are enabled.
+    ///
+    /// Output: the hint
+    pub fn pull(&mut self, name: &str, item: StateItem) -> Script {
+        self.decls.push(HintDecl {
+            label: name.to_string(),
+            item,
+        });
+        script! {
+            { label(name) }
+            { m31_vec_from_bottom_gadget(item.n_elements()) }
+        }
+    }
+
+    /// Append the hints declared by another stream, pulled after the ones of this stream.
+    pub fn extend(&mut self, other: HintStream) {
+        self.decls.extend(other.decls);
+    }
+
+    /// Return the declared hints, in the order of the pulls.
+    pub fn decls(&self) -> &[HintDecl] {
+        &self.decls
+    }
+
+    /// Return the number of the stack elements of the hints.
+    pub fn n_elements(&self) -> usize {
+        self.decls.iter().map(|decl| decl.item.n_elements()).sum()
+    }
+
+    /// Return a writer for the values of the declared hints.
+    pub fn writer(&self) -> HintWriter<'_> {
+        HintWriter {
+            decls: &self.decls,
+            values: vec![],
+        }
+    }
+}
+
+/// An error when the values of the hints do not match their declaration.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HintStreamError {
+    /// The value does not match the next declared hint, or all the hints have been written.
+    UnexpectedHint {
+        /// Index of the value.
+        index: usize,
+        /// The next declared hint.
+        expected: Option<HintDecl>,
+        /// The written hint.
+        found: HintDecl,
+    },
+    /// Some declared hints have not been written.
+    MissingHints(Vec<HintDecl>),
+}
+
+impl Display for HintStreamError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HintStreamError::UnexpectedHint {
+                index,
+                expected,
+                found,
+            } => match expected {
+                Some(expected) => write!(
+                    f,
+                    "hint {} is {} ({:?}), but {} ({:?}) is declared",
+                    index, found.label, found.item, expected.label, expected.item
+                ),
+                None => write!(
+                    f,
+                    "hint {} is {} ({:?}), but no more hints are declared",
+                    index, found.label, found.item
+                ),
+            },
+            HintStreamError::MissingHints(decls) => write!(
+                f,
+                "{} declared hints are missing, starting from {}",
+                decls.len(),
+                decls[0].label
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HintStreamError {}
+
+/// A writer of the values of the hints declared by a stream, which must be written in the order
+/// of the declaration.
+#[derive(Clone, Debug)]
+pub struct HintWriter<'a> {
+    decls: &'a [HintDecl],
+    values: Vec<StateValue>,
+}
+
+impl<'a> HintWriter<'a> {
+    /// Write the value of the next declared hint, which must have the label and the type of the
+    /// declaration.
+    pub fn write(&mut self, label: &str, value: StateValue) -> Result<(), HintStreamError> {
+        let index = self.values.len();
+        let expected = self.decls.get(index);
+        if expected.map_or(true, |decl| {
+            decl.label != label || decl.item != value.item()
+        }) {
+            return Err(HintStreamError::UnexpectedHint {
+                index,
+                expected: expected.cloned(),
+                found: HintDecl {
+                    label: label.to_string(),
+                    item: value.item(),
+                },
+            });
+        }
+        self.values.push(value);
+        Ok(())
+    }
+
+    /// Check that all the declared hints have been written, and return the stack elements of the
+    /// hints, from the bottom to the top, for the witness.
+    pub fn finish(self) -> Result<Vec<Vec<u8>>, HintStreamError> {
+        if self.values.len() < self.decls.len() {
+            return Err(HintStreamError::MissingHints(
+                self.decls[self.values.len()..].to_vec(),
+            ));
+        }
+        Ok(self
+            .values
+            .iter()
+            .flat_map(StateValue::to_elements)
+            .collect())
+    }
+
+    /// Check that all the declared hints have been written, and return the script that pushes
+    /// them.
+    pub fn finish_script(self) -> Result<Script, HintStreamError> {
+        Ok(push_state(&self.finish()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::{StateItem, StateValue};
+    use crate::hint_stream::{HintDecl, HintStream, HintStreamError};
+    use crate::treepp::*;
+    use crate::utils::get_rand_qm31;
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use rust_bitcoin_m31::{qm31_equalverify, qm31_mul};
+    use stwo_prover::core::fields::m31::M31;
+    use stwo_prover::core::fields::qm31::QM31;
+    use stwo_prover::core::fields::FieldExpOps;
+
+    // a gadget that checks the hinted inverse of a qm31 element, and pulls a m31 element and raw
+    // bytes after it
+    fn gadget(stream: &mut HintStream) -> Script {
+        script! {
+            { stream.pull("inverse", StateItem::QM31) }
+            qm31_mul
+            { QM31::from_u32_unchecked(1, 0, 0, 0) }
+            qm31_equalverify
+            { stream.pull("m", StateItem::M31) }
+            { stream.pull("bytes", StateItem::Bytes) }
+            OP_CAT OP_SIZE OP_NIP
+        }
+    }
+
+    #[test]
+    fn test_hint_stream() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut stream = HintStream::new();
+        let script = gadget(&mut stream);
+        assert_eq!(
+            stream.decls(),
+            &[
+                HintDecl {
+                    label: "inverse".to_string(),
+                    item: StateItem::QM31
+                },
+                HintDecl {
+                    label: "m".to_string(),
+                    item: StateItem::M31
+                },
+                HintDecl {
+                    label: "bytes".to_string(),
+                    item: StateItem::Bytes
+                },
+            ]
+        );
+        assert_eq!(stream.n_elements(), 6);
+
+        let a = get_rand_qm31(&mut prng);
+        let m = M31::reduce(prng.next_u64());
+        let bytes = vec![0xaau8; 10];
+
+        let mut writer = stream.writer();
+        writer
+            .write("inverse", StateValue::QM31(a.inverse()))
+            .unwrap();
+        writer.write("m", StateValue::M31(m)).unwrap();
+        writer
+            .write("bytes", StateValue::Bytes(bytes.clone()))
+            .unwrap();
+
+        let exec_result = execute_script(script! {
+            { writer.clone().finish_script().unwrap() }
+            { a }
+            { script.clone() }
+            { (StateValue::M31(m).to_elements()[0].len() + bytes.len()) as u32 }
+            OP_EQUAL
+        });
+        assert!(exec_result.success);
+
+        // the hints must be written in the order of the declaration, with the declared types
+        let mut writer = stream.writer();
+        assert!(matches!(
+            writer.write("m", StateValue::M31(m)),
+            Err(HintStreamError::UnexpectedHint { index: 0, .. })
+        ));
+        assert!(matches!(
+            writer.write("inverse", StateValue::M31(m)),
+            Err(HintStreamError::UnexpectedHint { index: 0, .. })
+        ));
+        writer
+            .write("inverse", StateValue::QM31(a.inverse()))
+            .unwrap();
+        assert_eq!(
+            writer.clone().finish(),
+            Err(HintStreamError::MissingHints(stream.decls()[1..].to_vec()))
+        );
+
+        writer.write("m", StateValue::M31(m)).unwrap();
+        writer.write("bytes", StateValue::Bytes(bytes)).unwrap();
+        assert!(matches!(
+            writer.write("extra", StateValue::M31(m)),
+            Err(HintStreamError::UnexpectedHint {
+                index: 3,
+                expected: None,
+                ..
+            })
+        ));
+    }
+}
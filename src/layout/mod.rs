@@ -0,0 +1,563 @@
+use crate::chunker::StateItem;
+use crate::hint_stream::HintStream;
+use crate::treepp::*;
+use rust_bitcoin_m31::{qm31_copy, qm31_roll};
+use std::fmt::{Display, Formatter};
+
+/// A named slot of the stack, which holds an item of the given type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Slot {
+    /// Name of the slot.
+    pub name: String,
+    /// Type of the item in the slot.
+    pub item: StateItem,
+}
+
+impl Slot {
+    /// Create a slot.
+    pub fn new(name: &str, item: StateItem) -> Self {
+        Self {
+            name: name.to_string(),
+            item,
+        }
+    }
+}
+
+/// An input of a gadget, taken from a named slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Input<'a> {
+    /// The item is copied, and the slot stays.
+    Copy(&'a str),
+    /// The item is moved, and the slot is consumed.
+    Move(&'a str),
+}
+
+/// An error when the layout of the stack does not fit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// No slot has the name, or the slot has been moved into the inputs of the gadget.
+    UnknownSlot(String),
+    /// A slot with the name already exists.
+    DuplicateSlot(String),
+    /// The slot does not hold the type that the gadget takes.
+    TypeMismatch {
+        /// Name of the slot.
+        name: String,
+        /// The type that the gadget takes.
+        expected: StateItem,
+        /// The type of the slot.
+        found: StateItem,
+    },
+    /// The stack does not have the expected slots.
+    LayoutMismatch {
+        /// The expected slots, from the bottom to the top.
+        expected: Vec<Slot>,
+        /// The slots of the stack, from the bottom to the top.
+        found: Vec<Slot>,
+    },
+}
+
+impl Display for LayoutError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LayoutError::UnknownSlot(name) => write!(f, "no slot named {}", name),
+            LayoutError::DuplicateSlot(name) => write!(f, "the slot {} already exists", name),
+            LayoutError::TypeMismatch {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "the slot {} holds {:?}, but {:?} is expected",
+                name, found, expected
+            ),
+            LayoutError::LayoutMismatch { expected, found } => {
+                write!(
+                    f,
+                    "the stack has {:?}, but {:?} is expected",
+                    found, expected
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for LayoutError {}
+
+/// A builder of a script that tracks the named slots of the stack, so that the inputs of each
+/// gadget are brought to the top with the right `OP_PICK`, `OP_ROLL`, `qm31_copy` or `qm31_roll`,
+/// instead of hand-counted depths.
+#[derive(Clone, Debug, Default)]
+pub struct LayoutBuilder {
+    slots: Vec<Slot>,
+    script: Vec<u8>,
+}
+
+impl LayoutBuilder {
+    /// Start from a stack with the given slots, from the bottom to the top.
+    pub fn new(slots: &[(&str, StateItem)]) -> Result<Self, LayoutError> {
+        let mut builder = Self::default();
+        for (name, item) in slots.iter() {
+            builder.add_slot(name, *item)?;
+        }
+        Ok(builder)
+    }
+
+    /// Return the slots of the stack, from the bottom to the top.
+    pub fn slots(&self) -> &[Slot] {
+        &self.slots
+    }
+
+    /// Return the types of the slots of the stack, from the bottom to the top.
+    pub fn items(&self) -> Vec<StateItem> {
+        self.slots.iter().map(|slot| slot.item).collect()
+    }
+
+    /// Return the script built so far.
+    pub fn script(&self) -> Script {
+        Script::from_bytes(self.script.clone())
+    }
+
+    /// Take the script built so far and continue from the same slots with an empty script, such
+    /// as at the boundary of two stages.
+    pub fn take_script(&mut self) -> Script {
+        Script::from_bytes(std::mem::take(&mut self.script))
+    }
+
+    /// Finish the builder and return the script.
+    pub fn finish(self) -> Script {
+        Script::from_bytes(self.script)
+    }
+
+    /// Pull an item from the hints into a new slot on the top of the stack, and declare it in the
+    /// stream under the name of the slot.
+    pub fn pull_hint(
+        &mut self,
+        stream: &mut HintStream,
+        name: &str,
+        item: StateItem,
+    ) -> Result<(), LayoutError> {
+        self.add_slot(name, item)?;
+        self.emit(stream.pull(name, item));
+        Ok(())
+    }
+
+    /// Push an item, such as a constant, with the script into a new slot on the top of the stack.
+    pub fn push(&mut self, name: &str, item: StateItem, script: Script) -> Result<(), LayoutError> {
+        self.add_slot(name, item)?;
+        self.emit(script);
+        Ok(())
+    }
+
+    /// Copy the item of a slot into a new slot on the top of the stack.
+    pub fn copy(&mut self, name: &str, new_name: &str) -> Result<(), LayoutError> {
+        let index = self.find(name, self.slots.len())?;
+        let item = self.slots[index].item;
+        let depth = self.depth_of(index);
+        self.add_slot(new_name, item)?;
+        self.emit(copy_script(item, depth));
+        Ok(())
+    }
+
+    /// Move a slot to the top of the stack.
+    pub fn roll(&mut self, name: &str) -> Result<(), LayoutError> {
+        let index = self.find(name, self.slots.len())?;
+        self.roll_index(index);
+        Ok(())
+    }
+
+    /// Drop a slot.
+    pub fn drop(&mut self, name: &str) -> Result<(), LayoutError> {
+        self.roll(name)?;
+        let slot = self.slots.pop().unwrap();
+        let n = slot.item.n_elements();
+        self.emit(script! {
+            for _ in 0..n / 2 {
+                OP_2DROP
+            }
+            if n % 2 == 1 {
+                OP_DROP
+            }
+        });
+        Ok(())
+    }
+
+    /// Rename a slot.
+    pub fn rename(&mut self, name: &str, new_name: &str) -> Result<(), LayoutError> {
+        let index = self.find(name, self.slots.len())?;
+        if self.slots.iter().any(|slot| slot.name == new_name) {
+            return Err(LayoutError::DuplicateSlot(new_name.to_string()));
+        }
+        self.slots[index].name = new_name.to_string();
+        Ok(())
+    }
+
+    /// Run a gadget: bring its inputs to the top of the stack in the given order, check their
+    /// types against the ones that the gadget takes, run its script, and put its outputs in new
+    /// slots.
+    ///
+    /// The first inputs that are moved from the top of the stack, where they already are in the
+    /// given order, stay in place. Nothing is emitted if the layout does not fit.
+    pub fn call(
+        &mut self,
+        inputs: &[(Input, StateItem)],
+        script: Script,
+        outputs: &[(&str, StateItem)],
+    ) -> Result<(), LayoutError> {
+        // check the layout on a copy, so that a failed call leaves the builder as it was
+        let mut builder = Self {
+            slots: self.slots.clone(),
+            script: vec![],
+        };
+
+        // the longest prefix of the inputs that is moved from the top of the stack in order
+        let n_in_place = (0..=inputs.len().min(builder.slots.len()))
+            .rev()
+            .find(|&k| {
+                let top = &builder.slots[builder.slots.len() - k..];
+                inputs[..k]
+                    .iter()
+                    .zip(top.iter())
+                    .all(|((input, _), slot)| *input == Input::Move(&slot.name))
+            })
+            .unwrap_or(0);
+
+        for (i, (input, expected)) in inputs.iter().enumerate() {
+            // the inputs already brought to the top cannot be taken again
+            let n_candidates = builder.slots.len() - i;
+            let (name, index) = match input {
+                Input::Copy(name) | Input::Move(name) if i < n_in_place => {
+                    (name, builder.slots.len() - n_in_place + i)
+                }
+                Input::Copy(name) | Input::Move(name) => (name, builder.find(name, n_candidates)?),
+            };
+
+            let found = builder.slots[index].item;
+            if found != *expected {
+                return Err(LayoutError::TypeMismatch {
+                    name: name.to_string(),
+                    expected: *expected,
+                    found,
+                });
+            }
+
+            match input {
+                _ if i < n_in_place => {}
+                Input::Copy(_) => {
+                    let depth = builder.depth_of(index);
+                    builder.slots.push(Slot::new("", found));
+                    builder.emit(copy_script(found, depth));
+                }
+                Input::Move(_) => builder.roll_index(index),
+            }
+        }
+
+        builder.slots.truncate(builder.slots.len() - inputs.len());
+        for (name, item) in outputs.iter() {
+            builder.add_slot(name, *item)?;
+        }
+        builder.emit(script);
+
+        self.slots = builder.slots;
+        self.script.extend_from_slice(&builder.script);
+        Ok(())
+    }
+
+    /// Check that the stack has exactly the given slots, from the bottom to the top.
+    pub fn check(&self, expected: &[(&str, StateItem)]) -> Result<(), LayoutError> {
+        let expected = expected
+            .iter()
+            .map(|(name, item)| Slot::new(name, *item))
+            .collect::<Vec<_>>();
+        if expected != self.slots {
+            return Err(LayoutError::LayoutMismatch {
+                expected,
+                found: self.slots.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    fn add_slot(&mut self, name: &str, item: StateItem) -> Result<(), LayoutError> {
+        if self.slots.iter().any(|slot| slot.name == name) {
+            return Err(LayoutError::DuplicateSlot(name.to_string()));
+        }
+        self.slots.push(Slot::new(name, item));
+        Ok(())
+    }
+
+    // find the slot among the bottom n slots
+    fn find(&self, name: &str, n: usize) -> Result<usize, LayoutError> {
+        self.slots[..n]
+            .iter()
+            .position(|slot| slot.name == name)
+            .ok_or_else(|| LayoutError::UnknownSlot(name.to_string()))
+    }
+
+    // the number of the stack elements above the slot
+    fn depth_of(&self, index: usize) -> usize {
+        self.slots[index + 1..]
+            .iter()
+            .map(|slot| slot.item.n_elements())
+            .sum()
+    }
+
+    fn roll_index(&mut self, index: usize) {
+        let depth = self.depth_of(index);
+        let slot = self.slots.remove(index);
+        self.emit(roll_script(slot.item, depth));
+        self.slots.push(slot);
+    }
+
+    fn emit(&mut self, script: Script) {
+        self.script.extend_from_slice(script.as_bytes());
+    }
+}
+
+/// Copy an item whose top element has `depth` elements above it to the top of the stack.
+fn copy_script(item: StateItem, depth: usize) -> Script {
+    let n = item.n_elements();
+    match (n, depth) {
+        (1, 0) => script! { OP_DUP },
+        (1, 1) => script! { OP_OVER },
+        (4, _) if depth % 4 == 0 => qm31_copy(depth / 4),
+        _ => script! {
+            for _ in 0..n {
+                { depth + n - 1 } OP_PICK
+            }
+        },
+    }
+}
+
+/// Move an item whose top element has `depth` elements above it to the top of the stack.
+fn roll_script(item: StateItem, depth: usize) -> Script {
+    let n = item.n_elements();
+    match (n, depth) {
+        (_, 0) => script! {},
+        (1, 1) => script! { OP_SWAP },
+        (1, 2) => script! { OP_ROT },
+        (4, _) if depth % 4 == 0 => qm31_roll(depth / 4),
+        _ => script! {
+            for _ in 0..n {
+                { depth + n - 1 } OP_ROLL
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::{push_state, StateItem, StateValue};
+    use crate::circle::CirclePointGadget;
+    use crate::hint_stream::HintStream;
+    use crate::layout::{Input, LayoutBuilder, LayoutError};
+    use crate::treepp::*;
+    use crate::utils::get_rand_qm31;
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use rust_bitcoin_m31::{qm31_add, qm31_equalverify, qm31_mul};
+    use stwo_prover::core::circle::CirclePoint;
+    use stwo_prover::core::fields::m31::M31;
+    use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+
+    #[test]
+    fn test_layout_builder() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let a = get_rand_qm31(&mut prng);
+        let b = get_rand_qm31(&mut prng);
+        let m = M31::reduce(prng.next_u64());
+        let mut digest = [0u8; 32];
+        prng.fill_bytes(&mut digest);
+        let digest = BWSSha256Hash::from(digest.to_vec());
+        let p = CirclePoint {
+            x: get_rand_qm31(&mut prng),
+            y: get_rand_qm31(&mut prng),
+        };
+        let q = CirclePoint {
+            x: get_rand_qm31(&mut prng),
+            y: get_rand_qm31(&mut prng),
+        };
+
+        let mut builder = LayoutBuilder::new(&[
+            ("a", StateItem::QM31),
+            ("digest", StateItem::Digest),
+            ("m", StateItem::M31),
+            ("p", StateItem::CirclePoint),
+        ])
+        .unwrap();
+        let mut stream = HintStream::new();
+        builder
+            .pull_hint(&mut stream, "b", StateItem::QM31)
+            .unwrap();
+        builder
+            .push("q", StateItem::CirclePoint, script! { { q } })
+            .unwrap();
+
+        // a * b + a, where a is not aligned with the qm31 elements above it
+        builder
+            .call(
+                &[
+                    (Input::Copy("a"), StateItem::QM31),
+                    (Input::Move("b"), StateItem::QM31),
+                ],
+                script! { qm31_mul },
+                &[("ab", StateItem::QM31)],
+            )
+            .unwrap();
+        builder
+            .call(
+                &[
+                    (Input::Move("ab"), StateItem::QM31),
+                    (Input::Move("a"), StateItem::QM31),
+                ],
+                script! { qm31_add },
+                &[("res", StateItem::QM31)],
+            )
+            .unwrap();
+
+        // p + q
+        builder
+            .call(
+                &[
+                    (Input::Move("p"), StateItem::CirclePoint),
+                    (Input::Move("q"), StateItem::CirclePoint),
+                ],
+                CirclePointGadget::add(),
+                &[("sum", StateItem::CirclePoint)],
+            )
+            .unwrap();
+
+        builder.copy("m", "m2").unwrap();
+        builder.drop("digest").unwrap();
+        builder.roll("m").unwrap();
+        builder.rename("m2", "m_copy").unwrap();
+
+        builder
+            .check(&[
+                ("res", StateItem::QM31),
+                ("sum", StateItem::CirclePoint),
+                ("m_copy", StateItem::M31),
+                ("m", StateItem::M31),
+            ])
+            .unwrap();
+
+        let mut writer = stream.writer();
+        writer.write("b", StateValue::QM31(b)).unwrap();
+
+        let script = script! {
+            { writer.finish_script().unwrap() }
+            { a }
+            { StateValue::Digest(digest) }
+            { m }
+            { p }
+            { builder.finish() }
+            { m } OP_EQUALVERIFY
+            { m } OP_EQUALVERIFY
+            { (p + q).y }
+            { qm31_equalverify }
+            { (p + q).x }
+            { qm31_equalverify }
+            { a * b + a }
+            { qm31_equalverify }
+            OP_TRUE
+        };
+        let exec_result = execute_script(script);
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_layout_errors() {
+        let mut builder =
+            LayoutBuilder::new(&[("a", StateItem::QM31), ("b", StateItem::M31)]).unwrap();
+
+        assert_eq!(
+            builder.call(&[(Input::Move("b"), StateItem::QM31)], script! {}, &[]),
+            Err(LayoutError::TypeMismatch {
+                name: "b".to_string(),
+                expected: StateItem::QM31,
+                found: StateItem::M31,
+            })
+        );
+        assert_eq!(
+            builder.call(
+                &[
+                    (Input::Move("a"), StateItem::QM31),
+                    (Input::Copy("a"), StateItem::QM31),
+                ],
+                script! {},
+                &[]
+            ),
+            Err(LayoutError::UnknownSlot("a".to_string()))
+        );
+        assert_eq!(
+            builder.copy("a", "b"),
+            Err(LayoutError::DuplicateSlot("b".to_string()))
+        );
+        assert!(matches!(
+            builder.check(&[("b", StateItem::M31), ("a", StateItem::QM31)]),
+            Err(LayoutError::LayoutMismatch { .. })
+        ));
+
+        // the failed calls emit nothing
+        assert!(builder.script().is_empty());
+        builder
+            .check(&[("a", StateItem::QM31), ("b", StateItem::M31)])
+            .unwrap();
+
+        let exec_result = execute_script(script! {
+            { push_state(&[vec![1], vec![2], vec![3], vec![4], vec![5]]) }
+            { builder.finish() }
+            5 OP_EQUALVERIFY
+            OP_2DROP OP_2DROP
+            OP_TRUE
+        });
+        assert!(exec_result.success);
+    }
+
+    #[test]
+    fn test_inputs_in_place() {
+        let mut builder = LayoutBuilder::new(&[
+            ("a", StateItem::M31),
+            ("b", StateItem::M31),
+            ("c", StateItem::M31),
+        ])
+        .unwrap();
+
+        // b and c are already on the top in order
+        builder
+            .call(
+                &[
+                    (Input::Move("b"), StateItem::M31),
+                    (Input::Move("c"), StateItem::M31),
+                ],
+                script! { OP_ADD },
+                &[("d", StateItem::M31)],
+            )
+            .unwrap();
+        let add = builder.take_script();
+        assert_eq!(add, script! { OP_ADD });
+
+        // d stays in place, and a is brought above it
+        builder
+            .call(
+                &[
+                    (Input::Move("d"), StateItem::M31),
+                    (Input::Move("a"), StateItem::M31),
+                ],
+                script! { OP_SUB },
+                &[("e", StateItem::M31)],
+            )
+            .unwrap();
+        let sub = builder.finish();
+        assert_eq!(sub, script! { OP_SWAP OP_SUB });
+
+        let exec_result = execute_script(script! {
+            1 2 3
+            { add }
+            { sub }
+            4 OP_EQUAL
+        });
+        assert!(exec_result.success);
+    }
+}
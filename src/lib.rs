@@ -12,28 +12,58 @@ use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
 
 /// Module for AIR-related features.
 pub mod air;
+/// Module for the static analysis of the stack depths of scripts.
+pub mod analyzer;
 /// Module for absorbing and squeezing of the channel.
 pub mod channel;
+/// Module for splitting a script into stages that hand off their live stacks.
+pub mod chunker;
 /// Module for the circle curve over the qm31 field.
 pub mod circle;
 /// Module for constraints over the circle curve
 pub mod constraints;
+/// Module for the covenant that runs the stages of a script in a chain of transactions.
+pub mod covenant;
+/// Module for labeling the hints pulled by the scripts and tracing the failures to them.
+pub mod debug;
+/// Module for the disprove leaves of the assertions of an optimistic verifier.
+pub mod disprove;
+/// Module for the binary encoding of the hints.
+pub mod encoding;
 /// Module for Fibonacci end-to-end test.
 pub mod fibonacci;
 /// Module for FRI.
 pub mod fri;
+/// Module for the gadgets with typed inputs, outputs and hints.
+pub mod gadget;
+/// Module for declaring the hints pulled by the scripts.
+pub mod hint_stream;
+/// Module for building scripts over named and typed slots of the stack.
+pub mod layout;
 /// Module for the Merkle tree.
 pub mod merkle_tree;
 /// Module for out-of-domain sampling.
 pub mod oods;
+/// Module for the peephole optimization of the scripts.
+pub mod optimizer;
 /// Module for PoW.
 pub mod pow;
 /// Module for the precomputed data Merkle tree.
 pub mod precomputed_merkle_tree;
+/// Module for the DEEP quotients.
+pub mod quotients;
+/// Module for the standardness and consensus checks of the leaves and their witnesses.
+pub mod standardness;
+/// Module for the taproot outputs locking coins to tapscript leaves.
+pub mod taproot;
 /// Module for test utils.
 pub mod tests_utils;
 /// Module for utility functions.
 pub mod utils;
+/// Module for the verifier of any AIR that can be verified in Bitcoin script.
+pub mod verifier;
+
+pub use verifier::HintGenerationError;
 
 pub(crate) mod treepp {
     pub use bitcoin_script::{define_pushable, script};
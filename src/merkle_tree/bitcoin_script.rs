@@ -1,16 +1,25 @@
+use crate::chunker::StateItem;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
-use crate::utils::{
-    dup_m31_vec_gadget, hash_m31_vec_gadget, limb_to_be_bits_toaltstack, m31_vec_from_bottom_gadget,
-};
+use crate::utils::{dup_m31_vec_gadget, hash_m31_vec_gadget, limb_to_be_bits_toaltstack};
+use rust_bitcoin_m31::{qm31_over, qm31_swap};
 
 /// Gadget for verifying a regular binary Merkle tree.
 pub struct MerkleTreeTwinGadget;
 
 impl MerkleTreeTwinGadget {
-    pub(crate) fn query_and_verify_internal(len: usize, logn: usize) -> Script {
+    pub(crate) fn query_and_verify_internal(
+        stream: &mut HintStream,
+        label: &str,
+        len: usize,
+        logn: usize,
+    ) -> Script {
         script! {
             // left
-            { m31_vec_from_bottom_gadget(len) }
+            for _ in 0..len {
+                { stream.pull(label, StateItem::M31) }
+            }
 
             // duplicate the left
             { dup_m31_vec_gadget(len) }
@@ -21,7 +30,9 @@ impl MerkleTreeTwinGadget {
             OP_TOALTSTACK
 
             // right
-            { m31_vec_from_bottom_gadget(len) }
+            for _ in 0..len {
+                { stream.pull(label, StateItem::M31) }
+            }
 
             // duplicate the right
             { dup_m31_vec_gadget(len) }
@@ -35,7 +46,7 @@ impl MerkleTreeTwinGadget {
             OP_SWAP OP_CAT OP_SHA256
 
             for _ in 0..(logn - 1) {
-                OP_DEPTH OP_1SUB OP_ROLL
+                { stream.pull(label, StateItem::Digest) }
                 OP_FROMALTSTACK OP_IF OP_SWAP OP_ENDIF
                 OP_CAT OP_SHA256
             }
@@ -45,7 +56,9 @@ impl MerkleTreeTwinGadget {
         }
     }
 
-    /// Query and verify using the Merkle path as a hint.
+    /// Query and verify using the Merkle path as a hint, which is pulled through the stream under
+    /// the label.
+    ///
     /// input:
     ///   root_hash
     ///   pos
@@ -53,34 +66,152 @@ impl MerkleTreeTwinGadget {
     /// output:
     ///   vl (the element on the left)
     ///   vr (the element on the right)
-    pub fn query_and_verify(len: usize, logn: usize) -> Script {
+    pub fn query_and_verify(
+        stream: &mut HintStream,
+        label: &str,
+        len: usize,
+        logn: usize,
+    ) -> Script {
         script! {
             // push the root hash to the altstack, first
             OP_SWAP OP_TOALTSTACK
             { limb_to_be_bits_toaltstack(logn as u32) }
             OP_FROMALTSTACK 0 OP_EQUALVERIFY // enforce the lowest bit is zero and drop it
-            { Self::query_and_verify_internal(len, logn) }
+            { Self::query_and_verify_internal(stream, label, len, logn) }
+        }
+    }
+
+    /// Query and verify a known qm31 leaf using its sibling and the Merkle path as a hint, which
+    /// is pulled through the stream under the label.
+    ///
+    /// input:
+    ///   v (qm31)
+    ///   root_hash
+    ///   pos
+    ///
+    /// output:
+    ///   vl (the element on the left)
+    ///   vr (the element on the right)
+    pub fn query_and_verify_sibling(stream: &mut HintStream, label: &str, logn: usize) -> Script {
+        script! {
+            // push the root hash to the altstack, first
+            OP_SWAP OP_TOALTSTACK
+            { limb_to_be_bits_toaltstack(logn as u32) }
+
+            // pull the sibling and put the two elements in order
+            { stream.pull(label, StateItem::QM31) }
+            OP_FROMALTSTACK OP_IF qm31_swap OP_ENDIF
+
+            // duplicate the two elements
+            qm31_over
+            qm31_over
+
+            // hash the right (with its limbs ordered as a leaf) and keep the hash in the altstack
+            OP_SWAP OP_2SWAP OP_SWAP
+            { hash_m31_vec_gadget(4) }
+            OP_SHA256
+            OP_TOALTSTACK
+
+            // hash the left
+            OP_SWAP OP_2SWAP OP_SWAP
+            { hash_m31_vec_gadget(4) }
+            OP_SHA256
+
+            // put the right hash out and merge into the parent hash
+            OP_FROMALTSTACK
+            OP_CAT OP_SHA256
+
+            for _ in 0..(logn - 1) {
+                { stream.pull(label, StateItem::Digest) }
+                OP_FROMALTSTACK OP_IF OP_SWAP OP_ENDIF
+                OP_CAT OP_SHA256
+            }
+
+            OP_FROMALTSTACK
+            OP_EQUALVERIFY
         }
     }
 }
 
+/// `MerkleTreeTwinGadget::query_and_verify` of a tree as a `Gadget`.
+pub struct MerkleTreeTwinQuery {
+    /// Number of the m31 elements of each leaf.
+    pub len: usize,
+    /// Log size of the number of the leaves.
+    pub logn: usize,
+}
+
+impl Gadget for MerkleTreeTwinQuery {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest, StateItem::M31]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::M31; 2 * self.len]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        MerkleTreeTwinGadget::query_and_verify(stream, "proof", self.len, self.logn)
+    }
+}
+
+/// `MerkleTreeTwinGadget::query_and_verify_sibling` of a tree as a `Gadget`.
+pub struct MerkleTreeSiblingQuery {
+    /// Log size of the number of the leaves.
+    pub logn: usize,
+}
+
+impl Gadget for MerkleTreeSiblingQuery {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31, StateItem::Digest, StateItem::M31]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31, StateItem::QM31]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        MerkleTreeTwinGadget::query_and_verify_sibling(stream, "proof", self.logn)
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use crate::chunker::StateValue;
+    use crate::hint_stream::HintStream;
+    use crate::tests_utils::gadget::check_gadget_with_sampler;
     use crate::treepp::*;
     use crate::utils::get_rand_qm31;
     use crate::{
-        merkle_tree::{MerkleTree, MerkleTreeTwinGadget},
+        merkle_tree::{
+            MerkleTree, MerkleTreeSiblingQuery, MerkleTreeTwinGadget, MerkleTreeTwinQuery,
+        },
         tests_utils::report::report_bitcoin_script_size,
     };
-    use rand::{Rng, SeedableRng};
+    use itertools::Itertools;
+    use rand::{Rng, RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
+    use rust_bitcoin_m31::qm31_equalverify;
+    use stwo_prover::core::fields::m31::M31;
+    use stwo_prover::core::fields::qm31::QM31;
+
+    // a tree of random leaves of `len` m31 elements
+    fn random_tree<R: RngCore>(prng: &mut R, len: usize, logn: usize) -> MerkleTree {
+        MerkleTree::new(
+            (0..1 << logn)
+                .map(|_| (0..len).map(|_| M31::reduce(prng.next_u64())).collect())
+                .collect(),
+        )
+    }
 
     #[test]
     fn test_merkle_tree_verify() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
         for logn in 12..=20 {
-            let verify_script = MerkleTreeTwinGadget::query_and_verify(4, logn);
+            let mut stream = HintStream::new();
+            let verify_script =
+                MerkleTreeTwinGadget::query_and_verify(&mut stream, "proof", 4, logn);
 
             report_bitcoin_script_size(
                 "MerkleTreeTwin",
@@ -110,8 +241,11 @@ mod test {
                 pos as usize
             ));
 
+            let mut writer = stream.writer();
+            proof.write(&mut writer, "proof").unwrap();
+
             let script = script! {
-                { proof }
+                { writer.finish_script().unwrap() }
                 { merkle_tree.root_hash }
                 { pos }
                 { verify_script.clone() }
@@ -130,4 +264,133 @@ mod test {
             assert!(exec_result.success);
         }
     }
+
+    #[test]
+    fn test_merkle_tree_verify_sibling() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for logn in 12..=20 {
+            let mut stream = HintStream::new();
+            let verify_script =
+                MerkleTreeTwinGadget::query_and_verify_sibling(&mut stream, "proof", logn);
+
+            report_bitcoin_script_size(
+                "MerkleTreeTwin",
+                format!("verify_sibling(2^{})", logn).as_str(),
+                verify_script.len(),
+            );
+
+            let mut last_layer = vec![];
+            for _ in 0..(1 << logn) {
+                let a = get_rand_qm31(&mut prng);
+                last_layer.push(a.to_m31_array().to_vec());
+            }
+
+            let merkle_tree = MerkleTree::new(last_layer.clone());
+
+            let mut pos: u32 = prng.gen();
+            pos &= (1 << logn) - 1;
+
+            let leaf = QM31::from_m31_array(last_layer[pos as usize].clone().try_into().unwrap());
+            let proof = merkle_tree.query_sibling(pos as usize);
+            assert!(MerkleTree::verify_sibling(
+                &merkle_tree.root_hash,
+                logn,
+                &proof,
+                leaf,
+                pos as usize
+            ));
+
+            let left =
+                QM31::from_m31_array(last_layer[(pos & !1) as usize].clone().try_into().unwrap());
+            let right =
+                QM31::from_m31_array(last_layer[(pos | 1) as usize].clone().try_into().unwrap());
+
+            let mut writer = stream.writer();
+            proof.write(&mut writer, "proof").unwrap();
+
+            let script = script! {
+                { writer.finish_script().unwrap() }
+                { leaf }
+                { merkle_tree.root_hash }
+                { pos }
+                { verify_script.clone() }
+                { right }
+                qm31_equalverify
+                { left }
+                qm31_equalverify
+                OP_TRUE
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_gadgets() {
+        for (len, logn) in [(1, 5), (4, 8)] {
+            check_gadget_with_sampler(&MerkleTreeTwinQuery { len, logn }, 5, |prng, writer| {
+                let merkle_tree = random_tree(prng, len, logn);
+                let pos = prng.gen_range(0..1 << logn) & !1;
+
+                let proof = merkle_tree.query(pos);
+                assert!(MerkleTree::verify_twin(
+                    &merkle_tree.root_hash,
+                    logn,
+                    &proof,
+                    pos
+                ));
+
+                proof.write(writer, "proof").unwrap();
+
+                (
+                    vec![
+                        StateValue::Digest(merkle_tree.root_hash),
+                        StateValue::M31(M31::from(pos as u32)),
+                    ],
+                    proof
+                        .left
+                        .iter()
+                        .chain(proof.right.iter())
+                        .map(|&v| StateValue::M31(v))
+                        .collect_vec(),
+                )
+            });
+        }
+
+        for logn in [5, 8] {
+            check_gadget_with_sampler(&MerkleTreeSiblingQuery { logn }, 5, |prng, writer| {
+                let merkle_tree = random_tree(prng, 4, logn);
+                let pos = prng.gen_range(0..1 << logn);
+
+                let leaf_at = |i: usize| {
+                    QM31::from_m31_array(merkle_tree.leaf_layer[i].clone().try_into().unwrap())
+                };
+                let leaf = leaf_at(pos);
+                let proof = merkle_tree.query_sibling(pos);
+                assert!(MerkleTree::verify_sibling(
+                    &merkle_tree.root_hash,
+                    logn,
+                    &proof,
+                    leaf,
+                    pos
+                ));
+
+                proof.write(writer, "proof").unwrap();
+
+                (
+                    vec![
+                        StateValue::QM31(leaf),
+                        StateValue::Digest(merkle_tree.root_hash),
+                        StateValue::M31(M31::from(pos as u32)),
+                    ],
+                    vec![
+                        StateValue::QM31(leaf_at(pos & !1)),
+                        StateValue::QM31(leaf_at(pos | 1)),
+                    ],
+                )
+            });
+        }
+    }
 }
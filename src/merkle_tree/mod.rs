@@ -1,10 +1,14 @@
+use crate::chunker::StateValue;
+use crate::hint_stream::{HintStreamError, HintWriter};
+use itertools::Itertools;
+use std::collections::BTreeMap;
 use stwo_prover::core::fields::m31::M31;
+use stwo_prover::core::fields::qm31::QM31;
 use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
 use stwo_prover::core::vcs::bws_sha256_merkle::BWSSha256MerkleHasher;
 use stwo_prover::core::vcs::ops::MerkleHasher;
 
 mod bitcoin_script;
-use crate::treepp::pushable::{Builder, Pushable};
 pub use bitcoin_script::*;
 
 /// A Merkle tree.
@@ -70,6 +74,25 @@ impl MerkleTree {
         merkle_tree_proof
     }
 
+    /// Query the Merkle tree on a leaf whose value is known to the verifier and generate a
+    /// proof consisting of its sibling leaf and the Merkle path.
+    pub fn query_sibling(&self, pos: usize) -> MerkleTreeSiblingProof {
+        let logn = self.intermediate_layers.len();
+
+        let mut merkle_tree_proof = MerkleTreeSiblingProof {
+            twin: QM31::from_m31_array(self.leaf_layer[pos ^ 1].clone().try_into().unwrap()),
+            ..Default::default()
+        };
+
+        for i in 0..(logn - 1) {
+            merkle_tree_proof
+                .siblings
+                .push(self.intermediate_layers[i][(pos >> (i + 1)) ^ 1]);
+        }
+
+        merkle_tree_proof
+    }
+
     /// Verify a Merkle tree proof.
     pub fn verify_twin(
         root_hash: &BWSSha256Hash,
@@ -99,6 +122,138 @@ impl MerkleTree {
 
         leaf_hash == *root_hash
     }
+
+    /// Verify a Merkle tree sibling proof for a known leaf.
+    pub fn verify_sibling(
+        root_hash: &BWSSha256Hash,
+        logn: usize,
+        proof: &MerkleTreeSiblingProof,
+        leaf: QM31,
+        query: usize,
+    ) -> bool {
+        let (left, right) = if query & 1 == 0 {
+            (leaf, proof.twin)
+        } else {
+            (proof.twin, leaf)
+        };
+
+        Self::verify_twin(
+            root_hash,
+            logn,
+            &MerkleTreeTwinProof {
+                left: left.to_m31_array().to_vec(),
+                right: right.to_m31_array().to_vec(),
+                siblings: proof.siblings.clone(),
+            },
+            query & !1,
+        )
+    }
+}
+
+/// A sparse Merkle tree reconstructed from a decommitment, in which all the columns have the same size.
+pub struct SparseMerkleTree {
+    /// Queried leaves, consisting of m31 elements.
+    pub leaves: BTreeMap<usize, Vec<M31>>,
+    /// Known nodes of each layer, starting from the leaf hashes and ending with the root hash.
+    pub layers: Vec<BTreeMap<usize, BWSSha256Hash>>,
+}
+
+impl SparseMerkleTree {
+    /// Reconstruct the known nodes from the queried leaves and the hash witness of the decommitment.
+    ///
+    /// The hash witness is consumed in the same order as stwo's Merkle verifier, i.e., layer by layer
+    /// from the leaves, and for each parent node, the left child before the right child.
+    pub fn from_hash_witness(
+        logn: usize,
+        leaves: &BTreeMap<usize, Vec<M31>>,
+        hash_witness: &[BWSSha256Hash],
+    ) -> Option<Self> {
+        let mut hash_witness = hash_witness.iter();
+
+        let mut layers = vec![leaves
+            .iter()
+            .map(|(&i, v)| (i, BWSSha256MerkleHasher::hash_node(None, v)))
+            .collect::<BTreeMap<usize, BWSSha256Hash>>()];
+
+        for _ in 0..logn {
+            let cur_layer = layers.last_mut().unwrap();
+
+            let parents = cur_layer.keys().map(|i| i >> 1).dedup().collect_vec();
+
+            let mut parent_layer = BTreeMap::new();
+            for parent in parents {
+                for child in [parent << 1, (parent << 1) | 1] {
+                    if !cur_layer.contains_key(&child) {
+                        cur_layer.insert(child, *hash_witness.next()?);
+                    }
+                }
+
+                parent_layer.insert(
+                    parent,
+                    BWSSha256MerkleHasher::hash_node(
+                        Some((cur_layer[&(parent << 1)], cur_layer[&((parent << 1) | 1)])),
+                        &[],
+                    ),
+                );
+            }
+
+            layers.push(parent_layer);
+        }
+
+        if hash_witness.next().is_some() {
+            return None;
+        }
+
+        Some(Self {
+            leaves: leaves.clone(),
+            layers,
+        })
+    }
+
+    /// Reconstruct the known nodes from the queried columns, whose values are at the sorted positions.
+    pub fn from_queried_columns(
+        logn: usize,
+        positions: &[usize],
+        columns: &[Vec<M31>],
+        hash_witness: &[BWSSha256Hash],
+    ) -> Option<Self> {
+        let leaves = positions
+            .iter()
+            .enumerate()
+            .map(|(row, &pos)| {
+                columns
+                    .iter()
+                    .map(|column| column.get(row).copied())
+                    .collect::<Option<Vec<_>>>()
+                    .map(|leaf| (pos, leaf))
+            })
+            .collect::<Option<_>>()?;
+        Self::from_hash_witness(logn, &leaves, hash_witness)
+    }
+
+    /// Generate a proof for a pair of queried leaves.
+    pub fn query(&self, pos: usize) -> MerkleTreeTwinProof {
+        assert_eq!(pos & 1, 0);
+
+        MerkleTreeTwinProof {
+            left: self.leaves[&pos].clone(),
+            right: self.leaves[&(pos | 1)].clone(),
+            siblings: self.siblings(pos),
+        }
+    }
+
+    /// Return the root hash.
+    pub fn root_hash(&self) -> BWSSha256Hash {
+        self.layers.last().unwrap()[&0]
+    }
+
+    /// Return the intermediate sibling nodes on the path of a pair of leaves.
+    pub fn siblings(&self, pos: usize) -> Vec<BWSSha256Hash> {
+        let logn = self.layers.len() - 1;
+        (1..logn)
+            .map(|i| self.layers[i][&((pos >> i) ^ 1)])
+            .collect()
+    }
 }
 
 /// A Merkle tree proof.
@@ -112,33 +267,48 @@ pub struct MerkleTreeTwinProof {
     pub siblings: Vec<BWSSha256Hash>,
 }
 
-impl Pushable for MerkleTreeTwinProof {
-    fn bitcoin_script_push(self, builder: Builder) -> Builder {
-        (&self).bitcoin_script_push(builder)
+impl MerkleTreeTwinProof {
+    /// Write the proof as pulled by `MerkleTreeTwinGadget::query_and_verify`, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        for v in self.left.iter().chain(self.right.iter()) {
+            writer.write(label, StateValue::M31(*v))?;
+        }
+        for sibling in self.siblings.iter() {
+            writer.write(label, StateValue::Digest(*sibling))?;
+        }
+        Ok(())
     }
 }
 
-impl Pushable for &MerkleTreeTwinProof {
-    fn bitcoin_script_push(self, mut builder: Builder) -> Builder {
-        for v in self.left.iter() {
-            builder = v.bitcoin_script_push(builder);
-        }
-        for v in self.right.iter() {
-            builder = v.bitcoin_script_push(builder);
-        }
-        for elem in self.siblings.iter() {
-            builder = elem.bitcoin_script_push(builder);
+/// A Merkle tree proof for a leaf that is known to the verifier.
+#[derive(Default, Clone, Debug)]
+pub struct MerkleTreeSiblingProof {
+    /// The sibling leaf, as a qm31 element.
+    pub twin: QM31,
+    /// All the intermediate sibling nodes.
+    pub siblings: Vec<BWSSha256Hash>,
+}
+
+impl MerkleTreeSiblingProof {
+    /// Write the proof as pulled by `MerkleTreeTwinGadget::query_and_verify_sibling`, all under
+    /// the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        writer.write(label, StateValue::QM31(self.twin))?;
+        for sibling in self.siblings.iter() {
+            writer.write(label, StateValue::Digest(*sibling))?;
         }
-        builder
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::merkle_tree::MerkleTree;
+    use crate::merkle_tree::{MerkleTree, SparseMerkleTree};
     use crate::utils::get_rand_qm31;
     use rand::{Rng, SeedableRng};
     use rand_chacha::ChaCha20Rng;
+    use std::collections::BTreeMap;
+    use stwo_prover::core::fields::qm31::QM31;
 
     #[test]
     fn test_merkle_tree() {
@@ -167,4 +337,66 @@ mod test {
             ));
         }
     }
+
+    #[test]
+    fn test_sparse_merkle_tree() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut last_layer = vec![];
+        for _ in 0..1 << 12 {
+            let a = get_rand_qm31(&mut prng);
+            last_layer.push(a.to_m31_array().to_vec());
+        }
+
+        let merkle_tree = MerkleTree::new(last_layer.clone());
+
+        let mut leaves = BTreeMap::new();
+        for _ in 0..10 {
+            let query = (prng.gen::<u32>() % (1 << 12)) as usize;
+            leaves.insert(query, last_layer[query].clone());
+            leaves.insert(query ^ 1, last_layer[query ^ 1].clone());
+        }
+
+        // collect the hash witness in the order of stwo's Merkle verifier
+        let mut hash_witness = vec![];
+        let mut known = leaves.keys().copied().collect::<Vec<usize>>();
+        for i in 0..12 {
+            let mut parents = known.iter().map(|j| j >> 1).collect::<Vec<usize>>();
+            parents.dedup();
+            if i > 0 {
+                for &parent in parents.iter() {
+                    for child in [parent << 1, (parent << 1) | 1] {
+                        if !known.contains(&child) {
+                            hash_witness.push(merkle_tree.intermediate_layers[i - 1][child]);
+                        }
+                    }
+                }
+            }
+            known = parents;
+        }
+
+        let sparse_merkle_tree =
+            SparseMerkleTree::from_hash_witness(12, &leaves, &hash_witness).unwrap();
+        assert_eq!(sparse_merkle_tree.root_hash(), merkle_tree.root_hash);
+
+        for &query in leaves.keys() {
+            let twin_proof = sparse_merkle_tree.query(query & !1);
+            assert!(MerkleTree::verify_twin(
+                &merkle_tree.root_hash,
+                12,
+                &twin_proof,
+                query & !1
+            ));
+
+            let proof = merkle_tree.query_sibling(query);
+            assert_eq!(proof.siblings, sparse_merkle_tree.siblings(query));
+            assert!(MerkleTree::verify_sibling(
+                &merkle_tree.root_hash,
+                12,
+                &proof,
+                QM31::from_m31_array(last_layer[query].clone().try_into().unwrap()),
+                query
+            ));
+        }
+    }
 }
@@ -1,8 +1,11 @@
 use crate::channel::Sha256ChannelGadget;
+use crate::chunker::StateItem;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
 use rust_bitcoin_m31::{
     m31_add_n31, m31_sub, push_m31_one, push_n31_one, qm31_double, qm31_dup, qm31_equalverify,
-    qm31_from_bottom, qm31_mul, qm31_neg, qm31_roll, qm31_rot, qm31_square, qm31_swap,
+    qm31_mul, qm31_neg, qm31_roll, qm31_rot, qm31_square, qm31_swap,
 };
 
 /// Gadget for out-of-domain sampling.
@@ -11,7 +14,7 @@ pub struct OODSGadget;
 impl OODSGadget {
     /// Samples a random point over the projective line, see Lemma 1 in https://eprint.iacr.org/2024/278.pdf
     ///
-    /// Hint:
+    /// Hint (pulled through the stream under the label):
     /// - OODSHint
     ///
     /// Input:
@@ -22,9 +25,9 @@ impl OODSGadget {
     /// - x
     /// - y
     /// where (x,y) - random point on C(QM31) satisfying x^2+y^2=1 (8 elements)
-    pub fn get_random_point() -> Script {
+    pub fn get_random_point(stream: &mut HintStream, label: &str) -> Script {
         script! {
-            { Sha256ChannelGadget::draw_felt_with_hint() }
+            { Sha256ChannelGadget::draw_felt_with_hint(stream, label) }
             // stack: x, y, channel', t
 
             // compute t^2 from t
@@ -45,7 +48,7 @@ impl OODSGadget {
             // stack: x, y, channel', t, t^2 - 1, t^2 + 1, t^2 + 1
 
             // pull the hint x and verify
-            qm31_from_bottom
+            { stream.pull(label, StateItem::QM31) }
             qm31_dup
             qm31_rot
             qm31_mul
@@ -56,7 +59,7 @@ impl OODSGadget {
             // stack: y, channel', t, t^2 + 1, x
 
             // pull the hint y
-            qm31_from_bottom
+            { stream.pull(label, StateItem::QM31) }
             qm31_dup
             { qm31_roll(3) }
             qm31_mul
@@ -67,9 +70,26 @@ impl OODSGadget {
     }
 }
 
+impl Gadget for OODSGadget {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest, StateItem::CirclePoint]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        Self::get_random_point(stream, "oods_hint")
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use crate::chunker::StateValue;
+    use crate::hint_stream::HintStream;
     use crate::oods::{OODSGadget, OODS};
+    use crate::tests_utils::gadget::check_gadget;
     use crate::treepp::*;
     use crate::{channel::Sha256Channel, tests_utils::report::report_bitcoin_script_size};
     use rand::{Rng, SeedableRng};
@@ -83,7 +103,8 @@ mod test {
     fn test_get_random_point() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
-        let get_random_point_script = OODSGadget::get_random_point();
+        let mut stream = HintStream::new();
+        let get_random_point_script = OODSGadget::get_random_point(&mut stream, "hint");
 
         report_bitcoin_script_size("OODS", "get_random_point", get_random_point_script.len());
 
@@ -98,8 +119,11 @@ mod test {
 
         let c = channel.digest;
 
+        let mut writer = stream.writer();
+        hint.write(&mut writer, "hint").unwrap();
+
         let script = script! {
-            { hint }
+            { writer.finish_script().unwrap() }
             { a }
             { get_random_point_script.clone() }
             { oods_res.y } // check y
@@ -113,4 +137,20 @@ mod test {
         let exec_result = execute_script(script);
         assert!(exec_result.success);
     }
+
+    #[test]
+    fn test_gadgets() {
+        check_gadget(&OODSGadget, 10, |inputs, writer| {
+            let [StateValue::Digest(digest)] = inputs else {
+                unreachable!()
+            };
+            let mut channel = Sha256Channel::new(*digest);
+            let (point, hint) = CirclePoint::get_random_point_with_hint(&mut channel);
+            hint.write(writer, "oods_hint").unwrap();
+            vec![
+                StateValue::Digest(channel.digest),
+                StateValue::CirclePoint(point),
+            ]
+        });
+    }
 }
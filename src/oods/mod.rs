@@ -1,5 +1,7 @@
 use crate::channel::Sha256Channel;
 use crate::channel::{ChannelWithHint, DrawHints};
+use crate::chunker::StateValue;
+use crate::hint_stream::{HintStreamError, HintWriter};
 use num_traits::One;
 use std::ops::{Add, Mul, Neg};
 use stwo_prover::core::circle::CirclePoint;
@@ -7,7 +9,6 @@ use stwo_prover::core::fields::qm31::QM31;
 use stwo_prover::core::fields::{Field, FieldExpOps};
 
 mod bitcoin_script;
-use crate::treepp::pushable::{Builder, Pushable};
 pub use bitcoin_script::*;
 
 /// An out-of-domain sampling implementation.
@@ -40,10 +41,11 @@ pub struct OODSHint {
     pub hint: DrawHints,
 }
 
-impl Pushable for OODSHint {
-    fn bitcoin_script_push(self, mut builder: Builder) -> Builder {
-        builder = self.hint.bitcoin_script_push(builder);
-        builder = self.x.bitcoin_script_push(builder);
-        self.y.bitcoin_script_push(builder)
+impl OODSHint {
+    /// Write the hint as pulled by `OODSGadget::get_random_point`, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        self.hint.write(writer, label)?;
+        writer.write(label, StateValue::QM31(self.x))?;
+        writer.write(label, StateValue::QM31(self.y))
     }
 }
@@ -0,0 +1,472 @@
+use crate::analyzer::stack_effect;
+use crate::treepp::*;
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+
+/// A rewrite of two consecutive opcodes, which applies only if the stack and the altstack are
+/// known to hold at least the given numbers of elements before the first opcode.
+///
+/// A rewrite gives the same result on every execution, but it may lower the peak number of the
+/// elements by one.
+struct Rule {
+    name: &'static str,
+    pattern: [Opcode; 2],
+    replacement: &'static [Opcode],
+    min_stack: usize,
+    min_altstack: usize,
+}
+
+/// The rules of the optimizer.
+///
+/// There is no rule for the runs of `OP_DEPTH OP_1SUB OP_ROLL`: keeping the depth on the stack to
+/// pull the next hint costs as many bytes as it saves.
+const RULES: &[Rule] = &[
+    Rule {
+        name: "toaltstack_fromaltstack",
+        pattern: [OP_TOALTSTACK, OP_FROMALTSTACK],
+        replacement: &[],
+        min_stack: 1,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "fromaltstack_toaltstack",
+        pattern: [OP_FROMALTSTACK, OP_TOALTSTACK],
+        replacement: &[],
+        min_stack: 0,
+        min_altstack: 1,
+    },
+    Rule {
+        name: "zero_roll",
+        pattern: [OP_PUSHBYTES_0, OP_ROLL],
+        replacement: &[],
+        min_stack: 1,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "one_roll",
+        pattern: [OP_PUSHNUM_1, OP_ROLL],
+        replacement: &[OP_SWAP],
+        min_stack: 0,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "two_roll",
+        pattern: [OP_PUSHNUM_2, OP_ROLL],
+        replacement: &[OP_ROT],
+        min_stack: 0,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "zero_pick",
+        pattern: [OP_PUSHBYTES_0, OP_PICK],
+        replacement: &[OP_DUP],
+        min_stack: 0,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "one_pick",
+        pattern: [OP_PUSHNUM_1, OP_PICK],
+        replacement: &[OP_OVER],
+        min_stack: 0,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "dup_drop",
+        pattern: [OP_DUP, OP_DROP],
+        replacement: &[],
+        min_stack: 1,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "swap_swap",
+        pattern: [OP_SWAP, OP_SWAP],
+        replacement: &[],
+        min_stack: 2,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "drop_drop",
+        pattern: [OP_DROP, OP_DROP],
+        replacement: &[OP_2DROP],
+        min_stack: 0,
+        min_altstack: 0,
+    },
+    Rule {
+        name: "equal_verify",
+        pattern: [OP_EQUAL, OP_VERIFY],
+        replacement: &[OP_EQUALVERIFY],
+        min_stack: 0,
+        min_altstack: 0,
+    },
+];
+
+/// How often a rule of the optimizer applies to a script, and the bytes it saves.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RuleReport {
+    /// Name of the rule.
+    pub name: &'static str,
+    /// Number of the rewrites.
+    pub applied: usize,
+    /// Number of the bytes saved by the rewrites.
+    pub saved_bytes: usize,
+}
+
+/// The result of the optimization of a script.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptimizationReport {
+    /// Size of the script before the optimization.
+    pub original_size: usize,
+    /// Size of the script after the optimization.
+    pub optimized_size: usize,
+    /// The reports of all the rules.
+    pub rules: Vec<RuleReport>,
+}
+
+/// Lower bounds of the depths of the stack and the altstack, which hold whenever the execution
+/// reaches the instruction.
+#[derive(Clone, Copy, Debug, Default)]
+struct DepthBounds {
+    stack: usize,
+    altstack: usize,
+}
+
+impl DepthBounds {
+    /// The bounds after the instruction, given as its bytes in the script.
+    fn after(self, instruction: &[u8]) -> Self {
+        let opcode = Opcode::from(instruction[0]);
+
+        // the pushes of data and of small numbers
+        if opcode.to_u8() <= OP_PUSHNUM_16.to_u8() && opcode != OP_RESERVED {
+            return Self {
+                stack: self.stack + 1,
+                ..self
+            };
+        }
+
+        match opcode {
+            OP_TOALTSTACK => Self {
+                stack: self.stack.saturating_sub(1),
+                altstack: self.altstack + 1,
+            },
+            OP_FROMALTSTACK => Self {
+                stack: self.stack + 1,
+                altstack: self.altstack.saturating_sub(1),
+            },
+            // the branch is entered only if the condition is popped
+            OP_IF | OP_NOTIF => Self {
+                stack: self.stack.saturating_sub(1),
+                ..self
+            },
+            OP_IFDUP => Self {
+                stack: self.stack.max(1),
+                ..self
+            },
+            // the other branch may have been taken, and the other opcodes are not tracked
+            _ => match stack_effect(opcode) {
+                Some((pops, pushes)) => Self {
+                    stack: self.stack.max(pops) - pops + pushes,
+                    ..self
+                },
+                None => Self::default(),
+            },
+        }
+    }
+}
+
+/// An instruction of the optimized script, with the depth bounds before it.
+struct Token {
+    bytes: Vec<u8>,
+    before: DepthBounds,
+}
+
+/// Apply the peephole rules to a script until none applies, and report the bytes saved by each
+/// rule.
+///
+/// The rules rewrite consecutive opcodes, such as `OP_TOALTSTACK OP_FROMALTSTACK` left at the
+/// boundary of two gadgets, and the removals apply only where the instructions before prove that
+/// the removed opcodes cannot fail. The optimized script gives the same result as the original one
+/// on every execution that stays within the stack limit. If the script cannot be decoded, its
+/// undecodable tail is kept as it is.
+pub fn optimize(script: &Script) -> (Script, OptimizationReport) {
+    let bytes = script.as_bytes();
+
+    let mut starts = vec![];
+    let mut end = bytes.len();
+    for (offset, instruction) in script.instruction_indices() {
+        if instruction.is_err() {
+            end = offset;
+            break;
+        }
+        starts.push(offset);
+    }
+
+    let mut rules = RULES
+        .iter()
+        .map(|rule| RuleReport {
+            name: rule.name,
+            applied: 0,
+            saved_bytes: 0,
+        })
+        .collect::<Vec<_>>();
+
+    let mut tokens: Vec<Token> = vec![];
+    let mut bounds = DepthBounds::default();
+    for (i, &start) in starts.iter().enumerate() {
+        let stop = starts.get(i + 1).copied().unwrap_or(end);
+        let instruction = bytes[start..stop].to_vec();
+        tokens.push(Token {
+            before: bounds,
+            bytes: instruction,
+        });
+        bounds = bounds.after(&tokens.last().unwrap().bytes);
+
+        // each rewrite shortens the tokens, so that this terminates
+        while let Some((rule_idx, rule)) = tokens.len().checked_sub(2).and_then(|first| {
+            RULES.iter().enumerate().find(|(_, rule)| {
+                tokens[first].bytes == [rule.pattern[0].to_u8()]
+                    && tokens[first + 1].bytes == [rule.pattern[1].to_u8()]
+                    && tokens[first].before.stack >= rule.min_stack
+                    && tokens[first].before.altstack >= rule.min_altstack
+            })
+        }) {
+            let first = tokens.len() - 2;
+            bounds = tokens[first].before;
+            tokens.truncate(first);
+
+            for opcode in rule.replacement.iter() {
+                tokens.push(Token {
+                    before: bounds,
+                    bytes: vec![opcode.to_u8()],
+                });
+                bounds = bounds.after(&[opcode.to_u8()]);
+            }
+
+            rules[rule_idx].applied += 1;
+            rules[rule_idx].saved_bytes += 2 - rule.replacement.len();
+        }
+    }
+
+    let mut optimized = tokens
+        .into_iter()
+        .flat_map(|token| token.bytes)
+        .collect::<Vec<_>>();
+    optimized.extend_from_slice(&bytes[end..]);
+
+    let report = OptimizationReport {
+        original_size: bytes.len(),
+        optimized_size: optimized.len(),
+        rules,
+    };
+    (Script::from_bytes(optimized), report)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::push_state;
+    use crate::optimizer::optimize;
+    use crate::tests_utils::fibonacci::hint_and_verifier;
+    use crate::tests_utils::report::report_bitcoin_script_size;
+    use crate::treepp::*;
+    use bitcoin::opcodes::all::*;
+    use bitcoin::opcodes::Opcode;
+    use itertools::Itertools;
+
+    fn ops(opcodes: &[Opcode]) -> Script {
+        Script::from_bytes(opcodes.iter().map(|opcode| opcode.to_u8()).collect())
+    }
+
+    fn applied(script: &Script, name: &str) -> usize {
+        let (_, report) = optimize(script);
+        report
+            .rules
+            .iter()
+            .find(|rule| rule.name == name)
+            .unwrap()
+            .applied
+    }
+
+    #[test]
+    fn test_rules() {
+        let cases: &[(&[Opcode], &[Opcode], &str)] = &[
+            (
+                &[OP_PUSHNUM_1, OP_TOALTSTACK, OP_FROMALTSTACK],
+                &[OP_PUSHNUM_1],
+                "toaltstack_fromaltstack",
+            ),
+            (
+                &[
+                    OP_PUSHNUM_1,
+                    OP_TOALTSTACK,
+                    OP_PUSHNUM_2,
+                    OP_FROMALTSTACK,
+                    OP_TOALTSTACK,
+                ],
+                &[OP_PUSHNUM_1, OP_TOALTSTACK, OP_PUSHNUM_2],
+                "fromaltstack_toaltstack",
+            ),
+            (
+                &[OP_PUSHNUM_1, OP_PUSHBYTES_0, OP_ROLL],
+                &[OP_PUSHNUM_1],
+                "zero_roll",
+            ),
+            (&[OP_PUSHNUM_1, OP_ROLL], &[OP_SWAP], "one_roll"),
+            (&[OP_PUSHNUM_2, OP_ROLL], &[OP_ROT], "two_roll"),
+            (&[OP_PUSHBYTES_0, OP_PICK], &[OP_DUP], "zero_pick"),
+            (&[OP_PUSHNUM_1, OP_PICK], &[OP_OVER], "one_pick"),
+            (
+                &[OP_PUSHNUM_1, OP_DUP, OP_DROP],
+                &[OP_PUSHNUM_1],
+                "dup_drop",
+            ),
+            (
+                &[OP_PUSHNUM_1, OP_PUSHNUM_2, OP_SWAP, OP_SWAP],
+                &[OP_PUSHNUM_1, OP_PUSHNUM_2],
+                "swap_swap",
+            ),
+            (&[OP_DROP, OP_DROP], &[OP_2DROP], "drop_drop"),
+            (&[OP_EQUAL, OP_VERIFY], &[OP_EQUALVERIFY], "equal_verify"),
+        ];
+
+        for (original, expected, name) in cases.iter() {
+            let script = ops(original);
+            let (optimized, report) = optimize(&script);
+            assert_eq!(optimized, ops(expected), "{}", name);
+            assert_eq!(applied(&script, name), 1, "{}", name);
+            assert_eq!(
+                report
+                    .rules
+                    .iter()
+                    .map(|rule| rule.saved_bytes)
+                    .sum::<usize>(),
+                report.original_size - report.optimized_size
+            );
+        }
+    }
+
+    #[test]
+    fn test_preconditions() {
+        // the removals must not hide a failure when the stack may be too short
+        for original in [
+            ops(&[OP_TOALTSTACK, OP_FROMALTSTACK]),
+            ops(&[OP_FROMALTSTACK, OP_TOALTSTACK]),
+            ops(&[OP_PUSHBYTES_0, OP_ROLL]),
+            ops(&[OP_DUP, OP_DROP]),
+            ops(&[OP_PUSHNUM_1, OP_SWAP, OP_SWAP]),
+            ops(&[OP_PUSHNUM_1, OP_DROP, OP_TOALTSTACK, OP_FROMALTSTACK]),
+            ops(&[
+                OP_PUSHNUM_1,
+                OP_PUSHNUM_1,
+                OP_IF,
+                OP_ELSE,
+                OP_TOALTSTACK,
+                OP_FROMALTSTACK,
+                OP_ENDIF,
+            ]),
+            ops(&[
+                OP_PUSHNUM_1,
+                OP_CHECKMULTISIG,
+                OP_TOALTSTACK,
+                OP_FROMALTSTACK,
+            ]),
+        ] {
+            let (optimized, report) = optimize(&original);
+            assert_eq!(optimized, original);
+            assert_eq!(report.original_size, report.optimized_size);
+        }
+
+        // inside a branch, the condition is popped from the stack
+        let (optimized, _) = optimize(&ops(&[
+            OP_PUSHNUM_1,
+            OP_PUSHNUM_1,
+            OP_IF,
+            OP_TOALTSTACK,
+            OP_FROMALTSTACK,
+            OP_ENDIF,
+        ]));
+        assert_eq!(
+            optimized,
+            ops(&[OP_PUSHNUM_1, OP_PUSHNUM_1, OP_IF, OP_ENDIF])
+        );
+    }
+
+    #[test]
+    fn test_cascade() {
+        let script = ops(&[
+            OP_PUSHNUM_3,
+            OP_PUSHNUM_1,
+            OP_PUSHNUM_2,
+            OP_TOALTSTACK,
+            OP_TOALTSTACK,
+            OP_FROMALTSTACK,
+            OP_FROMALTSTACK,
+            OP_PUSHNUM_1,
+            OP_ROLL,
+            OP_SWAP,
+            OP_DROP,
+        ]);
+        let (optimized, report) = optimize(&script);
+        assert_eq!(
+            optimized,
+            ops(&[OP_PUSHNUM_3, OP_PUSHNUM_1, OP_PUSHNUM_2, OP_DROP])
+        );
+        assert_eq!(report.original_size - report.optimized_size, 7);
+
+        for script in [script, optimized] {
+            let exec_result = execute_script(script! {
+                { script }
+                1 OP_EQUALVERIFY
+                3 OP_EQUAL
+            });
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_undecodable_tail() {
+        // a push of 5 bytes with only 2 of them
+        let script = Script::from_bytes(vec![
+            OP_PUSHNUM_1.to_u8(),
+            OP_PUSHBYTES_0.to_u8(),
+            OP_ROLL.to_u8(),
+            0x05,
+            0xaa,
+            0xbb,
+        ]);
+        let (optimized, _) = optimize(&script);
+        assert_eq!(
+            optimized,
+            Script::from_bytes(vec![OP_PUSHNUM_1.to_u8(), 0x05, 0xaa, 0xbb])
+        );
+    }
+
+    #[test]
+    fn test_verifier_optimized() {
+        let (hints, verifier) = hint_and_verifier(5);
+        let (optimized, report) = optimize(&verifier);
+        assert_eq!(optimized.len(), report.optimized_size);
+        assert!(report.optimized_size <= report.original_size);
+        report_bitcoin_script_size("FibonacciVerifier", "optimized", optimized.len());
+
+        // the optimized verifier must give the same results as the original one, on the hints and
+        // on the hints with an extra element
+        for extra in [false, true] {
+            let results = [verifier.clone(), optimized.clone()].map(|script| {
+                let exec_result = execute_script(script! {
+                    { push_state(&hints) }
+                    if extra {
+                        1
+                    }
+                    { script }
+                });
+                (
+                    exec_result.success,
+                    (0..exec_result.final_stack.len())
+                        .map(|i| exec_result.final_stack.get(i))
+                        .collect_vec(),
+                )
+            });
+            assert_eq!(results[0], results[1]);
+            assert_eq!(results[0].0, !extra);
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use crate::channel::Sha256ChannelGadget;
+use crate::chunker::StateItem;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
-use crate::OP_HINT;
 
 /// Gadget for verifying PoW.
 pub struct PowGadget;
@@ -8,7 +10,7 @@ pub struct PowGadget;
 impl PowGadget {
     /// Verify the PoW in Bitcoin script.
     ///
-    /// Hint:
+    /// Hint (pulled through the stream under the label):
     /// - nonce (64-bit string, aka 8 bytes)
     /// - prefix (the sha256 result after the leading zero bytes and the MSB [if applicable])
     /// - msb (applicable if n_bits % 8 != 0)
@@ -25,13 +27,13 @@ impl PowGadget {
     ///  prefix || msb || {0x00}^(n_bits // 8)  != sha256(channel||nonce)
     ///     where msb is required if n_bits % 8 != 0 and should not be present if it is not
     ///  msb starts with n_bits % 8 (which would be at least 1) zero bits.
-    pub fn verify_pow(n_bits: u32) -> Script {
+    pub fn verify_pow(stream: &mut HintStream, label: &str, n_bits: u32) -> Script {
         assert!(n_bits > 0);
         let n_bits = n_bits as usize;
 
         script! {
             // pull the nonce
-            OP_HINT
+            { stream.pull(label, StateItem::Bytes) }
 
             // check the length of the nonce
             OP_SIZE 8 OP_EQUALVERIFY
@@ -43,7 +45,7 @@ impl PowGadget {
             // stack: channel, nonce, sha256(channel||nonce)
 
             // pull the prefix
-            OP_HINT
+            { stream.pull(label, StateItem::Bytes) }
 
             // check the length of the prefix
             OP_SIZE { 32 - ((n_bits  + 7) / 8) } OP_EQUALVERIFY
@@ -51,7 +53,7 @@ impl PowGadget {
             // if msb is present, check the msb is small enough,
             // and if it is a zero, make it `0x00`
             if n_bits % 8 != 0 {
-                OP_HINT
+                { stream.pull(label, StateItem::Bytes) }
                 OP_DUP
                 0 OP_GREATERTHANOREQUAL OP_VERIFY
                 OP_DUP
@@ -76,18 +78,135 @@ impl PowGadget {
             { Sha256ChannelGadget::mix_nonce() }
         }
     }
+
+    /// Check the PoW of a nonce in Bitcoin script, leaving the result instead of failing, so that
+    /// a wrong nonce can be disproved.
+    ///
+    /// Hint (pulled through the stream under the label):
+    /// - prefix (the sha256 result before the MSB [if applicable] and the last n_bits // 8 bytes)
+    /// - msb (applicable if n_bits % 8 != 0, as a raw byte)
+    /// - rest (the last n_bits // 8 bytes, applicable if n_bits >= 8)
+    ///
+    /// Input:
+    ///  channel digest
+    ///  nonce
+    ///
+    /// Output:
+    ///  1 if the nonce has 8 bytes, msb starts with n_bits % 8 zero bits and rest is all zeros,
+    ///  0 otherwise
+    ///
+    /// The hint must be the split of sha256(channel||nonce), or of sha256(channel||0^8) if the
+    /// nonce does not have 8 bytes, see `PoWCheckHint`.
+    pub fn check_pow(stream: &mut HintStream, label: &str, n_bits: u32) -> Script {
+        assert!(n_bits > 0);
+        let n_bits = n_bits as usize;
+
+        script! {
+            // a nonce of a wrong length fails the check, and is replaced so that the hash can
+            // still be computed
+            OP_SIZE 8 OP_EQUAL
+            OP_DUP OP_TOALTSTACK
+            OP_NOTIF
+                OP_DROP { vec![0u8; 8] }
+            OP_ENDIF
+
+            // compute sha256(channel||nonce)
+            OP_CAT OP_SHA256
+
+            // pull the prefix
+            { stream.pull(label, StateItem::Bytes) }
+            OP_SIZE { 32 - ((n_bits + 7) / 8) } OP_EQUALVERIFY
+
+            // if msb is present, check whether it is small enough, as a number with `0x00` and
+            // `0x80` (which are not minimally encoded) mapped to 0 and 128
+            if n_bits % 8 != 0 {
+                { stream.pull(label, StateItem::Bytes) }
+                OP_SIZE 1 OP_EQUALVERIFY
+                OP_TUCK OP_CAT OP_SWAP
+                OP_DUP { vec![0x00u8] } OP_EQUAL
+                OP_IF
+                    OP_DROP 0
+                OP_ENDIF
+                OP_DUP { vec![0x80u8] } OP_EQUAL
+                OP_IF
+                    OP_DROP 128
+                OP_ENDIF
+                0 { 1 << (8 - n_bits % 8) } OP_WITHIN
+                OP_FROMALTSTACK OP_BOOLAND OP_TOALTSTACK
+            }
+
+            // pull the rest, and check whether it is all zeros
+            if n_bits / 8 > 0 {
+                { stream.pull(label, StateItem::Bytes) }
+                OP_SIZE { n_bits / 8 } OP_EQUALVERIFY
+                OP_DUP { vec![0u8; n_bits / 8] } OP_EQUAL
+                OP_FROMALTSTACK OP_BOOLAND OP_TOALTSTACK
+                OP_CAT
+            }
+
+            // the hint must be the split of the hash
+            OP_EQUALVERIFY
+            OP_FROMALTSTACK
+        }
+    }
+}
+
+/// `PowGadget::verify_pow` as a `Gadget`, with the hints under "pow_hint".
+pub struct VerifyPow {
+    /// Number of the leading zero bits.
+    pub n_bits: u32,
+}
+
+impl Gadget for VerifyPow {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        PowGadget::verify_pow(stream, "pow_hint", self.n_bits)
+    }
+}
+
+/// `PowGadget::check_pow` as a `Gadget`, with the hints under "pow_hint".
+pub struct CheckPow {
+    /// Number of the leading zero bits.
+    pub n_bits: u32,
+}
+
+impl Gadget for CheckPow {
+    fn inputs(&self) -> Vec<StateItem> {
+        vec![StateItem::Digest, StateItem::Bytes]
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::M31]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        PowGadget::check_pow(stream, "pow_hint", self.n_bits)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::channel::Sha256Channel;
+    use crate::chunker::StateValue;
+    use crate::hint_stream::HintStream;
+    use crate::tests_utils::gadget::check_gadget_with_sampler;
     use crate::{tests_utils::report::report_bitcoin_script_size, treepp::*};
-    use rand::{RngCore, SeedableRng};
+    use rand::{Rng, RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
     use stwo_prover::core::channel::Channel;
+    use stwo_prover::core::fields::m31::M31;
     use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
 
-    use crate::pow::{bitcoin_script::PowGadget, hash_with_nonce, PoWHint};
+    use crate::pow::{
+        bitcoin_script::PowGadget, hash_with_nonce, CheckPow, PoWCheckHint, PoWHint, VerifyPow,
+    };
 
     // Check that the prefix leading zeros is greater than `bound_bits`.
     fn check_leading_zeros(bytes: &[u8], bound_bits: u32) -> bool {
@@ -118,7 +237,7 @@ mod test {
     }
 
     #[test]
-    fn test_push_pow_hint() {
+    fn test_write_pow_hint() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
         let mut channel_digest = vec![0u8; 32];
@@ -129,8 +248,14 @@ mod test {
 
         let pow_hint = PoWHint::new(BWSSha256Hash::from(channel_digest), nonce, 1);
 
+        // the hint is written in the order of the pulls of the verifier of the PoW
+        let mut stream = HintStream::new();
+        let _ = PowGadget::verify_pow(&mut stream, "pow_hint", 1);
+        let mut writer = stream.writer();
+        pow_hint.write(&mut writer, "pow_hint").unwrap();
+
         let script = script! {
-            { pow_hint }
+            { writer.finish_script().unwrap() }
             { new_channel[31] }
             OP_EQUALVERIFY
             { new_channel[..31].to_vec() }
@@ -160,10 +285,15 @@ mod test {
             n_bits,
         );
 
+        let mut stream = HintStream::new();
+        let verify_pow_script = PowGadget::verify_pow(&mut stream, "pow_hint", n_bits);
+        let mut writer = stream.writer();
+        pow_hint.write(&mut writer, "pow_hint").unwrap();
+
         let script = script! {
-            { pow_hint }
+            { writer.finish_script().unwrap() }
             { channel_digest.clone() }
-            { PowGadget::verify_pow(n_bits) }
+            { verify_pow_script }
             OP_DROP
             OP_TRUE
         };
@@ -186,10 +316,15 @@ mod test {
             n_bits,
         );
 
+        let mut stream = HintStream::new();
+        let verify_pow_script = PowGadget::verify_pow(&mut stream, "pow_hint", n_bits);
+        let mut writer = stream.writer();
+        pow_hint.write(&mut writer, "pow_hint").unwrap();
+
         let script = script! {
-            { pow_hint }
+            { writer.finish_script().unwrap() }
             { channel_digest.clone() }
-            { PowGadget::verify_pow(n_bits)}
+            { verify_pow_script }
             OP_DROP
             OP_TRUE
         };
@@ -212,10 +347,15 @@ mod test {
             n_bits,
         );
 
+        let mut stream = HintStream::new();
+        let verify_pow_script = PowGadget::verify_pow(&mut stream, "pow_hint", n_bits);
+        let mut writer = stream.writer();
+        pow_hint.write(&mut writer, "pow_hint").unwrap();
+
         let script = script! {
-            { pow_hint }
+            { writer.finish_script().unwrap() }
             { channel_digest.clone() }
-            { PowGadget::verify_pow(n_bits)}
+            { verify_pow_script }
             OP_DROP
             OP_TRUE
         };
@@ -235,7 +375,8 @@ mod test {
 
                 let nonce = grind_find_nonce(channel_digest.clone(), n_bits);
 
-                let verify_pow_script = PowGadget::verify_pow(n_bits);
+                let mut stream = HintStream::new();
+                let verify_pow_script = PowGadget::verify_pow(&mut stream, "pow_hint", n_bits);
                 if prng_seed == 0 {
                     report_bitcoin_script_size(
                         "POW",
@@ -251,8 +392,11 @@ mod test {
                     Sha256Channel::new(BWSSha256Hash::from(channel_digest.as_slice()));
                 channel.mix_nonce(nonce);
 
+                let mut writer = stream.writer();
+                pow_hint.write(&mut writer, "pow_hint").unwrap();
+
                 let script = script! {
-                    { pow_hint }
+                    { writer.finish_script().unwrap() }
                     { channel_digest.clone() }
                     { verify_pow_script.clone() }
                     { channel.digest }
@@ -266,7 +410,127 @@ mod test {
         report_bitcoin_script_size(
             "POW",
             "verify_pow (78 bits)",
-            PowGadget::verify_pow(78).len(),
+            PowGadget::verify_pow(&mut HintStream::new(), "pow_hint", 78).len(),
         );
     }
+
+    #[test]
+    fn test_check_pow() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for n_bits in [1, 8, 12] {
+            let mut channel_digest = [0u8; 32].to_vec();
+            prng.fill_bytes(&mut channel_digest);
+
+            let mut stream = HintStream::new();
+            let check_pow_script = PowGadget::check_pow(&mut stream, "pow_hint", n_bits);
+            report_bitcoin_script_size(
+                "POW",
+                format!("check_pow ({} bits)", n_bits).as_str(),
+                check_pow_script.len(),
+            );
+
+            // a nonce with enough zeros, the first one without, and a nonce of a wrong length
+            let good_nonce = grind_find_nonce(channel_digest.clone(), n_bits);
+            let bad_nonce = (0..)
+                .find(|&nonce| {
+                    !check_leading_zeros(&hash_with_nonce(&channel_digest, nonce), n_bits)
+                })
+                .unwrap();
+
+            for (nonce, expected) in [
+                (good_nonce.to_le_bytes().to_vec(), 1),
+                (bad_nonce.to_le_bytes().to_vec(), 0),
+                (good_nonce.to_le_bytes()[..7].to_vec(), 0),
+            ] {
+                let hint = PoWCheckHint::new(&channel_digest, &nonce, n_bits);
+                let mut writer = stream.writer();
+                hint.write(&mut writer, "pow_hint").unwrap();
+
+                let script = script! {
+                    { writer.finish_script().unwrap() }
+                    { channel_digest.clone() }
+                    { nonce }
+                    { check_pow_script.clone() }
+                    { expected }
+                    OP_EQUAL
+                };
+                let exec_result = execute_script(script);
+                assert!(exec_result.success);
+            }
+
+            // the hint must be the split of the hash
+            let mut hint = PoWCheckHint::new(&channel_digest, &good_nonce.to_le_bytes(), n_bits);
+            hint.prefix[0] ^= 1;
+            let mut writer = stream.writer();
+            hint.write(&mut writer, "pow_hint").unwrap();
+
+            let script = script! {
+                { writer.finish_script().unwrap() }
+                { channel_digest.clone() }
+                { good_nonce.to_le_bytes().to_vec() }
+                { check_pow_script.clone() }
+                OP_DROP
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(!exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_gadgets() {
+        for n_bits in [1, 8, 12] {
+            check_gadget_with_sampler(&VerifyPow { n_bits }, 5, |prng, writer| {
+                let mut channel_digest = [0u8; 32].to_vec();
+                prng.fill_bytes(&mut channel_digest);
+                let channel_digest = BWSSha256Hash::from(channel_digest);
+
+                let nonce = grind_find_nonce(channel_digest.as_ref().to_vec(), n_bits);
+                PoWHint::new(channel_digest, nonce, n_bits)
+                    .write(writer, "pow_hint")
+                    .unwrap();
+
+                let mut channel = Sha256Channel::new(channel_digest);
+                channel.mix_nonce(nonce);
+                (
+                    vec![StateValue::Digest(channel_digest)],
+                    vec![StateValue::Digest(channel.digest)],
+                )
+            });
+
+            // a nonce with enough zeros, a random nonce, or a nonce of a wrong length
+            check_gadget_with_sampler(&CheckPow { n_bits }, 15, |prng, writer| {
+                let mut channel_digest = [0u8; 32].to_vec();
+                prng.fill_bytes(&mut channel_digest);
+
+                let nonce = match prng.gen_range(0..3) {
+                    0 => grind_find_nonce(channel_digest.clone(), n_bits)
+                        .to_le_bytes()
+                        .to_vec(),
+                    1 => prng.gen::<u64>().to_le_bytes().to_vec(),
+                    _ => prng.gen::<u64>().to_le_bytes()[..7].to_vec(),
+                };
+                PoWCheckHint::new(&channel_digest, &nonce, n_bits)
+                    .write(writer, "pow_hint")
+                    .unwrap();
+
+                let passes = nonce.len() == 8
+                    && check_leading_zeros(
+                        &hash_with_nonce(
+                            &channel_digest,
+                            u64::from_le_bytes(nonce.clone().try_into().unwrap()),
+                        ),
+                        n_bits,
+                    );
+                (
+                    vec![
+                        StateValue::Digest(BWSSha256Hash::from(channel_digest)),
+                        StateValue::Bytes(nonce),
+                    ],
+                    vec![StateValue::M31(M31::from(passes as u32))],
+                )
+            });
+        }
+    }
 }
@@ -1,7 +1,9 @@
 mod bitcoin_script;
 pub use bitcoin_script::*;
 
-use crate::treepp::pushable::{Builder, Pushable};
+use crate::chunker::StateValue;
+use crate::encoding::integer_to_bytes;
+use crate::hint_stream::{HintStreamError, HintWriter};
 use sha2::{Digest, Sha256};
 use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
 
@@ -17,6 +19,7 @@ pub fn hash_with_nonce(seed: &[u8], nonce: u64) -> Vec<u8> {
 }
 
 /// A hint for PoW.
+#[derive(Clone)]
 pub struct PoWHint {
     /// The PoW nonce.
     /// Note: with a nonce of only 64 bits, it is not possible to get 78 bit security here :)
@@ -52,17 +55,59 @@ impl PoWHint {
     }
 }
 
-impl Pushable for PoWHint {
-    fn bitcoin_script_push(self, mut builder: Builder) -> Builder {
-        builder = self
-            .nonce
-            .to_le_bytes()
-            .to_vec()
-            .bitcoin_script_push(builder);
-        builder = self.prefix.bitcoin_script_push(builder);
+impl PoWHint {
+    /// Write the hint as pulled by `PowGadget::verify_pow`, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        writer.write(label, StateValue::Bytes(self.nonce.to_le_bytes().to_vec()))?;
+        writer.write(label, StateValue::Bytes(self.prefix.clone()))?;
+        if let Some(msb) = self.msb {
+            writer.write(label, StateValue::Bytes(integer_to_bytes(msb as i64)))?;
+        }
+        Ok(())
+    }
+}
+
+/// A hint for checking the PoW of a nonce, which may not pass.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoWCheckHint {
+    /// The prefix of sha256(channel||nonce).
+    pub prefix: Vec<u8>,
+    /// The msb of sha256(channel||nonce) immediately before the rest (if n_bits % 8 != 0).
+    pub msb: Option<u8>,
+    /// The last n_bits // 8 bytes of sha256(channel||nonce), which are zeros if the PoW passes.
+    pub rest: Vec<u8>,
+}
+
+impl PoWCheckHint {
+    /// Create the hint for checking the PoW of the nonce as `PowGadget::check_pow` does, which
+    /// hashes eight zero bytes instead of a nonce that does not have 8 bytes.
+    pub fn new(channel_digest: &[u8], nonce: &[u8], n_bits: u32) -> Self {
+        assert!(n_bits > 0);
+
+        let nonce = if nonce.len() == 8 {
+            u64::from_le_bytes(nonce.try_into().unwrap())
+        } else {
+            0
+        };
+        let digest = hash_with_nonce(channel_digest, nonce);
+        let n_bits = n_bits as usize;
+
+        Self {
+            prefix: digest[..32 - (n_bits + 7) / 8].to_vec(),
+            msb: (n_bits % 8 != 0).then(|| digest[32 - (n_bits + 7) / 8]),
+            rest: digest[32 - n_bits / 8..].to_vec(),
+        }
+    }
+
+    /// Write the hint as pulled by `PowGadget::check_pow`, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        writer.write(label, StateValue::Bytes(self.prefix.clone()))?;
         if let Some(msb) = self.msb {
-            builder = msb.bitcoin_script_push(builder);
+            writer.write(label, StateValue::Bytes(vec![msb]))?;
+        }
+        if !self.rest.is_empty() {
+            writer.write(label, StateValue::Bytes(self.rest.clone()))?;
         }
-        builder
+        Ok(())
     }
 }
@@ -1,3 +1,5 @@
+use crate::chunker::StateItem;
+use crate::hint_stream::HintStream;
 use crate::treepp::*;
 use crate::utils::limb_to_le_bits;
 
@@ -5,7 +7,8 @@ use crate::utils::limb_to_le_bits;
 pub struct PrecomputedMerkleTreeGadget;
 
 impl PrecomputedMerkleTreeGadget {
-    /// Query the twiddle tree on a point and verify the Merkle tree proof (as a hint).
+    /// Query the twiddle tree on a point and verify the Merkle tree proof (as a hint, which is
+    /// pulled through the stream under the label).
     ///
     /// hint:
     ///   merkle path consisting of entries of the form (mid-element, sibling)
@@ -16,7 +19,7 @@ impl PrecomputedMerkleTreeGadget {
     ///
     /// output:
     ///   v (m31 -- [num_layer] elements)
-    pub fn query_and_verify(logn: usize) -> Script {
+    pub fn query_and_verify(stream: &mut HintStream, label: &str, logn: usize) -> Script {
         let num_layer = logn - 1;
         script! {
             // convert pos into bits and drop the LSB
@@ -24,7 +27,7 @@ impl PrecomputedMerkleTreeGadget {
             OP_DROP
 
             // obtain the leaf element v
-            OP_DEPTH OP_1SUB OP_ROLL
+            { stream.pull(label, StateItem::M31) }
             OP_DUP OP_TOALTSTACK
 
             // compute the current element's hash
@@ -36,14 +39,14 @@ impl PrecomputedMerkleTreeGadget {
             // for every layer
             for _ in 0..num_layer - 1 {
                 // pull the middle element and copy to the altstack
-                OP_DEPTH OP_1SUB OP_ROLL
+                { stream.pull(label, StateItem::M31) }
                 OP_DUP OP_TOALTSTACK
 
                 // stack: root_hash, <bits>, leaf-hash, middle-element
                 // altstack: leaf, middle-element
 
                 // pull the sibling
-                OP_DEPTH OP_1SUB OP_ROLL
+                { stream.pull(label, StateItem::Digest) }
 
                 // stack: root_hash, <bits>, leaf-hash, middle-element, sibling
                 // altstack: leaf, middle-element
@@ -58,7 +61,7 @@ impl PrecomputedMerkleTreeGadget {
             }
 
             // pull the sibling
-            OP_DEPTH OP_1SUB OP_ROLL
+            { stream.pull(label, StateItem::Digest) }
 
             // stack: root_hash, <bit>, leaf-hash, sibling
 
@@ -80,6 +83,7 @@ impl PrecomputedMerkleTreeGadget {
 
 #[cfg(test)]
 mod test {
+    use crate::hint_stream::HintStream;
     use crate::precomputed_merkle_tree::{PrecomputedMerkleTree, PrecomputedMerkleTreeGadget};
     use crate::treepp::*;
     use rand::{Rng, SeedableRng};
@@ -90,7 +94,9 @@ mod test {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
 
         for logn in 12..=20 {
-            let verify_script = PrecomputedMerkleTreeGadget::query_and_verify(logn);
+            let mut stream = HintStream::new();
+            let verify_script =
+                PrecomputedMerkleTreeGadget::query_and_verify(&mut stream, "proof", logn);
             println!("TMT.verify(2^{}) = {} bytes", logn, verify_script.len());
 
             let n_layers = logn - 1;
@@ -102,8 +108,11 @@ mod test {
 
             let twiddle_proof = twiddle_merkle_tree.query(pos as usize);
 
+            let mut writer = stream.writer();
+            twiddle_proof.write(&mut writer, "proof").unwrap();
+
             let script = script! {
-                { twiddle_proof.clone() }
+                { writer.finish_script().unwrap() }
                 { twiddle_merkle_tree.root_hash.to_vec() }
                 { pos }
                 { verify_script.clone() }
@@ -1,14 +1,16 @@
+use crate::chunker::StateValue;
+use crate::hint_stream::{HintStreamError, HintWriter};
 use crate::utils::get_twiddles;
 use crate::utils::num_to_bytes;
 use sha2::{Digest, Sha256};
 use stwo_prover::core::fields::m31::M31;
 use stwo_prover::core::fields::FieldExpOps;
+use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
 
 mod bitcoin_script;
 pub use bitcoin_script::*;
 
 mod constants;
-use crate::treepp::pushable::{Builder, Pushable};
 pub use constants::*;
 
 /// A precomputed data Merkle tree.
@@ -165,24 +167,22 @@ pub struct TwiddleMerkleTreeProof {
     pub siblings: Vec<[u8; 32]>,
 }
 
-impl Pushable for TwiddleMerkleTreeProof {
-    fn bitcoin_script_push(self, builder: Builder) -> Builder {
-        (&self).bitcoin_script_push(builder)
-    }
-}
-
-impl Pushable for &TwiddleMerkleTreeProof {
-    fn bitcoin_script_push(self, mut builder: Builder) -> Builder {
-        builder = self.elements.last().unwrap().bitcoin_script_push(builder);
+impl TwiddleMerkleTreeProof {
+    /// Write the proof as pulled by `PrecomputedMerkleTreeGadget::query_and_verify`, all under the
+    /// label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        writer.write(label, StateValue::M31(*self.elements.last().unwrap()))?;
         for (element, sibling) in self.elements.iter().rev().skip(1).zip(self.siblings.iter()) {
-            builder = element.bitcoin_script_push(builder);
-            builder = sibling.to_vec().bitcoin_script_push(builder);
+            writer.write(label, StateValue::M31(*element))?;
+            writer.write(
+                label,
+                StateValue::Digest(BWSSha256Hash::from(sibling.to_vec())),
+            )?;
         }
-        self.siblings
-            .last()
-            .unwrap()
-            .to_vec()
-            .bitcoin_script_push(builder)
+        writer.write(
+            label,
+            StateValue::Digest(BWSSha256Hash::from(self.siblings.last().unwrap().to_vec())),
+        )
     }
 }
 
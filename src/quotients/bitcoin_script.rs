@@ -0,0 +1,298 @@
+use crate::chunker::StateItem;
+use crate::constraints::ConstraintsGadget;
+use crate::gadget::Gadget;
+use crate::hint_stream::HintStream;
+use crate::treepp::*;
+use crate::utils::qm31_pick_gadget;
+use num_traits::One;
+use rust_bitcoin_m31::{
+    cm31_add, cm31_drop, cm31_equalverify, cm31_fromaltstack, cm31_mul, cm31_mul_m31, cm31_over,
+    cm31_sub, cm31_swap, cm31_toaltstack, qm31_add, qm31_drop, qm31_fromaltstack, qm31_mul,
+    qm31_toaltstack,
+};
+use stwo_prover::core::fields::cm31::CM31;
+
+/// Gadget for the DEEP quotients.
+pub struct QuotientsGadget;
+
+impl QuotientsGadget {
+    /// Compute the DEEP quotient of the queried values of a row, which matches `fri_answer`.
+    ///
+    /// `columns` lists, for each sample batch, the indices of the sampled columns.
+    ///
+    /// Input:
+    /// - z.x, z.y (m31, the domain point)
+    /// - f_0, f_1, ..., f_{n_columns - 1} (m31, the queried values)
+    /// - random_coeff (qm31)
+    /// - for each sample batch, from the last to the first:
+    ///     p.x, p.y (qm31, the sampled point), v_0, ..., v_{m-1} (qm31, the sampled values)
+    ///
+    /// Output:
+    /// - the DEEP quotient (qm31)
+    ///
+    /// Hint (pulled through the stream under the label):
+    /// - FriAnswerHint
+    ///
+    /// Both the numerators and the denominators only have the imaginary part, so each batch adds
+    /// `(sum_i random_coeff^(i+1) * (c * f_i - a_i * z.y - b_i)) * denominator^(-1)`, where the
+    /// inverse of the denominator is a cm31 from the hint.
+    pub fn fri_answer(
+        stream: &mut HintStream,
+        label: &str,
+        columns: &[Vec<usize>],
+        n_columns: usize,
+    ) -> Script {
+        assert!(!columns.is_empty());
+
+        // number of elements of the unprocessed sample batches below each batch
+        let remaining = (0..columns.len())
+            .map(|b| {
+                columns[b + 1..]
+                    .iter()
+                    .map(|c| 8 + 4 * c.len())
+                    .sum::<usize>()
+            })
+            .collect::<Vec<_>>();
+        let k = n_columns;
+
+        script! {
+            for (b, batch_columns) in columns.iter().enumerate() {
+                // compute the denominator and check its inverse from the hint
+                { qm31_pick_gadget(4 * batch_columns.len() + 4) }
+                { qm31_pick_gadget(4 * batch_columns.len() + 4) }
+                { remaining[b] + 4 * batch_columns.len() + k + 21 } OP_PICK
+                { remaining[b] + 4 * batch_columns.len() + k + 21 } OP_PICK
+                { ConstraintsGadget::fast_pair_vanishing() }
+                OP_2DROP
+                { stream.pull(label, StateItem::M31) }
+                { stream.pull(label, StateItem::M31) }
+                cm31_swap cm31_over cm31_mul
+                { CM31::one() } cm31_equalverify
+                cm31_toaltstack
+
+                { ConstraintsGadget::column_line_coeffs(batch_columns.len()) }
+
+                // stack: p.x, c, (a_0, b_0), ..., (a_{m-1}, b_{m-1})
+                for i in (0..batch_columns.len()).rev() {
+                    // compute c * f_i - (a_i * z.y + b_i)
+                    cm31_swap
+                    { remaining[b] + 4 * i + k + 14 } OP_PICK
+                    cm31_mul_m31 cm31_add
+                    { 4 * i + 3 } OP_PICK { 4 * i + 3 } OP_PICK
+                    { remaining[b] + 4 * i + k + 13 - batch_columns[i] } OP_PICK
+                    cm31_mul_m31
+                    cm31_swap cm31_sub
+
+                    // accumulate with random_coeff in the Horner's way
+                    if i == batch_columns.len() - 1 {
+                        0 0 OP_2SWAP
+                    } else {
+                        qm31_fromaltstack
+                        { qm31_pick_gadget(remaining[b] + 4 * i + 12) }
+                        qm31_mul
+                        5 OP_ROLL 5 OP_ROLL cm31_add
+                    }
+                    qm31_toaltstack
+                }
+
+                // drop c and p.x
+                cm31_drop
+                qm31_drop
+
+                // multiply with random_coeff and the inverse of the denominator
+                qm31_fromaltstack
+                { qm31_pick_gadget(remaining[b] + 4) }
+                qm31_mul
+                cm31_fromaltstack
+                cm31_swap cm31_over cm31_mul cm31_toaltstack
+                cm31_mul cm31_fromaltstack
+
+                // accumulate with the previous sample batches
+                if b > 0 {
+                    qm31_fromaltstack
+                    for _ in 0..batch_columns.len() {
+                        { qm31_pick_gadget(remaining[b] + 8) }
+                        qm31_mul
+                    }
+                    qm31_add
+                }
+                qm31_toaltstack
+            }
+
+            // drop random_coeff, the queried values, and the domain point
+            qm31_drop
+            for _ in 0..(n_columns + 2) {
+                OP_DROP
+            }
+            qm31_fromaltstack
+        }
+    }
+}
+
+/// `QuotientsGadget::fri_answer` of the sample batches as a `Gadget`.
+pub struct FriAnswer {
+    /// For each sample batch, the indices of the sampled columns.
+    pub columns: Vec<Vec<usize>>,
+    /// Number of the queried columns.
+    pub n_columns: usize,
+}
+
+impl Gadget for FriAnswer {
+    fn inputs(&self) -> Vec<StateItem> {
+        let mut inputs = vec![StateItem::M31; 2 + self.n_columns];
+        inputs.push(StateItem::QM31);
+        for batch_columns in self.columns.iter().rev() {
+            inputs.push(StateItem::CirclePoint);
+            inputs.extend(vec![StateItem::QM31; batch_columns.len()]);
+        }
+        inputs
+    }
+
+    fn outputs(&self) -> Vec<StateItem> {
+        vec![StateItem::QM31]
+    }
+
+    fn script(&self, stream: &mut HintStream) -> Script {
+        QuotientsGadget::fri_answer(stream, "fri_answer", &self.columns, self.n_columns)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::StateValue;
+    use crate::hint_stream::HintStream;
+    use crate::quotients::{
+        domain_point, fri_answer, FriAnswer, FriAnswerHint, QuotientsGadget, SampleBatch,
+    };
+    use crate::tests_utils::gadget::check_gadget;
+    use crate::tests_utils::report::report_bitcoin_script_size;
+    use crate::treepp::*;
+    use crate::utils::get_rand_qm31;
+    use itertools::Itertools;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use rust_bitcoin_m31::qm31_equalverify;
+    use stwo_prover::core::circle::CirclePoint;
+    use stwo_prover::core::fields::m31::M31;
+
+    #[test]
+    fn test_fri_answer() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let n_columns = 3;
+        let columns = vec![vec![0, 2], vec![1], vec![0, 1, 2]];
+
+        let mut stream = HintStream::new();
+        let fri_answer_script =
+            QuotientsGadget::fri_answer(&mut stream, "fri_answer", &columns, n_columns);
+        report_bitcoin_script_size("Quotients", "fri_answer", fri_answer_script.len());
+
+        for _ in 0..10 {
+            let sample_batches = columns
+                .iter()
+                .map(|batch_columns| SampleBatch {
+                    point: CirclePoint {
+                        x: get_rand_qm31(&mut prng),
+                        y: get_rand_qm31(&mut prng),
+                    },
+                    columns_and_values: batch_columns
+                        .iter()
+                        .map(|&column_index| (column_index, get_rand_qm31(&mut prng)))
+                        .collect(),
+                })
+                .collect_vec();
+            let random_coeff = get_rand_qm31(&mut prng);
+            let z = domain_point(10, prng.gen_range(0..1 << 10));
+            let queried_values = (0..n_columns)
+                .map(|_| M31::reduce(prng.next_u64()))
+                .collect_vec();
+
+            let hint = FriAnswerHint::new(&sample_batches, z);
+            let expected = fri_answer(&sample_batches, random_coeff, z, &queried_values);
+
+            let mut writer = stream.writer();
+            hint.write(&mut writer, "fri_answer").unwrap();
+
+            let script = script! {
+                { writer.finish_script().unwrap() }
+                { z.x }
+                { z.y }
+                for queried_value in queried_values.iter() {
+                    { queried_value }
+                }
+                { random_coeff }
+                for sample_batch in sample_batches.iter().rev() {
+                    { sample_batch.point.x }
+                    { sample_batch.point.y }
+                    for (_, value) in sample_batch.columns_and_values.iter() {
+                        { value }
+                    }
+                }
+                { fri_answer_script.clone() }
+                { expected }
+                qm31_equalverify
+                OP_TRUE
+            };
+
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
+    #[test]
+    fn test_gadgets() {
+        let n_columns = 3;
+        let columns = vec![vec![0, 2], vec![1], vec![0, 1, 2]];
+        let gadget = FriAnswer {
+            columns: columns.clone(),
+            n_columns,
+        };
+
+        check_gadget(&gadget, 10, |inputs, writer| {
+            let [StateValue::M31(x), StateValue::M31(y), rest @ ..] = inputs else {
+                unreachable!()
+            };
+            let z = CirclePoint { x: *x, y: *y };
+            let (queried_values, rest) = rest.split_at(n_columns);
+            let queried_values = queried_values
+                .iter()
+                .map(|value| match value {
+                    StateValue::M31(v) => *v,
+                    _ => unreachable!(),
+                })
+                .collect_vec();
+            let [StateValue::QM31(random_coeff), rest @ ..] = rest else {
+                unreachable!()
+            };
+            let mut rest = rest;
+
+            // the sample batches are from the last to the first
+            let mut sample_batches = vec![];
+            for batch_columns in columns.iter().rev() {
+                let [StateValue::CirclePoint(point), others @ ..] = rest else {
+                    unreachable!()
+                };
+                let (values, others) = others.split_at(batch_columns.len());
+                sample_batches.push(SampleBatch {
+                    point: *point,
+                    columns_and_values: batch_columns
+                        .iter()
+                        .zip(values.iter())
+                        .map(|(&column_index, value)| match value {
+                            StateValue::QM31(v) => (column_index, *v),
+                            _ => unreachable!(),
+                        })
+                        .collect(),
+                });
+                rest = others;
+            }
+            sample_batches.reverse();
+
+            FriAnswerHint::new(&sample_batches, z)
+                .write(writer, "fri_answer")
+                .unwrap();
+            let expected = fri_answer(&sample_batches, *random_coeff, z, &queried_values);
+            vec![StateValue::QM31(expected)]
+        });
+    }
+}
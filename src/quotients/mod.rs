@@ -0,0 +1,337 @@
+use itertools::Itertools;
+use num_traits::{One, Zero};
+use std::collections::BTreeMap;
+use stwo_prover::core::circle::CirclePoint;
+use stwo_prover::core::constraints::pair_vanishing;
+use stwo_prover::core::fields::cm31::CM31;
+use stwo_prover::core::fields::m31::M31;
+use stwo_prover::core::fields::qm31::SecureField;
+use stwo_prover::core::fields::{ComplexConjugate, FieldExpOps};
+use stwo_prover::core::poly::circle::CanonicCoset;
+
+use crate::chunker::StateValue;
+use crate::constraints::fast_pair_vanishing;
+use crate::hint_stream::{HintStreamError, HintWriter};
+use crate::utils::bit_reverse_index;
+
+mod bitcoin_script;
+pub use bitcoin_script::*;
+
+/// The samples of a number of columns at the same point.
+#[derive(Clone, Debug)]
+pub struct SampleBatch {
+    /// The sampled point.
+    pub point: CirclePoint<SecureField>,
+    /// The column indices and the sampled values.
+    pub columns_and_values: Vec<(usize, SecureField)>,
+}
+
+impl SampleBatch {
+    /// Group the samples of the columns by their points, in the order of first appearance.
+    pub fn new_vec(samples: &[&Vec<(CirclePoint<SecureField>, SecureField)>]) -> Vec<Self> {
+        let mut batches: Vec<Self> = vec![];
+        for (column_index, samples) in samples.iter().enumerate() {
+            for (point, value) in samples.iter() {
+                match batches.iter_mut().find(|batch| batch.point == *point) {
+                    Some(batch) => batch.columns_and_values.push((column_index, *value)),
+                    None => batches.push(Self {
+                        point: *point,
+                        columns_and_values: vec![(column_index, *value)],
+                    }),
+                }
+            }
+        }
+        batches
+    }
+}
+
+/// Group the samples of the columns into sample batches, for each column log size in the
+/// decreasing order.
+pub fn sample_batches_by_log_size(
+    column_log_sizes: &[u32],
+    samples: &[Vec<(CirclePoint<SecureField>, SecureField)>],
+) -> Vec<(u32, Vec<SampleBatch>)> {
+    column_log_sizes
+        .iter()
+        .copied()
+        .zip(samples.iter())
+        .sorted_by_key(|(log_size, _)| std::cmp::Reverse(*log_size))
+        .chunk_by(|(log_size, _)| *log_size)
+        .into_iter()
+        .map(|(log_size, tuples)| {
+            let samples = tuples.map(|(_, samples)| samples).collect_vec();
+            (log_size, SampleBatch::new_vec(&samples))
+        })
+        .collect()
+}
+
+/// Compute the point in the canonic circle domain of the given log size for a bit-reversed position.
+pub fn domain_point(log_size: u32, pos: usize) -> CirclePoint<M31> {
+    CanonicCoset::new(log_size)
+        .circle_domain()
+        .at(bit_reverse_index(pos, log_size as usize))
+}
+
+/// Compute the DEEP quotient of the queried values of a row, which follows stwo's `accumulate_row_quotients`.
+pub fn fri_answer(
+    sample_batches: &[SampleBatch],
+    random_coeff: SecureField,
+    domain_point: CirclePoint<M31>,
+    queried_values_at_row: &[M31],
+) -> SecureField {
+    let mut row_accumulator = SecureField::zero();
+    for sample_batch in sample_batches.iter() {
+        let mut numerator = SecureField::zero();
+        let mut alpha = SecureField::one();
+        let mut batch_coeff = SecureField::one();
+
+        for (column_index, value) in sample_batch.columns_and_values.iter() {
+            alpha *= random_coeff;
+            batch_coeff *= random_coeff;
+
+            let point = sample_batch.point;
+            let a = value.complex_conjugate() - *value;
+            let c = point.complex_conjugate().y - point.y;
+            let b = *value * c - a * point.y;
+
+            let queried_value = alpha * c * queried_values_at_row[*column_index];
+            numerator += queried_value - alpha * (a * domain_point.y + b);
+        }
+
+        let denominator = pair_vanishing(
+            sample_batch.point,
+            sample_batch.point.complex_conjugate(),
+            domain_point.into_ef(),
+        );
+
+        row_accumulator = row_accumulator * batch_coeff + numerator / denominator;
+    }
+    row_accumulator
+}
+
+/// Compute the DEEP quotients at the opening positions, for each column log size in the
+/// decreasing order, which follows stwo's `fri_answers`.
+pub fn fri_answers(
+    column_log_sizes: &[u32],
+    samples: &[Vec<(CirclePoint<SecureField>, SecureField)>],
+    random_coeff: SecureField,
+    opening_positions: &BTreeMap<u32, Vec<usize>>,
+    queried_values_per_column: &[Vec<M31>],
+) -> Vec<BTreeMap<usize, SecureField>> {
+    let queried_values_by_log_size = column_log_sizes
+        .iter()
+        .copied()
+        .zip(queried_values_per_column.iter())
+        .sorted_by_key(|(log_size, _)| std::cmp::Reverse(*log_size))
+        .chunk_by(|(log_size, _)| *log_size)
+        .into_iter()
+        .map(|(_, tuples)| tuples.map(|(_, values)| values).collect_vec())
+        .collect_vec();
+
+    sample_batches_by_log_size(column_log_sizes, samples)
+        .iter()
+        .zip(queried_values_by_log_size.iter())
+        .map(|((log_size, sample_batches), queried_values)| {
+            opening_positions[log_size]
+                .iter()
+                .enumerate()
+                .map(|(row, &pos)| {
+                    let queried_values_at_row = queried_values
+                        .iter()
+                        .map(|column| column[row])
+                        .collect_vec();
+                    (
+                        pos,
+                        fri_answer(
+                            sample_batches,
+                            random_coeff,
+                            domain_point(*log_size, pos),
+                            &queried_values_at_row,
+                        ),
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Hint for computing the DEEP quotient of a row in Bitcoin script.
+#[derive(Clone, Debug)]
+pub struct FriAnswerHint {
+    /// Inverses of the denominators (which only have the imaginary part), one for each sample batch.
+    pub denominator_inverses: Vec<CM31>,
+}
+
+impl FriAnswerHint {
+    /// Compute the hint for the sample batches at the domain point.
+    pub fn new(sample_batches: &[SampleBatch], domain_point: CirclePoint<M31>) -> Self {
+        Self {
+            denominator_inverses: sample_batches
+                .iter()
+                .map(|sample_batch| {
+                    fast_pair_vanishing(sample_batch.point, domain_point)
+                        .1
+                        .inverse()
+                })
+                .collect(),
+        }
+    }
+}
+
+impl FriAnswerHint {
+    /// Write the hint as pulled by `QuotientsGadget::fri_answer`, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        for denominator_inverse in self.denominator_inverses.iter() {
+            writer.write(label, StateValue::M31(denominator_inverse.1))?;
+            writer.write(label, StateValue::M31(denominator_inverse.0))?;
+        }
+        Ok(())
+    }
+}
+
+/// Hints for computing the DEEP quotients of a queried pair in Bitcoin script.
+#[derive(Clone, Debug)]
+pub struct FriAnswerPairHint {
+    /// The domain point at the even position (the one at the odd position is its conjugate).
+    pub domain_point: CirclePoint<M31>,
+    /// Hint for the even position.
+    pub even: FriAnswerHint,
+    /// Hint for the odd position.
+    pub odd: FriAnswerHint,
+}
+
+impl FriAnswerPairHint {
+    /// Compute the hints for the pair that contains the position in the domain of the given log size.
+    pub fn new(sample_batches: &[SampleBatch], log_size: u32, pos: usize) -> Self {
+        let domain_point_even = domain_point(log_size, pos & !1);
+        let domain_point_odd = domain_point(log_size, pos | 1);
+        Self {
+            domain_point: domain_point_even,
+            even: FriAnswerHint::new(sample_batches, domain_point_even),
+            odd: FriAnswerHint::new(sample_batches, domain_point_odd),
+        }
+    }
+}
+
+impl FriAnswerPairHint {
+    /// Write the hint as pulled by the verifier, the domain point first and then the hints of the
+    /// even and the odd positions, all under the label.
+    pub fn write(&self, writer: &mut HintWriter, label: &str) -> Result<(), HintStreamError> {
+        writer.write(label, StateValue::M31(self.domain_point.x))?;
+        writer.write(label, StateValue::M31(self.domain_point.y))?;
+        self.even.write(writer, label)?;
+        self.odd.write(writer, label)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::quotients::fri_answers;
+    use crate::utils::get_rand_qm31;
+    use itertools::Itertools;
+    use rand::{Rng, RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use std::collections::BTreeMap;
+    use stwo_prover::core::circle::CirclePoint;
+    use stwo_prover::core::fields::m31::M31;
+    use stwo_prover::core::fri::get_opening_positions;
+    use stwo_prover::core::pcs::quotients::PointSample;
+    use stwo_prover::core::queries::Queries;
+
+    #[test]
+    fn test_fri_answers() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let column_log_sizes = vec![8, 6, 8, 6, 8];
+        let log_sizes = vec![8, 6];
+
+        for _ in 0..10 {
+            // each column is sampled at the first point, and at each of the others with a
+            // probability of one half, so that some batches have several columns
+            let points = (0..3)
+                .map(|_| CirclePoint {
+                    x: get_rand_qm31(&mut prng),
+                    y: get_rand_qm31(&mut prng),
+                })
+                .collect_vec();
+            let mut samples = vec![];
+            for _ in column_log_sizes.iter() {
+                let mut column_samples = vec![];
+                for point in points.iter() {
+                    if column_samples.is_empty() || prng.gen_bool(0.5) {
+                        column_samples.push((*point, get_rand_qm31(&mut prng)));
+                    }
+                }
+                samples.push(column_samples);
+            }
+            let random_coeff = get_rand_qm31(&mut prng);
+
+            let queries = Queries {
+                positions: (0..5)
+                    .map(|_| prng.gen_range(0..1 << log_sizes[0]))
+                    .sorted()
+                    .dedup()
+                    .collect(),
+                log_domain_size: log_sizes[0],
+            };
+            let query_domains = get_opening_positions(&queries, &log_sizes);
+            let opening_positions = query_domains
+                .iter()
+                .map(|(&log_size, domain)| (log_size, domain.flatten()))
+                .collect::<BTreeMap<u32, Vec<usize>>>();
+            let queried_values = column_log_sizes
+                .iter()
+                .map(|log_size| {
+                    (0..opening_positions[log_size].len())
+                        .map(|_| M31::reduce(prng.next_u64()))
+                        .collect_vec()
+                })
+                .collect_vec();
+
+            let answers = fri_answers(
+                &column_log_sizes,
+                &samples,
+                random_coeff,
+                &opening_positions,
+                &queried_values,
+            );
+            let expected = stwo_prover::core::pcs::quotients::fri_answers(
+                column_log_sizes.clone(),
+                &samples
+                    .iter()
+                    .map(|column_samples| {
+                        column_samples
+                            .iter()
+                            .map(|&(point, value)| PointSample { point, value })
+                            .collect_vec()
+                    })
+                    .collect_vec(),
+                random_coeff,
+                get_opening_positions(&queries, &log_sizes),
+                &queried_values,
+            )
+            .unwrap();
+
+            // both are for each log size in the decreasing order, and stwo keeps the values in the
+            // order of the flattened opening positions
+            assert_eq!(answers.len(), expected.len());
+            for ((log_size, answers), mut expected) in log_sizes
+                .iter()
+                .zip(answers.iter())
+                .zip(expected.into_iter())
+            {
+                let expected_values = (&mut expected)
+                    .into_iter()
+                    .flat_map(|eval| eval.values.clone())
+                    .collect_vec();
+                assert_eq!(answers.len(), expected_values.len());
+                for (pos, expected_value) in opening_positions[log_size]
+                    .iter()
+                    .zip(expected_values.into_iter())
+                {
+                    assert_eq!(answers[pos], expected_value);
+                }
+            }
+        }
+    }
+}
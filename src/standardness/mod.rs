@@ -0,0 +1,575 @@
+use crate::analyzer::{analyze_stack_depth, ScriptPosition, StackDepthError, MAX_STACK_SIZE};
+use crate::taproot::{ScriptPathWitness, SpendError};
+use crate::treepp::*;
+use bitcoin::absolute::LockTime;
+use bitcoin::opcodes::all::*;
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::Instruction;
+use bitcoin::taproot::{LeafVersion, TapLeafHash};
+use bitcoin::transaction::Version;
+use bitcoin::Transaction;
+use bitcoin_scriptexec::{Exec, ExecCtx, Options, TxTemplate};
+use std::fmt::{Display, Formatter};
+
+/// The maximum size of a stack element, enforced by consensus.
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+
+/// The maximum weight of a standard transaction.
+pub const MAX_STANDARD_TX_WEIGHT: u64 = 400_000;
+
+/// A violation of the tapscript rules by a leaf script, its witness, or its hints.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Violation {
+    /// A witness element is larger than `MAX_SCRIPT_ELEMENT_SIZE` (consensus).
+    WitnessElementTooLarge {
+        /// Index of the element, from the bottom of the stack.
+        index: usize,
+        /// Size of the element.
+        size: usize,
+    },
+    /// A push in the script is larger than `MAX_SCRIPT_ELEMENT_SIZE` (consensus).
+    PushTooLarge {
+        /// Position of the push.
+        position: ScriptPosition,
+        /// Size of the pushed element.
+        size: usize,
+    },
+    /// The witness has more than `MAX_STACK_SIZE` elements (consensus).
+    TooManyWitnessElements(usize),
+    /// The stack and the altstack may hold more than `MAX_STACK_SIZE` elements (consensus).
+    StackLimitExceeded {
+        /// Peak number of the elements.
+        max_total: usize,
+        /// Position where the peak is first reached.
+        position: ScriptPosition,
+    },
+    /// The stack depths of the script cannot be analyzed.
+    Analysis(StackDepthError),
+    /// A push does not use the shortest encoding, against MINIMALDATA (policy).
+    NonMinimalPush(ScriptPosition),
+    /// The transaction is heavier than `MAX_STANDARD_TX_WEIGHT` (policy).
+    WeightExceeded(u64),
+    /// An element used as a number by the opcode at the position does not use the shortest
+    /// encoding, against MINIMALDATA (policy).
+    NonMinimalNumber(ScriptPosition),
+    /// The argument of the `OP_IF` or `OP_NOTIF` at the position is neither empty nor `0x01`,
+    /// against MINIMALIF (consensus in tapscript).
+    NonMinimalIf(ScriptPosition),
+    /// The witness has an annex (policy).
+    Annex,
+    /// The input cannot be spent through a leaf script (consensus).
+    InvalidSpend(SpendError),
+}
+
+impl Violation {
+    /// Return whether the violation makes the spend invalid, rather than only non-standard.
+    pub fn is_consensus(&self) -> bool {
+        !matches!(
+            self,
+            Violation::NonMinimalPush(_)
+                | Violation::WeightExceeded(_)
+                | Violation::NonMinimalNumber(_)
+                | Violation::Annex
+        )
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Violation::WitnessElementTooLarge { index, size } => write!(
+                f,
+                "witness element {} has {} bytes, more than {}",
+                index, size, MAX_SCRIPT_ELEMENT_SIZE
+            ),
+            Violation::PushTooLarge { position, size } => write!(
+                f,
+                "push at {} has {} bytes, more than {}",
+                position, size, MAX_SCRIPT_ELEMENT_SIZE
+            ),
+            Violation::TooManyWitnessElements(n) => write!(
+                f,
+                "the witness has {} elements, more than {}",
+                n, MAX_STACK_SIZE
+            ),
+            Violation::StackLimitExceeded {
+                max_total,
+                position,
+            } => write!(
+                f,
+                "{} stack elements at {}, more than {}",
+                max_total, position, MAX_STACK_SIZE
+            ),
+            Violation::Analysis(err) => write!(f, "{}", err),
+            Violation::NonMinimalPush(position) => write!(f, "non-minimal push at {}", position),
+            Violation::WeightExceeded(weight) => write!(
+                f,
+                "the transaction weighs {}, more than {}",
+                weight, MAX_STANDARD_TX_WEIGHT
+            ),
+            Violation::NonMinimalNumber(position) => {
+                write!(f, "non-minimal number used at {}", position)
+            }
+            Violation::NonMinimalIf(position) => {
+                write!(f, "non-minimal conditional argument at {}", position)
+            }
+            Violation::Annex => write!(f, "the witness has an annex"),
+            Violation::InvalidSpend(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+/// Check the pushes of a script: each must fit in a stack element and, for MINIMALDATA, use the
+/// shortest encoding.
+///
+/// A script of hints, such as `push_state(&hints)`, is checked this way before broadcast, so
+/// that raw byte strings that should be pushed as small integers are caught.
+pub fn check_pushes(script: &Script) -> Vec<Violation> {
+    let bytes = script.as_bytes();
+
+    let mut violations = vec![];
+    for (index, (offset, instruction)) in script.instruction_indices().enumerate() {
+        let position = ScriptPosition { index, offset };
+        let Ok(Instruction::PushBytes(push)) = instruction else {
+            continue;
+        };
+        let data = push.as_bytes();
+
+        if data.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            violations.push(Violation::PushTooLarge {
+                position,
+                size: data.len(),
+            });
+        }
+
+        // the opcode that a minimal push of the data starts with
+        let minimal_opcode = match data {
+            [] => 0x00,
+            // `OP_1NEGATE` and `OP_1` to `OP_16`
+            [0x81] => 0x4f,
+            [v @ 1..=16] => 0x50 + *v,
+            _ if data.len() <= 75 => data.len() as u8,
+            _ if data.len() <= 0xff => 0x4c,
+            _ if data.len() <= 0xffff => 0x4d,
+            _ => 0x4e,
+        };
+        if bytes[offset] != minimal_opcode {
+            violations.push(Violation::NonMinimalPush(position));
+        }
+    }
+    violations
+}
+
+/// The number of the elements on the top of the stack that an opcode reads as numbers, from the
+/// top.
+fn numeric_arguments(opcode: Opcode) -> &'static [usize] {
+    match opcode {
+        OP_PICK | OP_ROLL | OP_1ADD | OP_1SUB | OP_NEGATE | OP_ABS | OP_NOT | OP_0NOTEQUAL => &[0],
+        OP_ADD
+        | OP_SUB
+        | OP_BOOLAND
+        | OP_BOOLOR
+        | OP_NUMEQUAL
+        | OP_NUMEQUALVERIFY
+        | OP_NUMNOTEQUAL
+        | OP_LESSTHAN
+        | OP_GREATERTHAN
+        | OP_LESSTHANOREQUAL
+        | OP_GREATERTHANOREQUAL
+        | OP_MIN
+        | OP_MAX => &[0, 1],
+        OP_WITHIN => &[0, 1, 2],
+        // the counter is below the public key
+        OP_CHECKSIGADD => &[1],
+        // the lock time and the sequence are numbers of up to 5 bytes
+        OP_CLTV | OP_CSV => &[0],
+        _ => &[],
+    }
+}
+
+/// Return whether a number is encoded with the fewest bytes, without a redundant sign byte.
+fn is_minimal_number(data: &[u8]) -> bool {
+    match data {
+        [] => true,
+        [.., last] if last & 0x7f != 0 => true,
+        [.., before_last, _] => before_last & 0x80 != 0,
+        [_] => false,
+    }
+}
+
+/// Execute a leaf script with its witness, given as the stack elements from the bottom to the
+/// top, and check that the elements read as numbers and the arguments of the conditionals are
+/// minimally encoded.
+///
+/// The script is executed without a transaction, so the execution, and the check, stops at a
+/// failing signature check as well as at the first violation.
+pub fn check_execution(script: &Script, elements: &[Vec<u8>]) -> Vec<Violation> {
+    let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+    let Ok(mut exec) = Exec::new(
+        ExecCtx::Tapscript,
+        Options::default(),
+        TxTemplate {
+            tx: Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![],
+                output: vec![],
+            },
+            prevouts: vec![],
+            input_idx: 0,
+            taproot_annex_scriptleaf: Some((leaf_hash, None)),
+        },
+        script.clone(),
+        elements.to_vec(),
+    ) else {
+        // the witness is already reported by `check_leaf`
+        return vec![];
+    };
+
+    // whether each open conditional executes its current branch
+    let mut conditions: Vec<bool> = vec![];
+    let mut index = 0;
+    loop {
+        let position = ScriptPosition {
+            index,
+            offset: script.len() - exec.remaining_script().len(),
+        };
+        let executing = conditions.iter().all(|&condition| condition);
+        let stack = exec.stack();
+        let top = |depth: usize| (depth < stack.len()).then(|| stack.get(stack.len() - 1 - depth));
+
+        if let Some(Ok(Instruction::Op(opcode))) = exec.remaining_script().instructions().next() {
+            match opcode {
+                OP_IF | OP_NOTIF if executing => match top(0).as_deref() {
+                    Some([]) => conditions.push(opcode == OP_NOTIF),
+                    Some([1]) => conditions.push(opcode == OP_IF),
+                    Some(_) => return vec![Violation::NonMinimalIf(position)],
+                    // the execution fails on the empty stack
+                    None => {}
+                },
+                OP_IF | OP_NOTIF => conditions.push(false),
+                OP_ELSE => {
+                    if let Some(condition) = conditions.last_mut() {
+                        *condition = !*condition;
+                    }
+                }
+                OP_ENDIF => {
+                    conditions.pop();
+                }
+                _ if executing => {
+                    let non_minimal = numeric_arguments(opcode)
+                        .iter()
+                        .any(|&depth| top(depth).is_some_and(|elem| !is_minimal_number(&elem)));
+                    if non_minimal {
+                        return vec![Violation::NonMinimalNumber(position)];
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if exec.exec_next().is_err() {
+            break;
+        }
+        index += 1;
+    }
+    vec![]
+}
+
+/// Check a leaf script and its witness, given as the stack elements from the bottom to the top
+/// without the script and the control block, against the tapscript rules.
+///
+/// Besides the static checks, the script is executed with `check_execution`.
+pub fn check_leaf(script: &Script, elements: &[Vec<u8>]) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    for (index, elem) in elements.iter().enumerate() {
+        if elem.len() > MAX_SCRIPT_ELEMENT_SIZE {
+            violations.push(Violation::WitnessElementTooLarge {
+                index,
+                size: elem.len(),
+            });
+        }
+    }
+    if elements.len() > MAX_STACK_SIZE {
+        violations.push(Violation::TooManyWitnessElements(elements.len()));
+    }
+
+    violations.extend(check_pushes(script));
+
+    match analyze_stack_depth(script, elements.len()) {
+        Ok(_) => {}
+        Err(StackDepthError::LimitExceeded(report)) => {
+            violations.push(Violation::StackLimitExceeded {
+                max_total: report.max_total,
+                position: report.max_total_at,
+            })
+        }
+        Err(err) => violations.push(Violation::Analysis(err)),
+    }
+
+    violations.extend(check_execution(script, elements));
+
+    violations
+}
+
+/// Check the script path spend of an input of a transaction, whose witness ends with the leaf
+/// script and the control block, and the weight of the transaction.
+pub fn check_transaction(tx: &Transaction, input_idx: usize) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    match tx.input.get(input_idx) {
+        None => violations.push(Violation::InvalidSpend(SpendError::InputIndexOutOfRange)),
+        Some(input) => match ScriptPathWitness::parse(&input.witness) {
+            Err(err) => violations.push(Violation::InvalidSpend(err)),
+            Ok(witness) => {
+                violations.extend(check_leaf(&witness.script, &witness.stack));
+                if witness.annex.is_some() {
+                    violations.push(Violation::Annex);
+                }
+            }
+        },
+    }
+
+    let weight = tx.weight().to_wu();
+    if weight > MAX_STANDARD_TX_WEIGHT {
+        violations.push(Violation::WeightExceeded(weight));
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod test {
+    use crate::analyzer::ScriptPosition;
+    use crate::analyzer::MAX_STACK_SIZE;
+    use crate::chunker::push_state;
+    use crate::covenant::CovenantChain;
+    use crate::fibonacci::FibonacciVerifierGadget;
+    use crate::standardness::{
+        check_execution, check_leaf, check_pushes, check_transaction, Violation,
+        MAX_SCRIPT_ELEMENT_SIZE, MAX_STANDARD_TX_WEIGHT,
+    };
+    use crate::taproot::{unspendable_internal_key, SpendError, TaprootOutput};
+    use crate::tests_utils::fibonacci::{hint_and_channel, hint_stream};
+    use crate::treepp::*;
+    use crate::verifier::VerifierConfig;
+    use crate::OP_HINT;
+    use bitcoin::hashes::Hash;
+    use bitcoin::taproot::TAPROOT_ANNEX_PREFIX;
+    use bitcoin::{Amount, OutPoint, TxOut, Txid, Witness};
+
+    #[test]
+    fn test_check_pushes() {
+        // pushes from the builder are minimal
+        let script = script! {
+            0 1 16 { -1 } 17 { vec![0x80u8] } { vec![0x01u8, 0x02] } { vec![0xaau8; 100] }
+            { vec![0xaau8; 300] }
+        };
+        assert_eq!(check_pushes(&script), vec![]);
+
+        // a single byte that should be pushed as a small integer, and a direct push written as
+        // `OP_PUSHDATA1`
+        let script = Script::from_bytes(vec![0x01, 0x05, 0x01, 0x81, 0x4c, 0x02, 0xaa, 0xbb, 0x51]);
+        let violations = check_pushes(&script);
+        assert_eq!(violations.len(), 3);
+        assert!(violations
+            .iter()
+            .all(|violation| matches!(violation, Violation::NonMinimalPush(_))));
+        assert!(violations.iter().all(|violation| !violation.is_consensus()));
+    }
+
+    #[test]
+    fn test_check_leaf() {
+        let script = script! {
+            OP_HINT OP_HINT OP_EQUAL
+        };
+        assert_eq!(check_leaf(&script, &[vec![1], vec![1]]), vec![]);
+
+        let violations = check_leaf(&script, &[vec![0; MAX_SCRIPT_ELEMENT_SIZE + 1], vec![1]]);
+        assert_eq!(
+            violations,
+            vec![Violation::WitnessElementTooLarge {
+                index: 0,
+                size: MAX_SCRIPT_ELEMENT_SIZE + 1
+            }]
+        );
+        assert!(violations[0].is_consensus());
+
+        let violations = check_leaf(&script! { OP_DUP }, &vec![vec![1]; MAX_STACK_SIZE]);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(
+            violations[0],
+            Violation::StackLimitExceeded {
+                max_total: 1001,
+                ..
+            }
+        ));
+
+        let violations = check_leaf(&script! { OP_DROP }, &vec![vec![1]; MAX_STACK_SIZE + 1]);
+        assert_eq!(violations.len(), 2);
+        assert!(violations.contains(&Violation::TooManyWitnessElements(MAX_STACK_SIZE + 1)));
+    }
+
+    #[test]
+    fn test_check_execution() {
+        let script = script! {
+            OP_HINT OP_HINT OP_ADD
+            OP_HINT OP_IF
+                OP_1ADD
+            OP_ENDIF
+        };
+        assert_eq!(
+            check_execution(&script, &[vec![1], vec![2], vec![1]]),
+            vec![]
+        );
+        assert_eq!(
+            check_execution(&script, &[vec![1], vec![2], vec![]]),
+            vec![]
+        );
+
+        // 1 with a redundant sign byte, read as a number by `OP_ADD` (the fourth instruction,
+        // after the three of `OP_HINT`)
+        let violations = check_execution(&script, &[vec![1, 0], vec![2], vec![1]]);
+        assert_eq!(
+            violations,
+            vec![Violation::NonMinimalNumber(ScriptPosition {
+                index: 6,
+                offset: 6
+            })]
+        );
+        assert!(!violations[0].is_consensus());
+
+        // a true value other than 0x01 as the argument of `OP_IF`
+        let violations = check_execution(&script, &[vec![1], vec![2], vec![2]]);
+        assert_eq!(
+            violations,
+            vec![Violation::NonMinimalIf(ScriptPosition {
+                index: 10,
+                offset: 10
+            })]
+        );
+        assert!(violations[0].is_consensus());
+
+        // the lock time and the sequence are read as numbers of up to 5 bytes, which are checked
+        // before the execution fails without a transaction
+        for script in [
+            script! { OP_HINT OP_CHECKLOCKTIMEVERIFY },
+            script! { OP_HINT OP_CHECKSEQUENCEVERIFY },
+        ] {
+            assert_eq!(
+                check_execution(&script, &[vec![1, 0, 0, 0, 0]]),
+                vec![Violation::NonMinimalNumber(ScriptPosition {
+                    index: 3,
+                    offset: 3
+                })]
+            );
+            assert_eq!(check_execution(&script, &[vec![0, 0, 0, 0, 1]]), vec![]);
+        }
+
+        // the argument of a skipped `OP_IF` is not checked
+        let script = script! {
+            OP_HINT OP_NOTIF
+                OP_HINT OP_IF OP_ENDIF
+            OP_ENDIF
+            OP_TRUE
+        };
+        assert_eq!(check_execution(&script, &[vec![1], vec![2]]), vec![]);
+    }
+
+    #[test]
+    fn test_check_transaction() {
+        let output = TaprootOutput::from_scripts(
+            vec![
+                ("small".to_string(), script! { OP_HINT OP_DROP OP_TRUE }),
+                (
+                    "large".to_string(),
+                    script! {
+                        for _ in 0..1000 {
+                            { vec![0xaau8; 400] } OP_DROP
+                        }
+                        OP_TRUE
+                    },
+                ),
+            ],
+            unspendable_internal_key(),
+        )
+        .unwrap();
+        let payouts = vec![TxOut {
+            value: Amount::from_sat(1000),
+            script_pubkey: Script::from_bytes(vec![0x51]),
+        }];
+
+        let tx = output.spend_leaf(
+            0,
+            OutPoint::new(Txid::all_zeros(), 0),
+            payouts.clone(),
+            vec![vec![1]],
+        );
+        assert_eq!(check_transaction(&tx, 0), vec![]);
+
+        let tx = output.spend_leaf(1, OutPoint::new(Txid::all_zeros(), 0), payouts, vec![]);
+        let violations = check_transaction(&tx, 0);
+        assert_eq!(violations.len(), 1);
+        let Violation::WeightExceeded(weight) = violations[0] else {
+            panic!("the transaction must be too heavy");
+        };
+        assert!(weight > MAX_STANDARD_TX_WEIGHT);
+
+        let mut tx = output.spend_leaf(
+            0,
+            OutPoint::new(Txid::all_zeros(), 0),
+            payouts.clone(),
+            vec![vec![1]],
+        );
+        let mut witness = tx.input[0].witness.to_vec();
+        witness.push(vec![TAPROOT_ANNEX_PREFIX, 0x00]);
+        tx.input[0].witness = Witness::from_slice(&witness);
+        assert_eq!(check_transaction(&tx, 0), vec![Violation::Annex]);
+
+        // malformed spends are reported rather than panicking
+        assert_eq!(
+            check_transaction(&tx, 1),
+            vec![Violation::InvalidSpend(SpendError::InputIndexOutOfRange)]
+        );
+        tx.input[0].witness = Witness::from_slice(&[vec![1u8]]);
+        assert_eq!(
+            check_transaction(&tx, 0),
+            vec![Violation::InvalidSpend(SpendError::NotScriptPathSpend)]
+        );
+    }
+
+    #[test]
+    fn test_verifier_standardness() {
+        let (hint, claim, channel) = hint_and_channel(5);
+        let hints = hint.to_elements(&hint_stream(5, claim, &channel)).unwrap();
+        assert_eq!(check_pushes(&push_state(&hints)), vec![]);
+
+        let stages = FibonacciVerifierGadget::run_verifier_stages(
+            5,
+            claim,
+            &channel,
+            VerifierConfig::default(),
+        )
+        .unwrap();
+        let chain = CovenantChain::new(
+            stages,
+            unspendable_internal_key(),
+            Amount::from_sat(10_000),
+            Script::from_bytes(vec![0x51]),
+        )
+        .unwrap();
+        let txs = chain
+            .build_transactions(
+                OutPoint::new(Txid::all_zeros(), 0),
+                Amount::from_sat(100_000_000),
+                hints,
+            )
+            .unwrap();
+
+        for (tx, stage) in txs.iter().zip(chain.stages.iter()) {
+            // each stage fits the standardness rules, and its hints are minimally encoded
+            assert_eq!(check_transaction(tx, 0), vec![], "{}", stage.name);
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use crate::chunker::Stage;
+use crate::treepp::*;
+use bitcoin::absolute::LockTime;
+use bitcoin::secp256k1::{Secp256k1, XOnlyPublicKey};
+use bitcoin::taproot::{
+    ControlBlock, LeafVersion, TapLeafHash, TaprootBuilder, TaprootBuilderError, TaprootSpendInfo,
+    TAPROOT_ANNEX_PREFIX,
+};
+use bitcoin::transaction::Version;
+use bitcoin::{OutPoint, Sequence, Transaction, TxIn, TxOut, Witness};
+use bitcoin_scriptexec::{Exec, ExecCtx, ExecError, Options, TxTemplate};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// The x coordinate of the NUMS point H from BIP-341, whose discrete logarithm is unknown.
+const NUMS_INTERNAL_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// Return the internal key for which no one knows the secret key, so that the output can only be
+/// spent through its leaves.
+pub fn unspendable_internal_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_str(NUMS_INTERNAL_KEY).unwrap()
+}
+
+/// A leaf of the taproot output.
+#[derive(Clone, Debug)]
+pub struct TaprootLeaf {
+    /// Name of the leaf, such as the name of its stage.
+    pub name: String,
+    /// The tapscript of the stage.
+    pub script: Script,
+    /// The control block for spending the output through this leaf.
+    pub control_block: ControlBlock,
+}
+
+/// A taproot output that locks coins to its leaves, each of which can spend the output alone.
+///
+/// The stages of a script that hand off a state must not be sibling leaves of an output: the
+/// commitment of the input state that a stage reopens comes from its witness, so anyone could
+/// spend the output through any stage, such as the first one, without running the others. Such
+/// stages are chained by `CovenantChain` instead.
+#[derive(Clone, Debug)]
+pub struct TaprootOutput {
+    /// The spending information of the output, including the internal key and the Merkle root.
+    pub spend_info: TaprootSpendInfo,
+    /// The script pubkey of the output.
+    pub script_pubkey: Script,
+    /// The leaves, in the order of their scripts.
+    pub leaves: Vec<TaprootLeaf>,
+}
+
+/// An error when building the taproot output of a stage.
+#[derive(Debug)]
+pub enum TaprootError {
+    /// The stage reopens an input state or hands off an output state, which only a covenant chain
+    /// can bind to the previous or the next stage.
+    StateHandOff,
+    /// The taproot tree could not be built.
+    Builder(TaprootBuilderError),
+}
+
+impl Display for TaprootError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaprootError::StateHandOff => {
+                write!(
+                    f,
+                    "the stage hands off a state, which needs a covenant chain"
+                )
+            }
+            TaprootError::Builder(err) => write!(f, "taproot tree error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for TaprootError {}
+
+impl From<TaprootBuilderError> for TaprootError {
+    fn from(err: TaprootBuilderError) -> Self {
+        TaprootError::Builder(err)
+    }
+}
+
+impl TaprootOutput {
+    /// Build the output with the stage as its single leaf under the internal key.
+    ///
+    /// The stage must run the whole script, without an input or an output state, see the doc of
+    /// `TaprootOutput` for why.
+    ///
+    /// The output can be spent with the key path by whoever knows the secret key of the internal
+    /// key, which is why `unspendable_internal_key` should be used unless this is intended.
+    pub fn new(stage: &Stage, internal_key: XOnlyPublicKey) -> Result<Self, TaprootError> {
+        if stage.input_state_size > 0 || stage.output_state_size.is_some() {
+            return Err(TaprootError::StateHandOff);
+        }
+        Ok(Self::from_scripts(
+            vec![(stage.name.clone(), stage.script())],
+            internal_key,
+        )?)
+    }
+
+    /// Build the output for the named leaf scripts, with a balanced tree under the internal key.
+    ///
+    /// Each leaf can spend the output alone, so each script must be sound on its own.
+    pub fn from_scripts(
+        scripts: Vec<(String, Script)>,
+        internal_key: XOnlyPublicKey,
+    ) -> Result<Self, TaprootBuilderError> {
+        // with equal weights, the Huffman tree is a complete tree with balanced depths
+        let builder = TaprootBuilder::with_huffman_tree(
+            scripts.iter().map(|(_, script)| (1, script.clone())),
+        )?;
+
+        let secp = Secp256k1::verification_only();
+        let spend_info = builder
+            .finalize(&secp, internal_key)
+            .expect("a Huffman tree is always complete");
+
+        let leaves = scripts
+            .into_iter()
+            .map(|(name, script)| {
+                let control_block = spend_info
+                    .control_block(&(script.clone(), LeafVersion::TapScript))
+                    .expect("every script is a leaf of the tree");
+                TaprootLeaf {
+                    name,
+                    script,
+                    control_block,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            script_pubkey: Script::new_p2tr_tweaked(spend_info.output_key()),
+            spend_info,
+            leaves,
+        })
+    }
+
+    /// Build a transaction that spends `previous_output`, which is locked to this output, through
+    /// the leaf of the given index, with the stack elements (from the bottom to the top) in the
+    /// witness before the leaf script and its control block.
+    pub fn spend_leaf(
+        &self,
+        leaf_index: usize,
+        previous_output: OutPoint,
+        outputs: Vec<TxOut>,
+        elements: Vec<Vec<u8>>,
+    ) -> Transaction {
+        let leaf = &self.leaves[leaf_index];
+
+        let mut witness = elements;
+        witness.push(leaf.script.to_bytes());
+        witness.push(leaf.control_block.serialize());
+
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::from_slice(&witness),
+                ..Default::default()
+            }],
+            output: outputs,
+        }
+    }
+}
+
+/// The result of executing the tapscript that spends an input.
+#[derive(Clone, Debug)]
+pub struct SpendResult {
+    /// Whether the leaf is committed in the spent output and its execution succeeds.
+    pub success: bool,
+    /// Whether the control block proves that the leaf is committed in the spent output.
+    pub leaf_committed: bool,
+    /// The error, if the execution fails.
+    pub error: Option<ExecError>,
+    /// Number of the elements left on the stack.
+    pub final_stack_len: usize,
+}
+
+/// Error when an input cannot be executed as a spend through a tapscript leaf.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SpendError {
+    /// The input index is out of the range of the inputs or of the spent outputs.
+    InputIndexOutOfRange,
+    /// The witness, without the annex, does not have the leaf script and the control block.
+    NotScriptPathSpend,
+    /// The control block cannot be decoded.
+    InvalidControlBlock,
+    /// The executor rejects the witness before running the script, such as when it has too many
+    /// elements.
+    InvalidWitness,
+}
+
+impl Display for SpendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpendError::InputIndexOutOfRange => write!(f, "the input index is out of range"),
+            SpendError::NotScriptPathSpend => write!(f, "the witness is not a script path spend"),
+            SpendError::InvalidControlBlock => write!(f, "the control block is invalid"),
+            SpendError::InvalidWitness => write!(f, "the witness is rejected by the executor"),
+        }
+    }
+}
+
+impl std::error::Error for SpendError {}
+
+/// The parts of the witness of a script path spend.
+pub(crate) struct ScriptPathWitness {
+    /// The stack elements, from the bottom to the top.
+    pub(crate) stack: Vec<Vec<u8>>,
+    /// The leaf script.
+    pub(crate) script: Script,
+    /// The control block.
+    pub(crate) control_block: ControlBlock,
+    /// The annex, if any.
+    pub(crate) annex: Option<Vec<u8>>,
+}
+
+impl ScriptPathWitness {
+    /// Split the witness of a script path spend.
+    ///
+    /// As in BIP-341, the last element of a witness with at least two elements is the annex if
+    /// it starts with `TAPROOT_ANNEX_PREFIX`.
+    pub(crate) fn parse(witness: &Witness) -> Result<Self, SpendError> {
+        let elements = witness.iter().collect::<Vec<&[u8]>>();
+        let (elements, annex) = match elements.split_last() {
+            Some((last, rest))
+                if !rest.is_empty() && last.first() == Some(&TAPROOT_ANNEX_PREFIX) =>
+            {
+                (rest, Some(last.to_vec()))
+            }
+            _ => (elements.as_slice(), None),
+        };
+        let [stack @ .., script, control_block] = elements else {
+            return Err(SpendError::NotScriptPathSpend);
+        };
+
+        Ok(Self {
+            stack: stack.iter().map(|elem| elem.to_vec()).collect(),
+            script: Script::from_bytes(script.to_vec()),
+            control_block: ControlBlock::decode(control_block)
+                .map_err(|_| SpendError::InvalidControlBlock)?,
+            annex,
+        })
+    }
+}
+
+/// Execute the tapscript that spends an input of a transaction, with the transaction as the
+/// context of the signature checks, after checking that the leaf is committed in the spent output.
+///
+/// As in BIP-341, the last element of a witness with at least two elements is the annex if it
+/// starts with `TAPROOT_ANNEX_PREFIX`, in which case it is not a stack element but is covered by the
+/// signatures.
+pub fn execute_spend(
+    tx: &Transaction,
+    prevouts: &[TxOut],
+    input_idx: usize,
+) -> Result<SpendResult, SpendError> {
+    let (input, spent) = tx
+        .input
+        .get(input_idx)
+        .zip(prevouts.get(input_idx))
+        .ok_or(SpendError::InputIndexOutOfRange)?;
+
+    let ScriptPathWitness {
+        stack,
+        script,
+        control_block,
+        annex,
+    } = ScriptPathWitness::parse(&input.witness)?;
+
+    let leaf_hash = TapLeafHash::from_script(&script, LeafVersion::TapScript);
+
+    let spent_script_pubkey = &spent.script_pubkey;
+    let leaf_committed = spent_script_pubkey.is_p2tr()
+        && control_block.leaf_version == LeafVersion::TapScript
+        && XOnlyPublicKey::from_slice(&spent_script_pubkey.as_bytes()[2..]).map_or(
+            false,
+            |output_key| {
+                control_block.verify_taproot_commitment(
+                    &Secp256k1::verification_only(),
+                    output_key,
+                    &script,
+                )
+            },
+        );
+
+    let mut exec = Exec::new(
+        ExecCtx::Tapscript,
+        Options::default(),
+        TxTemplate {
+            tx: tx.clone(),
+            prevouts: prevouts.to_vec(),
+            input_idx,
+            taproot_annex_scriptleaf: Some((leaf_hash, annex)),
+        },
+        script,
+        stack,
+    )
+    .map_err(|_| SpendError::InvalidWitness)?;
+
+    loop {
+        if exec.exec_next().is_err() {
+            break;
+        }
+    }
+    let res = exec.result().unwrap();
+    Ok(SpendResult {
+        success: leaf_committed && res.success,
+        leaf_committed,
+        error: res.error.clone(),
+        final_stack_len: res.final_stack.len(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::Stage;
+    use crate::fibonacci::FibonacciVerifierGadget;
+    use crate::taproot::{
+        execute_spend, unspendable_internal_key, SpendError, TaprootError, TaprootOutput,
+    };
+    use crate::tests_utils::fibonacci::hint_and_verifier;
+    use crate::treepp::*;
+    use crate::verifier::VerifierConfig;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+    use bitcoin::taproot::TAPROOT_ANNEX_PREFIX;
+    use bitcoin::{Amount, OutPoint, TxOut, Txid, Witness};
+    use rand::{RngCore, SeedableRng};
+    use rand_chacha::ChaCha20Rng;
+    use stwo_prover::core::channel::{BWSSha256Channel, Channel};
+    use stwo_prover::core::fields::m31::M31;
+    use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+
+    #[test]
+    fn test_taproot_output() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        let mut digest = [0u8; 32];
+        prng.fill_bytes(&mut digest);
+        let channel = BWSSha256Channel::new(BWSSha256Hash::from(digest.to_vec()));
+        let claim = M31::reduce(prng.next_u64());
+
+        // the whole verifier as a single stage
+        let stage = Stage {
+            name: "verifier".to_string(),
+            input_state_size: 0,
+            output_state_size: None,
+            body: FibonacciVerifierGadget::run_verifier(
+                5,
+                claim,
+                &channel,
+                VerifierConfig::default(),
+            )
+            .unwrap(),
+        };
+
+        let secp = Secp256k1::new();
+
+        let output = TaprootOutput::new(&stage, unspendable_internal_key()).unwrap();
+        assert_eq!(output.leaves.len(), 1);
+        assert_eq!(
+            output.script_pubkey,
+            Script::new_p2tr_tweaked(output.spend_info.output_key())
+        );
+        assert_eq!(output.spend_info.internal_key(), unspendable_internal_key());
+
+        let leaf = &output.leaves[0];
+        assert_eq!(leaf.name, stage.name);
+        assert_eq!(leaf.script, stage.script());
+        assert!(leaf.control_block.verify_taproot_commitment(
+            &secp,
+            output.spend_info.output_key().to_inner(),
+            &leaf.script
+        ));
+
+        // a different internal key gives a different output with the same leaf
+        let mut secret = [0u8; 32];
+        prng.fill_bytes(&mut secret);
+        let internal_key =
+            PublicKey::from_secret_key(&secp, &SecretKey::from_slice(&secret).unwrap())
+                .x_only_public_key()
+                .0;
+
+        let other_output = TaprootOutput::new(&stage, internal_key).unwrap();
+        assert_eq!(other_output.spend_info.internal_key(), internal_key);
+        assert_ne!(other_output.script_pubkey, output.script_pubkey);
+        assert_eq!(
+            other_output.spend_info.merkle_root(),
+            output.spend_info.merkle_root()
+        );
+        assert!(!leaf.control_block.verify_taproot_commitment(
+            &secp,
+            other_output.spend_info.output_key().to_inner(),
+            &leaf.script
+        ));
+
+        // the stages handing off a state cannot be spent alone
+        let stages = FibonacciVerifierGadget::run_verifier_stages(
+            5,
+            claim,
+            &channel,
+            VerifierConfig::default(),
+        )
+        .unwrap();
+        for stage in stages.iter() {
+            assert!(matches!(
+                TaprootOutput::new(stage, unspendable_internal_key()),
+                Err(TaprootError::StateHandOff)
+            ));
+        }
+
+        // there must be at least one leaf
+        assert!(TaprootOutput::from_scripts(vec![], unspendable_internal_key()).is_err());
+    }
+
+    #[test]
+    fn test_execute_spend() {
+        let output = TaprootOutput::from_scripts(
+            vec![("leaf".to_string(), script! { 2 OP_EQUAL })],
+            unspendable_internal_key(),
+        )
+        .unwrap();
+        let prevouts = [TxOut {
+            value: Amount::from_sat(100_000),
+            script_pubkey: output.script_pubkey.clone(),
+        }];
+        let tx = output.spend_leaf(
+            0,
+            OutPoint::new(Txid::all_zeros(), 0),
+            vec![],
+            vec![vec![2]],
+        );
+
+        let exec_result = execute_spend(&tx, &prevouts, 0).unwrap();
+        assert!(exec_result.success);
+        assert_eq!(exec_result.final_stack_len, 1);
+
+        // the annex is neither a stack element nor the control block
+        let mut tx_with_annex = tx.clone();
+        tx_with_annex.input[0]
+            .witness
+            .push([TAPROOT_ANNEX_PREFIX, 1, 2, 3]);
+        let exec_result = execute_spend(&tx_with_annex, &prevouts, 0).unwrap();
+        assert!(exec_result.leaf_committed);
+        assert!(exec_result.success);
+
+        // the malformed spends are reported rather than executed
+        assert_eq!(
+            execute_spend(&tx, &prevouts, 1).err(),
+            Some(SpendError::InputIndexOutOfRange)
+        );
+
+        let mut malformed = tx.clone();
+        malformed.input[0].witness = Witness::from_slice(&[vec![2u8], vec![TAPROOT_ANNEX_PREFIX]]);
+        assert_eq!(
+            execute_spend(&malformed, &prevouts, 0).err(),
+            Some(SpendError::NotScriptPathSpend)
+        );
+
+        let mut elements = tx.input[0].witness.to_vec();
+        elements.last_mut().unwrap().truncate(10);
+        malformed.input[0].witness = Witness::from_slice(&elements);
+        assert_eq!(
+            execute_spend(&malformed, &prevouts, 0).err(),
+            Some(SpendError::InvalidControlBlock)
+        );
+    }
+
+    #[test]
+    fn test_verifier_spend() {
+        let (hints, verifier) = hint_and_verifier(5);
+
+        let output = TaprootOutput::from_scripts(
+            vec![("verifier".to_string(), verifier)],
+            unspendable_internal_key(),
+        )
+        .unwrap();
+        let funding = TxOut {
+            value: Amount::from_sat(100_000_000),
+            script_pubkey: output.script_pubkey.clone(),
+        };
+        let payouts = vec![TxOut {
+            value: Amount::from_sat(99_000_000),
+            script_pubkey: Script::from_bytes(vec![0x51]),
+        }];
+
+        let tx = output.spend_leaf(
+            0,
+            OutPoint::new(Txid::all_zeros(), 0),
+            payouts.clone(),
+            hints.clone(),
+        );
+
+        let exec_result = execute_spend(&tx, &[funding.clone()], 0).unwrap();
+        assert!(exec_result.success);
+        assert_eq!(exec_result.final_stack_len, 1);
+
+        // a missing hint must make the spend fail
+        let tx = output.spend_leaf(
+            0,
+            OutPoint::new(Txid::all_zeros(), 0),
+            payouts.clone(),
+            hints[1..].to_vec(),
+        );
+        assert!(!execute_spend(&tx, &[funding.clone()], 0).unwrap().success);
+
+        // the leaf must be committed in the spent output
+        let other_output = TaprootOutput::from_scripts(
+            vec![("other".to_string(), script! { OP_TRUE })],
+            unspendable_internal_key(),
+        )
+        .unwrap();
+        let tx = output.spend_leaf(0, OutPoint::new(Txid::all_zeros(), 0), payouts, hints);
+        let exec_result = execute_spend(
+            &tx,
+            &[TxOut {
+                value: funding.value,
+                script_pubkey: other_output.script_pubkey,
+            }],
+            0,
+        )
+        .unwrap();
+        assert!(!exec_result.leaf_committed);
+        assert!(!exec_result.success);
+    }
+}
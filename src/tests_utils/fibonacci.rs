@@ -0,0 +1,68 @@
+//! This module contains the proofs of the Fibonacci example and their hints, for the end-to-end
+//! tests of the verifier.
+use crate::fibonacci::FibonacciVerifierGadget;
+use crate::hint_stream::HintStream;
+use crate::treepp::*;
+use crate::verifier::{verify_with_hints, VerifierConfig, VerifierHints};
+use num_traits::One;
+use stwo_prover::core::channel::{BWSSha256Channel, Channel};
+use stwo_prover::core::fields::m31::{BaseField, M31};
+use stwo_prover::core::fields::FieldExpOps;
+use stwo_prover::core::fields::IntoSlice;
+use stwo_prover::core::prover::prove;
+use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hasher;
+use stwo_prover::core::vcs::hasher::Hasher;
+use stwo_prover::examples::fibonacci::Fibonacci;
+
+/// Return the last value of the trace, where each value is the sum of the squares of the
+/// previous two.
+pub fn claim(log_size: u32) -> M31 {
+    let (mut a, mut b) = (M31::one(), M31::one());
+    for _ in 0..(1 << log_size) - 1 {
+        (a, b) = (b, a.square() + b.square());
+    }
+    a
+}
+
+/// Prove the Fibonacci trace of the given log size and return the hints, the claim, and the
+/// initial channel.
+pub fn hint_and_channel(log_size: u32) -> (VerifierHints, M31, BWSSha256Channel) {
+    let claim = claim(log_size);
+    let fib = Fibonacci::new(log_size, claim);
+
+    let trace = fib.get_trace();
+    let channel = &mut BWSSha256Channel::new(BWSSha256Hasher::hash(BaseField::into_slice(&[fib
+        .air
+        .component
+        .claim])));
+    let proof = prove(&fib.air, channel, vec![trace]).unwrap();
+
+    let channel = &mut BWSSha256Channel::new(BWSSha256Hasher::hash(BaseField::into_slice(&[fib
+        .air
+        .component
+        .claim])));
+    let channel_clone = channel.clone();
+
+    let hint = verify_with_hints(proof, &fib.air, channel, VerifierConfig::default()).unwrap();
+
+    (hint, claim, channel_clone)
+}
+
+/// Return the hints pulled by the verifier for the Fibonacci trace of the given log size.
+pub fn hint_stream(log_size: u32, claim: M31, channel: &BWSSha256Channel) -> HintStream {
+    FibonacciVerifierGadget::hint_stream(log_size, claim, channel, VerifierConfig::default())
+        .unwrap()
+}
+
+/// Return the stack elements of the hints and the verifier script for the Fibonacci trace of the
+/// given log size.
+pub fn hint_and_verifier(log_size: u32) -> (Vec<Vec<u8>>, Script) {
+    let (hint, claim, channel) = hint_and_channel(log_size);
+    let stream = hint_stream(log_size, claim, &channel);
+
+    (
+        hint.to_elements(&stream).unwrap(),
+        FibonacciVerifierGadget::run_verifier(log_size, claim, &channel, VerifierConfig::default())
+            .unwrap(),
+    )
+}
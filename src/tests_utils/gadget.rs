@@ -0,0 +1,116 @@
+//! This module contains a harness for checking gadgets against their reference implementations.
+use crate::chunker::{push_state, StateItem, StateValue};
+use crate::gadget::Gadget;
+use crate::hint_stream::{HintStream, HintWriter};
+use crate::treepp::*;
+use crate::utils::get_rand_qm31;
+use bitcoin_scriptexec::execute_script;
+use itertools::Itertools;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use stwo_prover::core::circle::CirclePoint;
+use stwo_prover::core::fields::m31::M31;
+use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+
+/// Sample a random value of the given type.
+///
+/// The circle points are not on the circle, which does not matter for the gadgets that only
+/// evaluate polynomials over the coordinates.
+pub fn random_value<R: RngCore>(item: StateItem, prng: &mut R) -> StateValue {
+    match item {
+        StateItem::Digest => {
+            let mut digest = [0u8; 32];
+            prng.fill_bytes(&mut digest);
+            StateValue::Digest(BWSSha256Hash::from(digest.to_vec()))
+        }
+        StateItem::M31 => StateValue::M31(M31::reduce(prng.next_u64())),
+        StateItem::QM31 => StateValue::QM31(get_rand_qm31(prng)),
+        StateItem::CirclePoint => StateValue::CirclePoint(CirclePoint {
+            x: get_rand_qm31(prng),
+            y: get_rand_qm31(prng),
+        }),
+        StateItem::Bytes => {
+            let mut bytes = vec![0u8; 1 + (prng.next_u32() % 32) as usize];
+            prng.fill_bytes(&mut bytes);
+            StateValue::Bytes(bytes)
+        }
+    }
+}
+
+/// Run a gadget on random inputs, one run per seed, with the hints that the reference
+/// implementation writes for the inputs through the stream of the gadget, and the outputs that it
+/// returns.
+///
+/// Panic unless the reference writes exactly the hints declared by the script, and the script
+/// consumes exactly the inputs and the hints and leaves exactly the outputs.
+pub fn check_gadget<G: Gadget>(
+    gadget: &G,
+    n_runs: u64,
+    reference: impl Fn(&[StateValue], &mut HintWriter) -> Vec<StateValue>,
+) {
+    check_gadget_with_sampler(gadget, n_runs, |prng, writer| {
+        let inputs = gadget
+            .inputs()
+            .into_iter()
+            .map(|item| random_value(item, prng))
+            .collect_vec();
+        let outputs = reference(&inputs, writer);
+        (inputs, outputs)
+    });
+}
+
+/// Run a gadget as `check_gadget` does, with the inputs, the hints, and the outputs sampled
+/// together, for the gadgets whose inputs cannot be independent, such as a Merkle root and the
+/// position of a leaf.
+pub fn check_gadget_with_sampler<G: Gadget>(
+    gadget: &G,
+    n_runs: u64,
+    sample: impl Fn(&mut ChaCha20Rng, &mut HintWriter) -> (Vec<StateValue>, Vec<StateValue>),
+) {
+    let mut stream = HintStream::new();
+    let script = gadget.script(&mut stream);
+
+    for seed in 0..n_runs {
+        let mut prng = ChaCha20Rng::seed_from_u64(seed);
+
+        let mut writer = stream.writer();
+        let (inputs, outputs) = sample(&mut prng, &mut writer);
+        let hints = writer
+            .finish()
+            .unwrap_or_else(|e| panic!("the reference must write the declared hints: {}", e));
+        assert_eq!(
+            inputs.iter().map(StateValue::item).collect_vec(),
+            gadget.inputs(),
+            "the sampler must return inputs of the declared types"
+        );
+        assert_eq!(
+            outputs.iter().map(StateValue::item).collect_vec(),
+            gadget.outputs(),
+            "the reference must return outputs of the declared types"
+        );
+
+        let elements = |values: &[StateValue]| {
+            values
+                .iter()
+                .flat_map(StateValue::to_elements)
+                .collect_vec()
+        };
+        let n = gadget.n_output_elements();
+
+        let exec_result = execute_script(script! {
+            { push_state(&hints) }
+            { push_state(&elements(&inputs)) }
+            { script.clone() }
+            { push_state(&elements(&outputs)) }
+            for i in 0..n {
+                { n - i } OP_ROLL OP_EQUALVERIFY
+            }
+            OP_DEPTH OP_NOT
+        });
+        assert!(
+            exec_result.success,
+            "the gadget must match its reference implementation (seed {})",
+            seed
+        );
+    }
+}
@@ -1,3 +1,9 @@
+/// This module contains the proofs of the Fibonacci example for the end-to-end tests.
+pub mod fibonacci;
+
+/// This module contains a harness for checking gadgets against their reference implementations.
+pub mod gadget;
+
 #[cfg(not(tarpaulin_include))]
 /// This module contains functions for reporting test results to a CSV file.
 pub mod report;
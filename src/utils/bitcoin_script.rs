@@ -1,4 +1,5 @@
 use crate::treepp::*;
+use crate::utils::limb_to_le_bits;
 
 /// Gadget for trimming away a m31 element to keep only logn bits.
 pub fn trim_m31_gadget(logn: usize) -> Script {
@@ -81,6 +82,15 @@ pub fn dup_m31_vec_gadget(len: usize) -> Script {
     }
 }
 
+/// Gadget for copying a qm31 element whose top limb is at the given depth to the top of the stack.
+pub fn qm31_pick_gadget(depth: usize) -> Script {
+    script! {
+        for _ in 0..4 {
+            { depth + 3 } OP_PICK
+        }
+    }
+}
+
 /// Gadget for pulling a m31 vector of k elements into the stack.
 pub fn m31_vec_from_bottom_gadget(len: usize) -> Script {
     script! {
@@ -90,10 +100,38 @@ pub fn m31_vec_from_bottom_gadget(len: usize) -> Script {
     }
 }
 
+/// Gadget for computing all the right shifts of a number of `num_bits` bits.
+///
+/// Input:
+/// - v
+///
+/// Output:
+/// - v >> (num_bits - 1)
+/// - ...
+/// - v >> 1
+/// - v
+pub fn limb_to_right_shifts_gadget(num_bits: u32) -> Script {
+    script! {
+        { limb_to_le_bits(num_bits) }
+
+        // stack: b_{num_bits - 1}, ..., b_1, b_0
+        { num_bits - 1 } OP_ROLL
+
+        // the next bit always sits below the other bits and the shifts computed so far
+        for _ in 0..num_bits - 1 {
+            OP_DUP OP_DUP OP_ADD
+            { num_bits } OP_ROLL
+            OP_ADD
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::treepp::*;
-    use crate::utils::{dup_m31_vec_gadget, trim_m31, trim_m31_gadget};
+    use crate::utils::{
+        dup_m31_vec_gadget, limb_to_right_shifts_gadget, trim_m31, trim_m31_gadget,
+    };
     use rand::{RngCore, SeedableRng};
     use rand_chacha::ChaCha20Rng;
     use stwo_prover::core::fields::m31::M31;
@@ -120,6 +158,28 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_limb_to_right_shifts() {
+        let mut prng = ChaCha20Rng::seed_from_u64(0);
+
+        for num_bits in 2..=20 {
+            let shifts_script = limb_to_right_shifts_gadget(num_bits);
+
+            let v = prng.next_u32() & ((1 << num_bits) - 1);
+
+            let script = script! {
+                { v }
+                { shifts_script.clone() }
+                for i in 0..num_bits {
+                    { v >> i } OP_EQUALVERIFY
+                }
+                OP_TRUE
+            };
+            let exec_result = execute_script(script);
+            assert!(exec_result.success);
+        }
+    }
+
     #[test]
     fn test_copy_m31_vec() {
         let mut prng = ChaCha20Rng::seed_from_u64(0);
@@ -0,0 +1,1490 @@
+use crate::air::{AirGadget, ScriptAir};
+use crate::channel::Sha256ChannelGadget;
+use crate::chunker::{n_state_elements, Stage, StateItem};
+use crate::disprove::{DisprovableStep, StepKind};
+use crate::fri::FriGadget;
+use crate::hint_stream::HintStream;
+use crate::layout::{Input, LayoutBuilder, LayoutError};
+use crate::merkle_tree::MerkleTreeTwinGadget;
+use crate::oods::OODSGadget;
+use crate::pow::PowGadget;
+use crate::precomputed_merkle_tree::{PrecomputedMerkleTree, PrecomputedMerkleTreeGadget};
+use crate::quotients::QuotientsGadget;
+use crate::treepp::*;
+use crate::utils::{limb_to_le_bits, limb_to_right_shifts_gadget};
+use crate::verifier::{UnsupportedAirError, VerifierConfig, VerifierSizes};
+use itertools::Itertools;
+use num_traits::One;
+use rust_bitcoin_m31::{
+    m31_mul, m31_neg, qm31_add, qm31_drop, qm31_equalverify, qm31_fromaltstack, qm31_mul,
+    qm31_square, qm31_swap, qm31_toaltstack,
+};
+use stwo_prover::core::channel::BWSSha256Channel;
+use stwo_prover::core::fields::m31::M31;
+use stwo_prover::core::poly::circle::CanonicCoset;
+
+/// Number of the columns of the composition polynomial.
+pub(crate) const N_COMPOSITION_COLUMNS: usize = 4;
+
+/// A sample batch as seen from the script: the slot of the sampled point and, for each sampled
+/// column, the column index and the slot of the sampled value.
+type ScriptSampleBatch = (String, Vec<(usize, String)>);
+
+/// The parts of the verifier script, from which both the single script and the stages are built.
+struct VerifierParts {
+    /// The stages before the queries, with their names and the numbers of the elements they leave.
+    stages: Vec<(&'static str, Script, usize)>,
+    /// The checks of the queries, each of which pulls its query from the altstack.
+    queries: Vec<Script>,
+    /// The clean-up of the stack after all the queries, which accepts.
+    cleanup: Script,
+}
+
+/// A chain of disprovable steps on the named slots of the state, each of which starts from the
+/// state left by the last computation before it.
+struct StepChain {
+    /// The slots of the state left by the last computation.
+    builder: LayoutBuilder,
+    /// Number of the stack elements of the state.
+    n_elements: usize,
+    /// The steps so far.
+    steps: Vec<DisprovableStep>,
+}
+
+impl StepChain {
+    /// Start from the initial state with the given slots, from the bottom to the top.
+    fn new(slots: &[(&str, StateItem)]) -> Result<Self, LayoutError> {
+        let builder = LayoutBuilder::new(slots)?;
+        Ok(Self {
+            n_elements: n_state_elements(&builder.items()),
+            builder,
+            steps: vec![],
+        })
+    }
+
+    /// Add a computation, which maps the state to the slots left by the gadgets it runs, with the
+    /// hints pulled through its own stream.
+    fn computation(
+        &mut self,
+        name: &str,
+        build: impl FnOnce(&mut LayoutBuilder, &mut HintStream) -> Result<(), LayoutError>,
+    ) -> Result<(), LayoutError> {
+        let mut stream = HintStream::new();
+        build(&mut self.builder, &mut stream)?;
+
+        let n_outputs = n_state_elements(&self.builder.items());
+        self.steps.push(DisprovableStep::new(
+            name,
+            self.n_elements,
+            StepKind::Computation { n_outputs },
+            self.builder.take_script(),
+            stream,
+        ));
+        self.n_elements = n_outputs;
+        Ok(())
+    }
+
+    /// Add a check, which runs gadgets on copies of the slots of the state into a "result" slot,
+    /// and then drops the state, so that the next step starts from the same state.
+    fn check(
+        &mut self,
+        name: &str,
+        build: impl FnOnce(&mut LayoutBuilder, &mut HintStream) -> Result<(), LayoutError>,
+    ) -> Result<(), LayoutError> {
+        let mut builder = LayoutBuilder::new(
+            &self
+                .builder
+                .slots()
+                .iter()
+                .map(|slot| (slot.name.as_str(), slot.item))
+                .collect_vec(),
+        )?;
+        let mut stream = HintStream::new();
+        build(&mut builder, &mut stream)?;
+        builder.roll("result")?;
+        builder.check(
+            &self
+                .builder
+                .slots()
+                .iter()
+                .map(|slot| (slot.name.as_str(), slot.item))
+                .chain([("result", StateItem::M31)])
+                .collect_vec(),
+        )?;
+
+        let n = self.n_elements;
+        self.steps.push(DisprovableStep::new(
+            name,
+            n,
+            StepKind::Check,
+            script! {
+                { builder.finish() }
+                OP_TOALTSTACK
+                for _ in 0..n / 2 {
+                    OP_2DROP
+                }
+                if n % 2 == 1 {
+                    OP_DROP
+                }
+                OP_FROMALTSTACK
+            },
+            stream,
+        ));
+        Ok(())
+    }
+}
+
+/// A verifier for the proof of a `ScriptAir`.
+pub struct VerifierGadget;
+
+impl VerifierGadget {
+    /// Compute the DEEP quotient of a row from the queried values into the output slot, at the
+    /// domain point taken from the two slots, with random_coeff2 and the sampled points and values
+    /// copied from their slots, and with the hints pulled through the stream under the label.
+    fn fri_answer(
+        builder: &mut LayoutBuilder,
+        stream: &mut HintStream,
+        label: &str,
+        z: [Input; 2],
+        queried_values: &[String],
+        sample_batches: &[ScriptSampleBatch],
+        output: &str,
+    ) -> Result<(), LayoutError> {
+        let mut inputs = vec![(z[0], StateItem::M31), (z[1], StateItem::M31)];
+        for value in queried_values.iter() {
+            inputs.push((Input::Copy(value), StateItem::M31));
+        }
+        inputs.push((Input::Copy("random_coeff2"), StateItem::QM31));
+        for (point, columns_and_values) in sample_batches.iter().rev() {
+            inputs.push((Input::Copy(point), StateItem::CirclePoint));
+            for (_, value) in columns_and_values.iter() {
+                inputs.push((Input::Copy(value), StateItem::QM31));
+            }
+        }
+
+        let columns = sample_batches
+            .iter()
+            .map(|(_, columns_and_values)| {
+                columns_and_values
+                    .iter()
+                    .map(|(column, _)| *column)
+                    .collect_vec()
+            })
+            .collect_vec();
+
+        builder.call(
+            &inputs,
+            QuotientsGadget::fri_answer(stream, label, &columns, queried_values.len()),
+            &[(output, StateItem::QM31)],
+        )
+    }
+
+    /// Group the masked points of the trace into sample batches in the order they first appear,
+    /// as `SampleBatch::new_vec` does, assuming that different mask offsets give different points.
+    fn trace_sample_batches(mask_offsets: &[Vec<usize>]) -> Vec<ScriptSampleBatch> {
+        let mut batches: Vec<(usize, ScriptSampleBatch)> = vec![];
+        let mut k = 0;
+        for (column, offsets) in mask_offsets.iter().enumerate() {
+            for &offset in offsets.iter() {
+                let value = format!("trace_oods_values[{}]", k);
+                match batches.iter_mut().find(|(o, _)| *o == offset) {
+                    Some((_, (_, columns_and_values))) => columns_and_values.push((column, value)),
+                    None => batches.push((
+                        offset,
+                        (format!("masked_points[{}]", k), vec![(column, value)]),
+                    )),
+                }
+                k += 1;
+            }
+        }
+
+        batches.into_iter().map(|(_, batch)| batch).collect()
+    }
+
+    /// The only sample batch of the composition, which is sampled at the OODS point.
+    fn composition_sample_batches() -> Vec<ScriptSampleBatch> {
+        vec![(
+            "oods_point".to_string(),
+            (0..N_COMPOSITION_COLUMNS)
+                .map(|i| (i, format!("composition_oods_values[{}]", i)))
+                .collect(),
+        )]
+    }
+
+    /// Split the verifier into the stages before the queries, each with its name and the number
+    /// of the elements it leaves on the stack, the check of one query, and the final clean-up.
+    ///
+    /// All the hints are pulled through the stream, in the order of the parts.
+    fn parts<A: ScriptAir>(
+        air: &A,
+        channel: &BWSSha256Channel,
+        config: VerifierConfig,
+        stream: &mut HintStream,
+    ) -> Result<VerifierParts, UnsupportedAirError> {
+        let sizes = VerifierSizes::new(air, config)?;
+        Self::layout_parts(air, channel, config, sizes, stream).map_err(UnsupportedAirError::Layout)
+    }
+
+    /// Build the parts of the verifier on the named slots of the stack.
+    fn layout_parts<A: ScriptAir>(
+        air: &A,
+        channel: &BWSSha256Channel,
+        config: VerifierConfig,
+        sizes: VerifierSizes,
+        stream: &mut HintStream,
+    ) -> Result<VerifierParts, LayoutError> {
+        let log_last_layer_degree_bound = config.fri_config.log_last_layer_degree_bound;
+        let n_queries = config.fri_config.n_queries;
+
+        // the column log sizes of the trace and of the composition polynomial, the latter being the
+        // largest one
+        let VerifierSizes {
+            trace_log_size,
+            n_trace_columns,
+            n_mask_points,
+            t,
+            n,
+            n_layers,
+        } = sizes;
+        let mask_offsets = air.mask_offsets();
+
+        let trace_sample_batches = Self::trace_sample_batches(&mask_offsets);
+        let composition_sample_batches = Self::composition_sample_batches();
+
+        let masked_points = (0..n_mask_points)
+            .map(|k| format!("masked_points[{}]", k))
+            .collect_vec();
+        let trace_oods_values = (0..n_mask_points)
+            .map(|k| format!("trace_oods_values[{}]", k))
+            .collect_vec();
+        let composition_oods_values = (0..N_COMPOSITION_COLUMNS)
+            .map(|i| format!("composition_oods_values[{}]", i))
+            .collect_vec();
+
+        let mut builder = LayoutBuilder::default();
+        let mut stages = vec![];
+
+        // push the initial channel
+        builder.push("channel", StateItem::Digest, script! { { channel.digest } })?;
+
+        // pull the first commitment and mix it with the channel
+        builder.pull_hint(stream, "commitments[0]", StateItem::Digest)?;
+        builder.call(
+            &[
+                (Input::Copy("commitments[0]"), StateItem::Digest),
+                (Input::Move("channel"), StateItem::Digest),
+            ],
+            Sha256ChannelGadget::mix_digest(),
+            &[("channel", StateItem::Digest)],
+        )?;
+
+        // draw random_coeff
+        builder.call(
+            &[(Input::Move("channel"), StateItem::Digest)],
+            Sha256ChannelGadget::draw_felt_with_hint(stream, "random_coeff_hint"),
+            &[
+                ("channel", StateItem::Digest),
+                ("random_coeff", StateItem::QM31),
+            ],
+        )?;
+
+        // pull the second commitment and mix it with the channel
+        builder.pull_hint(stream, "commitments[1]", StateItem::Digest)?;
+        builder.call(
+            &[
+                (Input::Copy("commitments[1]"), StateItem::Digest),
+                (Input::Move("channel"), StateItem::Digest),
+            ],
+            Sha256ChannelGadget::mix_digest(),
+            &[("channel", StateItem::Digest)],
+        )?;
+
+        // draw the OODS point
+        builder.call(
+            &[(Input::Move("channel"), StateItem::Digest)],
+            OODSGadget::get_random_point(stream, "oods_hint"),
+            &[
+                ("channel", StateItem::Digest),
+                ("oods_point", StateItem::CirclePoint),
+            ],
+        )?;
+
+        // mask the points
+        builder.call(
+            &[(Input::Copy("oods_point"), StateItem::CirclePoint)],
+            AirGadget::shifted_mask_points(
+                &mask_offsets,
+                &vec![CanonicCoset::new(trace_log_size); n_trace_columns],
+            ),
+            &masked_points
+                .iter()
+                .map(|name| (name.as_str(), StateItem::CirclePoint))
+                .collect_vec(),
+        )?;
+
+        // pull the trace oods values and the composition oods raw values from the hint, and update
+        // the channel with all of them
+        for value in trace_oods_values
+            .iter()
+            .chain(composition_oods_values.iter())
+        {
+            builder.pull_hint(stream, value, StateItem::QM31)?;
+        }
+        for value in trace_oods_values
+            .iter()
+            .chain(composition_oods_values.iter())
+        {
+            builder.call(
+                &[
+                    (Input::Copy(value), StateItem::QM31),
+                    (Input::Move("channel"), StateItem::Digest),
+                ],
+                Sha256ChannelGadget::mix_felt(),
+                &[("channel", StateItem::Digest)],
+            )?;
+        }
+        stages.push((
+            "channel_oods",
+            builder.take_script(),
+            n_state_elements(&builder.items()),
+        ));
+
+        // check the composition polynomial at the OODS point against the composition oods raw
+        // values
+        builder.call(
+            &composition_oods_values
+                .iter()
+                .map(|value| (Input::Copy(value), StateItem::QM31))
+                .collect_vec(),
+            AirGadget::eval_from_partial_evals(),
+            &[("composition_oods_value", StateItem::QM31)],
+        )?;
+
+        let mut inputs = vec![(Input::Copy("random_coeff"), StateItem::QM31)];
+        for value in trace_oods_values.iter() {
+            inputs.push((Input::Copy(value), StateItem::QM31));
+        }
+        inputs.push((Input::Copy("oods_point"), StateItem::CirclePoint));
+        builder.call(
+            &inputs,
+            air.eval_composition_polynomial_at_point_gadget(stream, "composition_hint"),
+            &[("composition_value", StateItem::QM31)],
+        )?;
+
+        builder.call(
+            &[
+                (Input::Move("composition_oods_value"), StateItem::QM31),
+                (Input::Move("composition_value"), StateItem::QM31),
+            ],
+            script! { qm31_equalverify },
+            &[],
+        )?;
+
+        // draw random_coeff2 and circle_poly_alpha
+        for (label, name) in [
+            ("random_coeff_hint2", "random_coeff2"),
+            ("circle_poly_alpha_hint", "circle_poly_alpha"),
+        ] {
+            builder.call(
+                &[(Input::Move("channel"), StateItem::Digest)],
+                Sha256ChannelGadget::draw_felt_with_hint(stream, label),
+                &[("channel", StateItem::Digest), (name, StateItem::QM31)],
+            )?;
+        }
+        stages.push((
+            "composition",
+            builder.take_script(),
+            n_state_elements(&builder.items()),
+        ));
+
+        // pull the FRI commitments, mix them with the channel, and draw the folding alphas
+        for i in 0..n_layers {
+            let commitment = format!("fri_commitment[{}]", i);
+            builder.pull_hint(stream, &commitment, StateItem::Digest)?;
+            builder.call(
+                &[
+                    (Input::Copy(&commitment), StateItem::Digest),
+                    (Input::Move("channel"), StateItem::Digest),
+                ],
+                Sha256ChannelGadget::mix_digest(),
+                &[("channel", StateItem::Digest)],
+            )?;
+            let folding_alpha = format!("folding_alpha[{}]", i);
+            builder.call(
+                &[(Input::Move("channel"), StateItem::Digest)],
+                Sha256ChannelGadget::draw_felt_with_hint(
+                    stream,
+                    &format!("fri_folding_hint[{}]", i),
+                ),
+                &[
+                    ("channel", StateItem::Digest),
+                    (folding_alpha.as_str(), StateItem::QM31),
+                ],
+            )?;
+        }
+
+        // pull the last layer coefficients and mix them with the channel one by one
+        for i in 0..(1 << log_last_layer_degree_bound) {
+            let coeff = format!("last_layer[{}]", i);
+            builder.pull_hint(stream, &coeff, StateItem::QM31)?;
+            builder.call(
+                &[
+                    (Input::Copy(&coeff), StateItem::QM31),
+                    (Input::Move("channel"), StateItem::Digest),
+                ],
+                Sha256ChannelGadget::mix_felt(),
+                &[("channel", StateItem::Digest)],
+            )?;
+        }
+        stages.push((
+            "fri_commitments",
+            builder.take_script(),
+            n_state_elements(&builder.items()),
+        ));
+
+        builder.call(
+            &[(Input::Move("channel"), StateItem::Digest)],
+            PowGadget::verify_pow(stream, "pow_hint", config.pow_bits),
+            &[("channel", StateItem::Digest)],
+        )?;
+
+        let queries = (0..n_queries)
+            .map(|i| format!("queries[{}]", i))
+            .collect_vec();
+        let mut outputs = vec![("channel", StateItem::Digest)];
+        outputs.extend(queries.iter().map(|name| (name.as_str(), StateItem::M31)));
+        builder.call(
+            &[(Input::Move("channel"), StateItem::Digest)],
+            Sha256ChannelGadget::draw_numbers_with_hint(stream, "queries_hints", n_queries, n),
+            &outputs,
+        )?;
+
+        // drop the channel digest, which is not used after the queries
+        builder.drop("channel")?;
+        stages.push((
+            "pow_queries",
+            builder.take_script(),
+            n_state_elements(&builder.items()),
+        ));
+
+        // the queries are moved to the altstack before the query checks, so that the first drawn
+        // query comes out first, and each check starts from the slots below them
+        let base_slots = builder.slots()[..builder.slots().len() - n_queries].to_vec();
+        let base = base_slots
+            .iter()
+            .map(|slot| (slot.name.as_str(), slot.item))
+            .collect_vec();
+
+        // the right shifts of the query, from query >> (n - 1) to query
+        let shifts = (0..n)
+            .rev()
+            .map(|k| format!("query >> {}", k))
+            .collect_vec();
+        let shift = |k: usize| Input::Copy(&shifts[n - 1 - k]);
+
+        let trace_values = ["left", "right"]
+            .iter()
+            .flat_map(|side| (0..n_trace_columns).map(move |j| format!("trace_{}[{}]", side, j)))
+            .collect_vec();
+        let composition_values = ["left", "right"]
+            .iter()
+            .flat_map(|side| {
+                (0..N_COMPOSITION_COLUMNS).map(move |j| format!("composition_{}[{}]", side, j))
+            })
+            .collect_vec();
+
+        // the checks differ only in the labels of their hints, which carry the index of the query
+        let mut query_checks = vec![];
+        for i in 0..n_queries {
+            let mut builder = LayoutBuilder::new(&base)?;
+            builder.push("query", StateItem::M31, script! { OP_FROMALTSTACK })?;
+            builder.call(
+                &[(Input::Move("query"), StateItem::M31)],
+                limb_to_right_shifts_gadget(n as u32),
+                &shifts
+                    .iter()
+                    .map(|name| (name.as_str(), StateItem::M31))
+                    .collect_vec(),
+            )?;
+
+            // check the trace values against c1, at the pair position 2 * (query >> (n - t + 1)),
+            // and the composition values against c2, at the pair position 2 * (query >> 1)
+            for (position_shift, commitment, label, values, log_size) in [
+                (
+                    shift(n - t + 1),
+                    "commitments[0]",
+                    format!("merkle_proofs_traces[{}]", i),
+                    &trace_values,
+                    t,
+                ),
+                (
+                    shift(1),
+                    "commitments[1]",
+                    format!("merkle_proofs_compositions[{}]", i),
+                    &composition_values,
+                    n,
+                ),
+            ] {
+                builder.call(
+                    &[(position_shift, StateItem::M31)],
+                    script! { OP_DUP OP_ADD },
+                    &[("position", StateItem::M31)],
+                )?;
+                builder.call(
+                    &[
+                        (Input::Copy(commitment), StateItem::Digest),
+                        (Input::Move("position"), StateItem::M31),
+                    ],
+                    MerkleTreeTwinGadget::query_and_verify(
+                        stream,
+                        &label,
+                        values.len() / 2,
+                        log_size,
+                    ),
+                    &values
+                        .iter()
+                        .map(|name| (name.as_str(), StateItem::M31))
+                        .collect_vec(),
+                )?;
+            }
+
+            // compute the DEEP quotients of the trace and then of the composition on the queried
+            // pair, with the domain point from the hint, and move them to the altstack; the domain
+            // point goes along, and `FriGadget::verify_query` checks it against the twiddle
+            // factors of the queried position within this part, before the query is accepted
+            for (j, values, sample_batches) in [
+                (0, &trace_values, &trace_sample_batches),
+                (1, &composition_values, &composition_sample_batches),
+            ] {
+                let label = format!("fri_answer_hints[{}][{}]", i, j);
+                let (left, right) = values.split_at(values.len() / 2);
+
+                builder.push("z.x", StateItem::M31, stream.pull(&label, StateItem::M31))?;
+                builder.push("z.y", StateItem::M31, stream.pull(&label, StateItem::M31))?;
+                Self::fri_answer(
+                    &mut builder,
+                    stream,
+                    &label,
+                    [Input::Copy("z.x"), Input::Copy("z.y")],
+                    left,
+                    sample_batches,
+                    "answer_left",
+                )?;
+
+                // the conjugate of the domain point, for the right values
+                builder.call(
+                    &[
+                        (Input::Copy("z.x"), StateItem::M31),
+                        (Input::Copy("z.y"), StateItem::M31),
+                    ],
+                    script! { m31_neg },
+                    &[("conj_z.x", StateItem::M31), ("conj_z.y", StateItem::M31)],
+                )?;
+                Self::fri_answer(
+                    &mut builder,
+                    stream,
+                    &label,
+                    [Input::Move("conj_z.x"), Input::Move("conj_z.y")],
+                    right,
+                    sample_batches,
+                    "answer_right",
+                )?;
+                builder.call(
+                    &[
+                        (Input::Move("answer_left"), StateItem::QM31),
+                        (Input::Move("answer_right"), StateItem::QM31),
+                        (Input::Move("z.x"), StateItem::M31),
+                        (Input::Move("z.y"), StateItem::M31),
+                    ],
+                    script! { OP_TOALTSTACK OP_TOALTSTACK qm31_toaltstack qm31_toaltstack },
+                    &[],
+                )?;
+            }
+
+            // drop the queried values
+            builder.call(
+                &trace_values
+                    .iter()
+                    .chain(composition_values.iter())
+                    .map(|name| (Input::Move(name), StateItem::M31))
+                    .collect_vec(),
+                script! {
+                    for _ in 0..(n_trace_columns + N_COMPOSITION_COLUMNS) {
+                        OP_2DROP
+                    }
+                },
+                &[],
+            )?;
+
+            // check the FRI folding, which takes circle_poly_alpha, the FRI commitments, the
+            // folding alphas and the last layer from the slots, and consumes the right shifts of
+            // the query
+            let mut expected = base.clone();
+            expected.extend(shifts.iter().map(|name| (name.as_str(), StateItem::M31)));
+            builder.check(&expected)?;
+            FriGadget::verify_query(
+                &mut builder,
+                stream,
+                &format!("fri_query_hints[{}]", i),
+                &[n as u32, t as u32],
+                n_layers,
+                log_last_layer_degree_bound as usize,
+            )?;
+            builder.check(&base)?;
+            query_checks.push(builder.finish());
+        }
+
+        // clean up the stack, and accept only if all the hints have been consumed, which leaves
+        // exactly one element
+        let mut builder = LayoutBuilder::new(&base)?;
+        for (name, _) in base.iter().rev() {
+            builder.drop(name)?;
+        }
+        let cleanup = script! {
+            { builder.finish() }
+            OP_DEPTH OP_NOT
+        };
+
+        Ok(VerifierParts {
+            stages,
+            queries: query_checks,
+            cleanup,
+        })
+    }
+
+    /// Run the verifier in the Bitcoin script, for the proof of the AIR, starting from the channel.
+    ///
+    /// The script consumes all the hints and leaves exactly one element, which is true if and only
+    /// if the proof is accepted and no other element is left on the stack.
+    ///
+    /// The configuration must be the one used to generate the hints. An error is returned if the
+    /// AIR is not supported, see `ScriptAir`.
+    pub fn run_verifier<A: ScriptAir>(
+        air: &A,
+        channel: &BWSSha256Channel,
+        config: VerifierConfig,
+    ) -> Result<Script, UnsupportedAirError> {
+        let parts = Self::parts(air, channel, config, &mut HintStream::new())?;
+
+        Ok(script! {
+            for (_, stage, _) in parts.stages.iter() {
+                { stage.clone() }
+            }
+            for _ in 0..parts.queries.len() {
+                OP_TOALTSTACK
+            }
+            for query in parts.queries.iter() {
+                { query.clone() }
+            }
+            { parts.cleanup }
+        })
+    }
+
+    /// Split the verifier into stages that hand off their live stacks through SHA256 commitments:
+    /// the channel and the OODS, the composition, the FRI commitments, the PoW and the queries, and
+    /// then one stage per query, the last of which accepts as `run_verifier` does.
+    ///
+    /// The configuration must be the one used to generate the hints. An error is returned if the
+    /// AIR is not supported, see `ScriptAir`.
+    pub fn run_verifier_stages<A: ScriptAir>(
+        air: &A,
+        channel: &BWSSha256Channel,
+        config: VerifierConfig,
+    ) -> Result<Vec<Stage>, UnsupportedAirError> {
+        let parts = Self::parts(air, channel, config, &mut HintStream::new())?;
+        let n_queries = parts.queries.len();
+
+        let mut stages = vec![];
+        let mut input_state_size = 0;
+        for (name, body, output_state_size) in parts.stages.into_iter() {
+            stages.push(Stage {
+                name: name.to_string(),
+                input_state_size,
+                output_state_size: Some(output_state_size),
+                body,
+            });
+            input_state_size = output_state_size;
+        }
+
+        // each query stage moves the remaining queries to the altstack, checks the first one, and
+        // moves the others back
+        for (i, query) in parts.queries.into_iter().enumerate() {
+            let n_remaining_queries = n_queries - 1 - i;
+            let is_last = n_remaining_queries == 0;
+
+            stages.push(Stage {
+                name: format!("query_{}", i),
+                input_state_size,
+                output_state_size: if is_last {
+                    None
+                } else {
+                    Some(input_state_size - 1)
+                },
+                body: script! {
+                    for _ in 0..(n_remaining_queries + 1) {
+                        OP_TOALTSTACK
+                    }
+                    { query }
+                    for _ in 0..n_remaining_queries {
+                        OP_FROMALTSTACK
+                    }
+                    if is_last {
+                        { parts.cleanup.clone() }
+                    }
+                },
+            });
+            input_state_size -= 1;
+        }
+
+        Ok(stages)
+    }
+
+    /// Return the hints pulled by the verifier, in the order in which both `run_verifier` and the
+    /// stages of `run_verifier_stages` pull them, for `VerifierHints::write`.
+    ///
+    /// The configuration must be the one used to generate the hints. An error is returned if the
+    /// AIR is not supported, see `ScriptAir`.
+    pub fn hint_stream<A: ScriptAir>(
+        air: &A,
+        channel: &BWSSha256Channel,
+        config: VerifierConfig,
+    ) -> Result<HintStream, UnsupportedAirError> {
+        let mut stream = HintStream::new();
+        Self::parts(air, channel, config, &mut stream)?;
+        Ok(stream)
+    }
+
+    /// Return the steps of the verifier whose assertions can be disproved by running a single
+    /// gadget, as a chain over the named slots of the state, from the initial state asserted by
+    /// the prover, see `disprove_initial_state`:
+    /// - the channel, with random_coeff, the OODS point, and the oods values mixed in
+    /// - the composition polynomial at the OODS point, and its check against the composition
+    ///   oods raw values
+    /// - random_coeff2 and circle_poly_alpha, and then the folding alpha of each inner FRI layer
+    /// - the last layer mixed in, the check of the PoW, and the queries
+    /// - for each query, the decommitments of the trace and of the composition, and the check of
+    ///   their DEEP quotients folded from the circle domain against the folded circle polynomials
+    /// - for each query, the decommitment of the twin and the folding in each inner FRI layer, with
+    ///   the inverse twiddle factor from the twiddle tree, and the check of the last layer
+    ///   polynomial at the folded query
+    ///
+    /// The folded circle polynomials of each query and the twins of its inner FRI layers are
+    /// asserted in the initial state, and each of them is checked against its commitment.
+    ///
+    /// The configuration must be the one used to generate the hints. An error is returned if the
+    /// AIR is not supported, see `ScriptAir`.
+    pub fn disprovable_steps<A: ScriptAir>(
+        air: &A,
+        channel: &BWSSha256Channel,
+        config: VerifierConfig,
+    ) -> Result<Vec<DisprovableStep>, UnsupportedAirError> {
+        let sizes = VerifierSizes::new(air, config)?;
+        Self::layout_disprovable_steps(air, channel, config, sizes)
+            .map_err(UnsupportedAirError::Layout)
+    }
+
+    /// Return the slots of the initial state of the disprovable steps, from the bottom to the top.
+    pub(super) fn disprove_initial_slots(
+        sizes: &VerifierSizes,
+        config: VerifierConfig,
+    ) -> Vec<(String, StateItem)> {
+        let n_queries = config.fri_config.n_queries;
+        let log_last_layer_degree_bound = config.fri_config.log_last_layer_degree_bound;
+
+        let mut slots = vec![
+            ("commitments[0]".to_string(), StateItem::Digest),
+            ("commitments[1]".to_string(), StateItem::Digest),
+        ];
+        for k in 0..sizes.n_mask_points {
+            slots.push((format!("trace_oods_values[{}]", k), StateItem::QM31));
+        }
+        for i in 0..N_COMPOSITION_COLUMNS {
+            slots.push((format!("composition_oods_values[{}]", i), StateItem::QM31));
+        }
+        for j in 0..sizes.n_layers {
+            slots.push((format!("fri_commitment[{}]", j), StateItem::Digest));
+        }
+        for i in 0..(1 << log_last_layer_degree_bound) {
+            slots.push((format!("last_layer[{}]", i), StateItem::QM31));
+        }
+        slots.push(("nonce".to_string(), StateItem::Bytes));
+        for q in 0..n_queries {
+            slots.push((format!("fri_value[{}]", q), StateItem::QM31));
+            slots.push((format!("fri_trace_value[{}]", q), StateItem::QM31));
+            for j in 0..sizes.n_layers {
+                slots.push((format!("fri_twin[{}][{}]", q, j), StateItem::QM31));
+            }
+        }
+        slots
+    }
+
+    /// Build the disprovable steps on the named slots of the state.
+    fn layout_disprovable_steps<A: ScriptAir>(
+        air: &A,
+        channel: &BWSSha256Channel,
+        config: VerifierConfig,
+        sizes: VerifierSizes,
+    ) -> Result<Vec<DisprovableStep>, LayoutError> {
+        let log_last_layer_degree_bound = config.fri_config.log_last_layer_degree_bound;
+        let n_queries = config.fri_config.n_queries;
+        let VerifierSizes {
+            trace_log_size,
+            n_trace_columns,
+            n_mask_points,
+            t,
+            n,
+            n_layers,
+        } = sizes;
+        let mask_offsets = air.mask_offsets();
+
+        let trace_sample_batches = Self::trace_sample_batches(&mask_offsets);
+        let composition_sample_batches = Self::composition_sample_batches();
+
+        let masked_points = (0..n_mask_points)
+            .map(|k| format!("masked_points[{}]", k))
+            .collect_vec();
+        let trace_oods_values = (0..n_mask_points)
+            .map(|k| format!("trace_oods_values[{}]", k))
+            .collect_vec();
+        let composition_oods_values = (0..N_COMPOSITION_COLUMNS)
+            .map(|i| format!("composition_oods_values[{}]", i))
+            .collect_vec();
+
+        let initial_slots = Self::disprove_initial_slots(&sizes, config);
+        let mut chain = StepChain::new(
+            &initial_slots
+                .iter()
+                .map(|(name, item)| (name.as_str(), *item))
+                .collect_vec(),
+        )?;
+
+        // push the initial channel, mix the first commitment, and draw random_coeff
+        chain.computation("random_coeff", |builder, stream| {
+            builder.push("channel", StateItem::Digest, script! { { channel.digest } })?;
+            builder.call(
+                &[
+                    (Input::Copy("commitments[0]"), StateItem::Digest),
+                    (Input::Move("channel"), StateItem::Digest),
+                ],
+                Sha256ChannelGadget::mix_digest(),
+                &[("channel", StateItem::Digest)],
+            )?;
+            builder.call(
+                &[(Input::Move("channel"), StateItem::Digest)],
+                Sha256ChannelGadget::draw_felt_with_hint(stream, "random_coeff_hint"),
+                &[
+                    ("channel", StateItem::Digest),
+                    ("random_coeff", StateItem::QM31),
+                ],
+            )
+        })?;
+
+        // mix the second commitment and draw the OODS point
+        chain.computation("oods_point", |builder, stream| {
+            builder.call(
+                &[
+                    (Input::Copy("commitments[1]"), StateItem::Digest),
+                    (Input::Move("channel"), StateItem::Digest),
+                ],
+                Sha256ChannelGadget::mix_digest(),
+                &[("channel", StateItem::Digest)],
+            )?;
+            builder.call(
+                &[(Input::Move("channel"), StateItem::Digest)],
+                OODSGadget::get_random_point(stream, "oods_hint"),
+                &[
+                    ("channel", StateItem::Digest),
+                    ("oods_point", StateItem::CirclePoint),
+                ],
+            )
+        })?;
+
+        // mix the trace oods values and the composition oods raw values
+        chain.computation("oods_values", |builder, _| {
+            for value in trace_oods_values
+                .iter()
+                .chain(composition_oods_values.iter())
+            {
+                builder.call(
+                    &[
+                        (Input::Copy(value), StateItem::QM31),
+                        (Input::Move("channel"), StateItem::Digest),
+                    ],
+                    Sha256ChannelGadget::mix_felt(),
+                    &[("channel", StateItem::Digest)],
+                )?;
+            }
+            Ok(())
+        })?;
+
+        // evaluate the composition polynomial at the OODS point, and check it against the
+        // composition oods raw values
+        chain.computation("composition", |builder, stream| {
+            let mut inputs = vec![(Input::Copy("random_coeff"), StateItem::QM31)];
+            for value in trace_oods_values.iter() {
+                inputs.push((Input::Copy(value), StateItem::QM31));
+            }
+            inputs.push((Input::Copy("oods_point"), StateItem::CirclePoint));
+            builder.call(
+                &inputs,
+                air.eval_composition_polynomial_at_point_gadget(stream, "composition_hint"),
+                &[("composition_value", StateItem::QM31)],
+            )
+        })?;
+        chain.check("composition_check", |builder, _| {
+            builder.call(
+                &composition_oods_values
+                    .iter()
+                    .map(|value| (Input::Copy(value), StateItem::QM31))
+                    .collect_vec(),
+                AirGadget::eval_from_partial_evals(),
+                &[("composition_oods_value", StateItem::QM31)],
+            )?;
+            builder.call(
+                &[
+                    (Input::Move("composition_oods_value"), StateItem::QM31),
+                    (Input::Copy("composition_value"), StateItem::QM31),
+                ],
+                Self::qm31_equal(),
+                &[("result", StateItem::M31)],
+            )
+        })?;
+
+        // draw random_coeff2 and circle_poly_alpha
+        chain.computation("fri_alphas", |builder, stream| {
+            builder.drop("composition_value")?;
+            for (label, name) in [
+                ("random_coeff_hint2", "random_coeff2"),
+                ("circle_poly_alpha_hint", "circle_poly_alpha"),
+            ] {
+                builder.call(
+                    &[(Input::Move("channel"), StateItem::Digest)],
+                    Sha256ChannelGadget::draw_felt_with_hint(stream, label),
+                    &[("channel", StateItem::Digest), (name, StateItem::QM31)],
+                )?;
+            }
+            Ok(())
+        })?;
+
+        // mix the FRI commitment and draw the folding alpha of each inner layer
+        for j in 0..n_layers {
+            chain.computation(&format!("fri_layer[{}]", j), |builder, stream| {
+                builder.call(
+                    &[
+                        (
+                            Input::Copy(&format!("fri_commitment[{}]", j)),
+                            StateItem::Digest,
+                        ),
+                        (Input::Move("channel"), StateItem::Digest),
+                    ],
+                    Sha256ChannelGadget::mix_digest(),
+                    &[("channel", StateItem::Digest)],
+                )?;
+                builder.call(
+                    &[(Input::Move("channel"), StateItem::Digest)],
+                    Sha256ChannelGadget::draw_felt_with_hint(
+                        stream,
+                        &format!("fri_folding_hint[{}]", j),
+                    ),
+                    &[
+                        ("channel", StateItem::Digest),
+                        (format!("folding_alpha[{}]", j).as_str(), StateItem::QM31),
+                    ],
+                )
+            })?;
+        }
+
+        // mix the last layer coefficients one by one
+        chain.computation("last_layer", |builder, _| {
+            for i in 0..(1 << log_last_layer_degree_bound) {
+                builder.call(
+                    &[
+                        (Input::Copy(&format!("last_layer[{}]", i)), StateItem::QM31),
+                        (Input::Move("channel"), StateItem::Digest),
+                    ],
+                    Sha256ChannelGadget::mix_felt(),
+                    &[("channel", StateItem::Digest)],
+                )?;
+            }
+            Ok(())
+        })?;
+
+        // check the PoW of the nonce, and then mix the nonce and draw the queries
+        chain.check("pow", |builder, stream| {
+            builder.call(
+                &[
+                    (Input::Copy("channel"), StateItem::Digest),
+                    (Input::Copy("nonce"), StateItem::Bytes),
+                ],
+                PowGadget::check_pow(stream, "pow_hint", config.pow_bits),
+                &[("result", StateItem::M31)],
+            )
+        })?;
+        chain.computation("queries", |builder, stream| {
+            builder.call(
+                &[
+                    (Input::Copy("nonce"), StateItem::Bytes),
+                    (Input::Move("channel"), StateItem::Digest),
+                ],
+                Sha256ChannelGadget::mix_nonce(),
+                &[("channel", StateItem::Digest)],
+            )?;
+
+            let queries = (0..n_queries)
+                .map(|q| format!("queries[{}]", q))
+                .collect_vec();
+            let mut outputs = vec![("channel", StateItem::Digest)];
+            outputs.extend(queries.iter().map(|name| (name.as_str(), StateItem::M31)));
+            builder.call(
+                &[(Input::Move("channel"), StateItem::Digest)],
+                Sha256ChannelGadget::draw_numbers_with_hint(stream, "queries_hints", n_queries, n),
+                &outputs,
+            )?;
+            builder.drop("channel")
+        })?;
+
+        // the queried values of the trace and of the composition, from the left and the right of
+        // the queried pair
+        let trace_values = ["left", "right"]
+            .iter()
+            .flat_map(|side| (0..n_trace_columns).map(move |j| format!("trace_{}[{}]", side, j)))
+            .collect_vec();
+        let composition_values = ["left", "right"]
+            .iter()
+            .flat_map(|side| {
+                (0..N_COMPOSITION_COLUMNS).map(move |j| format!("composition_{}[{}]", side, j))
+            })
+            .collect_vec();
+
+        // check each query through the layers, as `FriGadget::verify_query` does
+        let twiddle_tree = PrecomputedMerkleTree::new(n - 1);
+        let trace_twiddle_tree = PrecomputedMerkleTree::new(t - 1);
+
+        // query >> k, bit k of the query, and the path of the query in the twiddle tree
+        let right_shift = |k: usize| Self::pick_one_of(limb_to_right_shifts_gadget(n as u32), n, k);
+        let bit = |k: usize| Self::pick_one_of(limb_to_le_bits(n as u32), n, k);
+        let twiddle_path = |stream: &mut HintStream, label: &str| {
+            script! {
+                { twiddle_tree.root_hash.to_vec() }
+                OP_SWAP
+                { PrecomputedMerkleTreeGadget::query_and_verify(stream, label, n) }
+            }
+        };
+        for q in 0..n_queries {
+            let query = format!("queries[{}]", q);
+            let value = format!("fri_value[{}]", q);
+            let trace_value = format!("fri_trace_value[{}]", q);
+
+            // decommit the trace values against c1, at the pair position
+            // 2 * (query >> (n - t + 1)), and the composition values against c2, at the pair
+            // position 2 * (query >> 1), and check the folding of their DEEP quotients from the
+            // circle domain against the folded circle polynomial of the same log size
+            for (name, commitment, values, log_size, root_hash, points, sample_batches, folded) in [
+                (
+                    format!("trace_decommitment[{}]", q),
+                    "commitments[0]",
+                    &trace_values,
+                    t,
+                    trace_twiddle_tree.root_hash,
+                    masked_points.as_slice(),
+                    &trace_sample_batches,
+                    &trace_value,
+                ),
+                (
+                    format!("composition_decommitment[{}]", q),
+                    "commitments[1]",
+                    &composition_values,
+                    n,
+                    twiddle_tree.root_hash,
+                    [].as_slice(),
+                    &composition_sample_batches,
+                    &value,
+                ),
+            ] {
+                chain.check(&name, |builder, stream| {
+                    builder.call(
+                        &[(Input::Copy(&query), StateItem::M31)],
+                        script! {
+                            { right_shift(n - log_size + 1) }
+                            OP_DUP OP_ADD
+                        },
+                        &[("position", StateItem::M31)],
+                    )?;
+                    builder.call(
+                        &[
+                            (Input::Copy(commitment), StateItem::Digest),
+                            (Input::Move("position"), StateItem::M31),
+                        ],
+                        MerkleTreeTwinGadget::query_and_verify(
+                            stream,
+                            &name,
+                            values.len() / 2,
+                            log_size,
+                        ),
+                        &values
+                            .iter()
+                            .map(|name| (name.as_str(), StateItem::M31))
+                            .collect_vec(),
+                    )?;
+
+                    // the inverse twiddle factors of the circle and of the first line, which are
+                    // the two on the top of the path of query >> (n - log_size) in the twiddle
+                    // tree of the log size
+                    let path =
+                        PrecomputedMerkleTreeGadget::query_and_verify(stream, &name, log_size);
+                    builder.call(
+                        &[(Input::Copy(&query), StateItem::M31)],
+                        script! {
+                            { right_shift(n - log_size) }
+                            { root_hash.to_vec() }
+                            OP_SWAP
+                            { path }
+                            for _ in 0..log_size - 3 {
+                                OP_ROT OP_DROP
+                            }
+                        },
+                        &[
+                            ("line_itwid", StateItem::M31),
+                            ("circle_itwid", StateItem::M31),
+                        ],
+                    )?;
+
+                    // the domain point from the hint, whose y is the inverse of the circle twiddle
+                    // factor, and whose x is the inverse of the line twiddle factor up to the sign
+                    // given by bit n - log_size + 1 of the query
+                    builder.push("z.x", StateItem::M31, stream.pull(&name, StateItem::M31))?;
+                    builder.push("z.y", StateItem::M31, stream.pull(&name, StateItem::M31))?;
+                    builder.call(
+                        &[
+                            (Input::Copy("z.y"), StateItem::M31),
+                            (Input::Copy("circle_itwid"), StateItem::M31),
+                        ],
+                        script! { m31_mul 1 OP_EQUALVERIFY },
+                        &[],
+                    )?;
+                    builder.call(
+                        &[
+                            (Input::Copy("z.x"), StateItem::M31),
+                            (Input::Move("line_itwid"), StateItem::M31),
+                            (Input::Copy(&query), StateItem::M31),
+                        ],
+                        script! {
+                            { bit(n - log_size + 1) }
+                            OP_TOALTSTACK
+                            m31_mul
+                            OP_FROMALTSTACK
+                            OP_IF { -M31::one() } OP_ELSE 1 OP_ENDIF
+                            OP_EQUALVERIFY
+                        },
+                        &[],
+                    )?;
+
+                    // the DEEP quotients of the pair, at the domain point and at its conjugate,
+                    // with the masked points of the trace
+                    if !points.is_empty() {
+                        builder.call(
+                            &[(Input::Copy("oods_point"), StateItem::CirclePoint)],
+                            AirGadget::shifted_mask_points(
+                                &mask_offsets,
+                                &vec![CanonicCoset::new(trace_log_size); n_trace_columns],
+                            ),
+                            &points
+                                .iter()
+                                .map(|name| (name.as_str(), StateItem::CirclePoint))
+                                .collect_vec(),
+                        )?;
+                    }
+                    let (left, right) = values.split_at(values.len() / 2);
+                    Self::fri_answer(
+                        builder,
+                        stream,
+                        &name,
+                        [Input::Copy("z.x"), Input::Copy("z.y")],
+                        left,
+                        sample_batches,
+                        "answer_left",
+                    )?;
+                    builder.call(
+                        &[
+                            (Input::Move("z.x"), StateItem::M31),
+                            (Input::Move("z.y"), StateItem::M31),
+                        ],
+                        script! { m31_neg },
+                        &[("conj_z.x", StateItem::M31), ("conj_z.y", StateItem::M31)],
+                    )?;
+                    Self::fri_answer(
+                        builder,
+                        stream,
+                        &name,
+                        [Input::Move("conj_z.x"), Input::Move("conj_z.y")],
+                        right,
+                        sample_batches,
+                        "answer_right",
+                    )?;
+                    for name in values.iter().chain(points.iter()) {
+                        builder.drop(name)?;
+                    }
+
+                    builder.call(
+                        &[
+                            (Input::Move("answer_left"), StateItem::QM31),
+                            (Input::Move("answer_right"), StateItem::QM31),
+                            (Input::Move("circle_itwid"), StateItem::M31),
+                            (Input::Copy("circle_poly_alpha"), StateItem::QM31),
+                        ],
+                        FriGadget::fold(),
+                        &[("circle_folded", StateItem::QM31)],
+                    )?;
+                    builder.call(
+                        &[
+                            (Input::Move("circle_folded"), StateItem::QM31),
+                            (Input::Copy(folded), StateItem::QM31),
+                        ],
+                        Self::qm31_equal(),
+                        &[("result", StateItem::M31)],
+                    )
+                })?;
+            }
+
+            for j in 0..n_layers {
+                let twin = format!("fri_twin[{}][{}]", q, j);
+
+                // decommit the twin against the FRI commitment of the layer, as the sibling of the
+                // folded value at the position query >> (j + 1)
+                let name = format!("fri_twin_decommitment[{}][{}]", q, j);
+                chain.check(&name, |builder, stream| {
+                    if j > 0 && n - j == t {
+                        builder.call(
+                            &[
+                                (Input::Copy(&value), StateItem::QM31),
+                                (Input::Copy("circle_poly_alpha"), StateItem::QM31),
+                                (Input::Copy(&trace_value), StateItem::QM31),
+                            ],
+                            script! {
+                                qm31_toaltstack
+                                qm31_square
+                                qm31_mul
+                                qm31_fromaltstack
+                                qm31_add
+                            },
+                            &[("leaf", StateItem::QM31)],
+                        )?;
+                    } else {
+                        builder.copy(&value, "leaf")?;
+                    }
+                    builder.call(
+                        &[(Input::Copy(&query), StateItem::M31)],
+                        right_shift(j + 1),
+                        &[("position", StateItem::M31)],
+                    )?;
+                    builder.call(
+                        &[(Input::Copy(&query), StateItem::M31)],
+                        bit(j + 1),
+                        &[("parity", StateItem::M31)],
+                    )?;
+                    builder.call(
+                        &[
+                            (Input::Move("leaf"), StateItem::QM31),
+                            (
+                                Input::Copy(&format!("fri_commitment[{}]", j)),
+                                StateItem::Digest,
+                            ),
+                            (Input::Move("position"), StateItem::M31),
+                        ],
+                        MerkleTreeTwinGadget::query_and_verify_sibling(stream, &name, n - 1 - j),
+                        &[
+                            ("pair_left", StateItem::QM31),
+                            ("pair_right", StateItem::QM31),
+                        ],
+                    )?;
+
+                    // the twin is on the left of an odd position, and on the right otherwise
+                    builder.call(
+                        &[
+                            (Input::Move("pair_left"), StateItem::QM31),
+                            (Input::Move("pair_right"), StateItem::QM31),
+                            (Input::Move("parity"), StateItem::M31),
+                        ],
+                        script! {
+                            OP_NOTIF
+                                qm31_swap
+                            OP_ENDIF
+                            qm31_drop
+                        },
+                        &[("decommitted_twin", StateItem::QM31)],
+                    )?;
+                    builder.call(
+                        &[
+                            (Input::Move("decommitted_twin"), StateItem::QM31),
+                            (Input::Copy(&twin), StateItem::QM31),
+                        ],
+                        Self::qm31_equal(),
+                        &[("result", StateItem::M31)],
+                    )
+                })?;
+
+                let name = format!("fri_fold[{}][{}]", q, j);
+                chain.computation(&name, |builder, stream| {
+                    // the folded trace joins the layer of its size
+                    if j > 0 && n - j == t {
+                        builder.call(
+                            &[
+                                (Input::Move(&value), StateItem::QM31),
+                                (Input::Copy("circle_poly_alpha"), StateItem::QM31),
+                                (Input::Copy(&trace_value), StateItem::QM31),
+                            ],
+                            script! {
+                                qm31_toaltstack
+                                qm31_square
+                                qm31_mul
+                                qm31_fromaltstack
+                                qm31_add
+                            },
+                            &[(value.as_str(), StateItem::QM31)],
+                        )?;
+                    }
+
+                    // the inverse twiddle factor of the layer, which is the (j + 2)-th from the
+                    // top of the ones on the path of the query in the twiddle tree
+                    builder.call(
+                        &[(Input::Copy(&query), StateItem::M31)],
+                        Self::pick_one_of(twiddle_path(stream, &name), n - 1, j + 1),
+                        &[("itwid", StateItem::M31)],
+                    )?;
+
+                    // the parity of the position in the layer, which is bit j + 1 of the query
+                    builder.call(
+                        &[(Input::Copy(&query), StateItem::M31)],
+                        bit(j + 1),
+                        &[("parity", StateItem::M31)],
+                    )?;
+
+                    builder.call(
+                        &[
+                            (Input::Move(&value), StateItem::QM31),
+                            (Input::Copy(&twin), StateItem::QM31),
+                            (Input::Move("parity"), StateItem::M31),
+                            (Input::Move("itwid"), StateItem::M31),
+                            (
+                                Input::Copy(&format!("folding_alpha[{}]", j)),
+                                StateItem::QM31,
+                            ),
+                        ],
+                        script! {
+                            qm31_toaltstack
+                            OP_TOALTSTACK
+                            OP_IF
+                                qm31_swap
+                            OP_ENDIF
+                            OP_FROMALTSTACK
+                            qm31_fromaltstack
+                            { FriGadget::fold() }
+                        },
+                        &[(value.as_str(), StateItem::QM31)],
+                    )
+                })?;
+            }
+
+            // check the folded query against the last layer polynomial, which is evaluated at the
+            // x-coordinate of the query in the last layer domain unless it is a constant
+            let name = format!("last_layer_check[{}]", q);
+            chain.check(&name, |builder, stream| {
+                if log_last_layer_degree_bound > 0 {
+                    // the x-coordinate from the hint, which is the inverse of the twiddle factor
+                    // of the last layer up to the sign given by bit n_layers + 1 of the query
+                    if n - 1 - n_layers >= 2 {
+                        builder.call(
+                            &[(Input::Copy(&query), StateItem::M31)],
+                            Self::pick_one_of(twiddle_path(stream, &name), n - 1, n_layers + 1),
+                            &[("itwid", StateItem::M31)],
+                        )?;
+                    } else {
+                        builder.push(
+                            "itwid",
+                            StateItem::M31,
+                            script! { { twiddle_tree.twiddles_inverse[n_layers + 1][0] } },
+                        )?;
+                    }
+                    builder.push("x", StateItem::M31, stream.pull(&name, StateItem::M31))?;
+                    builder.call(
+                        &[
+                            (Input::Copy("x"), StateItem::M31),
+                            (Input::Move("itwid"), StateItem::M31),
+                            (Input::Copy(&query), StateItem::M31),
+                        ],
+                        script! {
+                            { bit(n_layers + 1) }
+                            OP_TOALTSTACK
+                            m31_mul
+                            OP_FROMALTSTACK
+                            OP_IF { -M31::one() } OP_ELSE 1 OP_ENDIF
+                            OP_EQUALVERIFY
+                        },
+                        &[],
+                    )?;
+
+                    let coeffs = (0..(1 << log_last_layer_degree_bound))
+                        .map(|i| format!("last_layer[{}]", i))
+                        .collect_vec();
+                    let mut inputs = coeffs
+                        .iter()
+                        .map(|coeff| (Input::Copy(coeff), StateItem::QM31))
+                        .collect_vec();
+                    inputs.push((Input::Move("x"), StateItem::M31));
+                    builder.call(
+                        &inputs,
+                        FriGadget::eval_last_layer_poly(log_last_layer_degree_bound as usize),
+                        &[("last_layer_value", StateItem::QM31)],
+                    )?;
+                } else {
+                    builder.copy("last_layer[0]", "last_layer_value")?;
+                }
+                builder.call(
+                    &[
+                        (Input::Move("last_layer_value"), StateItem::QM31),
+                        (Input::Copy(&value), StateItem::QM31),
+                    ],
+                    Self::qm31_equal(),
+                    &[("result", StateItem::M31)],
+                )
+            })?;
+        }
+
+        Ok(chain.steps)
+    }
+
+    /// Compare two qm31 elements.
+    ///
+    /// Input: a (qm31), b (qm31)
+    /// Output: 1 if a == b, 0 otherwise
+    fn qm31_equal() -> Script {
+        script! {
+            4 OP_ROLL OP_EQUAL OP_TOALTSTACK
+            3 OP_ROLL OP_EQUAL OP_TOALTSTACK
+            2 OP_ROLL OP_EQUAL OP_TOALTSTACK
+            OP_EQUAL
+            OP_FROMALTSTACK OP_BOOLAND
+            OP_FROMALTSTACK OP_BOOLAND
+            OP_FROMALTSTACK OP_BOOLAND
+        }
+    }
+
+    /// Keep only the element at the given depth among the elements left by a script, such as a
+    /// bit or a right shift of a query.
+    ///
+    /// Input: the inputs of the script
+    /// Output: the element at the depth among the n elements left by the script
+    fn pick_one_of(script: Script, n: usize, depth: usize) -> Script {
+        script! {
+            { script }
+            { depth } OP_ROLL OP_TOALTSTACK
+            for _ in 0..(n - 1) / 2 {
+                OP_2DROP
+            }
+            if n % 2 == 0 {
+                OP_DROP
+            }
+            OP_FROMALTSTACK
+        }
+    }
+}
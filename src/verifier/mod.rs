@@ -0,0 +1,1101 @@
+mod bitcoin_script;
+mod offchain;
+
+pub use bitcoin_script::*;
+use itertools::Itertools;
+pub use offchain::*;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use crate::air::{CompositionHint, ScriptAir};
+use crate::channel::{ChannelWithHint, DrawHints};
+use crate::chunker::{run_stages, Stage, StageError, StageWitness, StateValue};
+use crate::disprove::DisprovableStep;
+use crate::fri::{generate_fri_query_hints, FriQueryHint};
+use crate::hint_stream::{HintStream, HintStreamError, HintWriter};
+use crate::layout::LayoutError;
+use crate::merkle_tree::{MerkleTreeTwinProof, SparseMerkleTree};
+use crate::oods::{OODSHint, OODS};
+use crate::pow::{PoWCheckHint, PoWHint};
+use crate::quotients::{fri_answers, sample_batches_by_log_size, FriAnswerPairHint};
+use bitcoin::Witness;
+use stwo_prover::core::air::{Air, AirExt};
+use stwo_prover::core::backend::CpuBackend;
+use stwo_prover::core::channel::{BWSSha256Channel, Channel};
+use stwo_prover::core::circle::{CirclePoint, Coset};
+use stwo_prover::core::fields::qm31::{SecureField, QM31};
+use stwo_prover::core::fri::{
+    get_opening_positions, CirclePolyDegreeBound, FriConfig, FriLayerVerifier,
+    FriVerificationError, FOLD_STEP,
+};
+use stwo_prover::core::pcs::{CommitmentSchemeVerifier, TreeVec};
+use stwo_prover::core::poly::circle::SecureCirclePoly;
+use stwo_prover::core::poly::line::LineDomain;
+use stwo_prover::core::proof_of_work::ProofOfWork;
+use stwo_prover::core::prover::{
+    InvalidOodsSampleStructure, StarkProof, LOG_BLOWUP_FACTOR, LOG_LAST_LAYER_DEGREE_BOUND,
+    N_QUERIES, PROOF_OF_WORK_BITS,
+};
+use stwo_prover::core::queries::Queries;
+use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+use stwo_prover::core::{ColumnVec, ComponentVec};
+
+/// Error in generating the hints for the verifier, which happens when the proof is invalid.
+#[derive(Debug)]
+pub enum HintGenerationError {
+    /// The proof does not have exactly one trace tree and one composition tree.
+    InvalidNumTrees,
+    /// The sampled values do not match the structure of the sampled points.
+    MalformedSampledValues,
+    /// The composition polynomial does not match the trace at the OODS point.
+    OodsNotMatching,
+    /// The number of FRI layers does not match the degree bound.
+    InvalidNumFriLayers,
+    /// The last layer polynomial does not have exactly `2^log_last_layer_degree_bound`
+    /// coefficients, which the verifier pulls one by one.
+    LastLayerDegreeInvalid,
+    /// The proof of work does not have enough leading zeros.
+    ProofOfWork,
+    /// The queried values do not match the commitment of the given tree.
+    DecommitmentFailure {
+        /// Index of the tree, 0 for the trace and 1 for the composition.
+        tree_index: usize,
+    },
+    /// The FRI layers do not match the queried values.
+    Fri(FriVerificationError),
+    /// The AIR or the configuration is not supported by the verifier in Bitcoin script.
+    UnsupportedAir(UnsupportedAirError),
+}
+
+impl Display for HintGenerationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HintGenerationError::InvalidNumTrees => write!(f, "invalid number of trees"),
+            HintGenerationError::MalformedSampledValues => {
+                write!(f, "unexpected sampled values structure")
+            }
+            HintGenerationError::OodsNotMatching => write!(f, "OODS values do not match"),
+            HintGenerationError::InvalidNumFriLayers => write!(f, "invalid number of FRI layers"),
+            HintGenerationError::LastLayerDegreeInvalid => {
+                write!(f, "last layer degree is invalid")
+            }
+            HintGenerationError::ProofOfWork => write!(f, "proof of work verification failed"),
+            HintGenerationError::DecommitmentFailure { tree_index } => {
+                write!(f, "invalid Merkle decommitment of tree {}", tree_index)
+            }
+            HintGenerationError::Fri(e) => write!(f, "FRI verification failed: {}", e),
+            HintGenerationError::UnsupportedAir(e) => write!(f, "unsupported AIR: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for HintGenerationError {}
+
+impl From<FriVerificationError> for HintGenerationError {
+    fn from(e: FriVerificationError) -> Self {
+        HintGenerationError::Fri(e)
+    }
+}
+
+impl From<UnsupportedAirError> for HintGenerationError {
+    fn from(e: UnsupportedAirError) -> Self {
+        HintGenerationError::UnsupportedAir(e)
+    }
+}
+
+/// Error in building the witnesses of the stages of the verifier.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StageWitnessError {
+    /// The hints do not match the ones pulled by the verifier.
+    Hints(HintStreamError),
+    /// A stage cannot be run on the hints, see `StageError`.
+    Stage(StageError),
+}
+
+impl Display for StageWitnessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StageWitnessError::Hints(e) => write!(f, "invalid hints: {}", e),
+            StageWitnessError::Stage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StageWitnessError {}
+
+impl From<HintStreamError> for StageWitnessError {
+    fn from(e: HintStreamError) -> Self {
+        StageWitnessError::Hints(e)
+    }
+}
+
+impl From<StageError> for StageWitnessError {
+    fn from(e: StageError) -> Self {
+        StageWitnessError::Stage(e)
+    }
+}
+
+/// Error when the AIR is not supported by the verifier in Bitcoin script, see `ScriptAir`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UnsupportedAirError {
+    /// The AIR has no trace columns.
+    EmptyTrace,
+    /// The trace columns do not all have the same log size.
+    MixedTraceLogSizes,
+    /// The mask offsets are not given for each trace column.
+    InvalidMaskOffsets,
+    /// The composition polynomial does not have a larger log size than the trace.
+    CompositionNotLargerThanTrace,
+    /// The trace is not folded into an inner FRI layer, or there are no inner FRI layers.
+    TooFewFriLayers,
+    /// The columns of the trace have a log size below 3, which the FRI query does not fold.
+    ColumnLogSizeTooSmall,
+    /// The number of bits of the PoW is zero or larger than the digest.
+    InvalidPowBits,
+    /// The slots of the verifier do not fit on the stack.
+    Layout(LayoutError),
+}
+
+impl Display for UnsupportedAirError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UnsupportedAirError::EmptyTrace => write!(f, "the trace has no columns"),
+            UnsupportedAirError::MixedTraceLogSizes => {
+                write!(f, "the trace columns have different log sizes")
+            }
+            UnsupportedAirError::InvalidMaskOffsets => {
+                write!(f, "the mask offsets do not match the trace columns")
+            }
+            UnsupportedAirError::CompositionNotLargerThanTrace => {
+                write!(f, "the composition polynomial is not larger than the trace")
+            }
+            UnsupportedAirError::TooFewFriLayers => {
+                write!(f, "the trace is not folded into an inner FRI layer")
+            }
+            UnsupportedAirError::ColumnLogSizeTooSmall => {
+                write!(f, "the column log size of the trace is below 3")
+            }
+            UnsupportedAirError::InvalidPowBits => {
+                write!(f, "the number of bits of the PoW is out of range")
+            }
+            UnsupportedAirError::Layout(e) => write!(f, "layout error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for UnsupportedAirError {}
+
+impl From<LayoutError> for UnsupportedAirError {
+    fn from(e: LayoutError) -> Self {
+        UnsupportedAirError::Layout(e)
+    }
+}
+
+/// The sizes of the verifier for an AIR supported by the verifier in Bitcoin script.
+struct VerifierSizes {
+    /// Log size of the trace columns, before the blowup.
+    trace_log_size: u32,
+    /// Number of the trace columns.
+    n_trace_columns: usize,
+    /// Number of the mask points, over all the trace columns.
+    n_mask_points: usize,
+    /// Column log size of the trace.
+    t: usize,
+    /// Column log size of the composition polynomial, which is the largest one.
+    n: usize,
+    /// Number of the inner FRI layers.
+    n_layers: usize,
+}
+
+impl VerifierSizes {
+    /// Compute the sizes, or return an error if the AIR is not supported.
+    fn new<A: ScriptAir>(air: &A, config: VerifierConfig) -> Result<Self, UnsupportedAirError> {
+        let log_blowup_factor = config.fri_config.log_blowup_factor;
+        let log_last_layer_degree_bound = config.fri_config.log_last_layer_degree_bound;
+
+        let trace_column_log_sizes = air.column_log_sizes();
+        let trace_log_size = *trace_column_log_sizes
+            .first()
+            .ok_or(UnsupportedAirError::EmptyTrace)?;
+        if !trace_column_log_sizes.iter().all_equal() {
+            return Err(UnsupportedAirError::MixedTraceLogSizes);
+        }
+
+        let mask_offsets = air.mask_offsets();
+        if mask_offsets.len() != trace_column_log_sizes.len() {
+            return Err(UnsupportedAirError::InvalidMaskOffsets);
+        }
+
+        let t = (trace_log_size + log_blowup_factor) as usize;
+        let n = (air.composition_log_degree_bound() + log_blowup_factor) as usize;
+        if n <= t {
+            return Err(UnsupportedAirError::CompositionNotLargerThanTrace);
+        }
+        // the FRI query expects each column to have a log size of at least 3
+        if t < 3 {
+            return Err(UnsupportedAirError::ColumnLogSizeTooSmall);
+        }
+
+        // the trace joins the FRI at the inner layer n - t
+        let n_layers = n
+            .checked_sub(1 + log_blowup_factor as usize + log_last_layer_degree_bound as usize)
+            .filter(|&n_layers| n - t < n_layers)
+            .ok_or(UnsupportedAirError::TooFewFriLayers)?;
+
+        // the PoW checks the zero bits at the end of a 32-byte digest
+        if config.pow_bits == 0 || config.pow_bits > 256 {
+            return Err(UnsupportedAirError::InvalidPowBits);
+        }
+
+        Ok(Self {
+            trace_log_size,
+            n_trace_columns: trace_column_log_sizes.len(),
+            n_mask_points: mask_offsets.iter().map(|offsets| offsets.len()).sum(),
+            t,
+            n,
+            n_layers,
+        })
+    }
+}
+
+/// Parameters of the FRI and the PoW for the verifier, which must match the ones of the prover.
+#[derive(Clone, Copy, Debug)]
+pub struct VerifierConfig {
+    /// FRI parameters, including the blowup factor and the number of queries.
+    pub fri_config: FriConfig,
+    /// Number of bits of the PoW.
+    pub pow_bits: u32,
+}
+
+impl Default for VerifierConfig {
+    fn default() -> Self {
+        Self {
+            fri_config: FriConfig::new(LOG_LAST_LAYER_DEGREE_BOUND, LOG_BLOWUP_FACTOR, N_QUERIES),
+            pow_bits: PROOF_OF_WORK_BITS,
+        }
+    }
+}
+
+/// All the hints for the verifier (note: proof is also provided as a hint).
+#[derive(Clone)]
+pub struct VerifierHints {
+    /// Commitments from the proof.
+    pub commitments: [BWSSha256Hash; 2],
+
+    /// random_coeff comes from adding `proof.commitments[0]` to the channel.
+    pub random_coeff_hint: DrawHints,
+
+    /// OODS hint.
+    pub oods_hint: OODSHint,
+
+    /// trace oods values, for each column and then for each mask point.
+    pub trace_oods_values: Vec<SecureField>,
+
+    /// composition odds raw values.
+    pub composition_oods_values: [SecureField; 4],
+
+    /// Composition hint.
+    pub composition_hint: CompositionHint,
+
+    /// second random_coeff hint
+    pub random_coeff_hint2: DrawHints,
+
+    /// circle_poly_alpha hint
+    pub circle_poly_alpha_hint: DrawHints,
+
+    /// fri commit and hints for deriving the folding parameter
+    pub fri_commitment_and_folding_hints: Vec<(BWSSha256Hash, DrawHints)>,
+
+    /// last layer poly coefficients (in the bit-reversed order)
+    pub last_layer: Vec<QM31>,
+
+    /// PoW hint
+    pub pow_hint: PoWHint,
+
+    /// Query sampling hints
+    pub queries_hints: DrawHints,
+
+    /// Merkle proofs of the trace values for each query (in the order they are drawn)
+    pub merkle_proofs_traces: Vec<MerkleTreeTwinProof>,
+
+    /// Merkle proofs of the composition values for each query (in the order they are drawn)
+    pub merkle_proofs_compositions: Vec<MerkleTreeTwinProof>,
+
+    /// Hints for the DEEP quotients of each query (in the order they are drawn), for each column
+    /// log size in the increasing order
+    pub fri_answer_hints: Vec<Vec<FriAnswerPairHint>>,
+
+    /// FRI hints for each query (in the order they are drawn)
+    pub fri_query_hints: Vec<FriQueryHint>,
+}
+
+impl VerifierHints {
+    /// Write the hints in the order they are pulled by the verifier, with the labels of their
+    /// pulls, which fails if the order declared by the verifier is not the one of the hints.
+    pub fn write(&self, writer: &mut HintWriter) -> Result<(), HintStreamError> {
+        writer.write("commitments[0]", StateValue::Digest(self.commitments[0]))?;
+        self.random_coeff_hint.write(writer, "random_coeff_hint")?;
+        writer.write("commitments[1]", StateValue::Digest(self.commitments[1]))?;
+        self.oods_hint.write(writer, "oods_hint")?;
+        for (i, v) in self.trace_oods_values.iter().enumerate() {
+            writer.write(&format!("trace_oods_values[{}]", i), StateValue::QM31(*v))?;
+        }
+        for (i, v) in self.composition_oods_values.iter().enumerate() {
+            writer.write(
+                &format!("composition_oods_values[{}]", i),
+                StateValue::QM31(*v),
+            )?;
+        }
+        self.composition_hint.write(writer, "composition_hint")?;
+        self.random_coeff_hint2
+            .write(writer, "random_coeff_hint2")?;
+        self.circle_poly_alpha_hint
+            .write(writer, "circle_poly_alpha_hint")?;
+        for (i, (c, h)) in self.fri_commitment_and_folding_hints.iter().enumerate() {
+            writer.write(&format!("fri_commitment[{}]", i), StateValue::Digest(*c))?;
+            h.write(writer, &format!("fri_folding_hint[{}]", i))?;
+        }
+        for (i, coeff) in self.last_layer.iter().enumerate() {
+            writer.write(&format!("last_layer[{}]", i), StateValue::QM31(*coeff))?;
+        }
+        self.pow_hint.write(writer, "pow_hint")?;
+        self.queries_hints.write(writer, "queries_hints")?;
+        for (i, (((trace_proof, composition_proof), fri_answer_hints), fri_query_hint)) in self
+            .merkle_proofs_traces
+            .iter()
+            .zip(self.merkle_proofs_compositions.iter())
+            .zip(self.fri_answer_hints.iter())
+            .zip(self.fri_query_hints.iter())
+            .enumerate()
+        {
+            trace_proof.write(writer, &format!("merkle_proofs_traces[{}]", i))?;
+            composition_proof.write(writer, &format!("merkle_proofs_compositions[{}]", i))?;
+            for (j, fri_answer_hint) in fri_answer_hints.iter().enumerate() {
+                fri_answer_hint.write(writer, &format!("fri_answer_hints[{}][{}]", i, j))?;
+            }
+            fri_query_hint.write(writer, &format!("fri_query_hints[{}]", i))?;
+        }
+        Ok(())
+    }
+
+    /// Return the stack elements of the hints, from the bottom to the top, in the order declared
+    /// by the stream of the verifier, see `VerifierGadget::hint_stream`.
+    pub fn to_elements(&self, stream: &HintStream) -> Result<Vec<Vec<u8>>, HintStreamError> {
+        let mut writer = stream.writer();
+        self.write(&mut writer)?;
+        writer.finish()
+    }
+
+    /// Split the hints among the stages of the verifier and return the witness of each stage: its
+    /// hints, in the order they are pulled from the bottom of the stack, followed by the opening of
+    /// its input state.
+    ///
+    /// The stream must be the one of the verifier, see `VerifierGadget::hint_stream`. To spend a
+    /// leaf of the stage, its script and its control block must be appended.
+    pub fn into_stage_witnesses(
+        self,
+        stream: &HintStream,
+        stages: &[Stage],
+    ) -> Result<Vec<Witness>, StageWitnessError> {
+        let hints = self.to_elements(stream)?;
+        Ok(run_stages(stages, hints)?
+            .iter()
+            .map(StageWitness::to_witness)
+            .collect())
+    }
+
+    /// Write the hints in the order they are pulled by the disprovable steps of the verifier, see
+    /// `VerifierGadget::disprovable_steps`, with the labels of their pulls, which fails if the
+    /// order declared by the steps is not the one of the hints.
+    ///
+    /// The PoW is checked on the honest nonce, with the PoW bits of the configuration.
+    fn write_step_hints(
+        &self,
+        writer: &mut HintWriter,
+        config: VerifierConfig,
+    ) -> Result<(), HintStreamError> {
+        self.random_coeff_hint.write(writer, "random_coeff_hint")?;
+        self.oods_hint.write(writer, "oods_hint")?;
+        self.composition_hint.write(writer, "composition_hint")?;
+        self.random_coeff_hint2
+            .write(writer, "random_coeff_hint2")?;
+        self.circle_poly_alpha_hint
+            .write(writer, "circle_poly_alpha_hint")?;
+        for (i, (_, h)) in self.fri_commitment_and_folding_hints.iter().enumerate() {
+            h.write(writer, &format!("fri_folding_hint[{}]", i))?;
+        }
+        PoWCheckHint {
+            prefix: self.pow_hint.prefix.clone(),
+            msb: self.pow_hint.msb,
+            rest: vec![0; config.pow_bits as usize / 8],
+        }
+        .write(writer, "pow_hint")?;
+        self.queries_hints.write(writer, "queries_hints")?;
+
+        // a missing part of the hints of a query is left out, so that the writer reports it
+        let n_layers = self.fri_commitment_and_folding_hints.len();
+        for (q, (((trace_proof, composition_proof), fri_answer_hints), fri_query_hint)) in self
+            .merkle_proofs_traces
+            .iter()
+            .zip(self.merkle_proofs_compositions.iter())
+            .zip(self.fri_answer_hints.iter())
+            .zip(self.fri_query_hints.iter())
+            .enumerate()
+        {
+            // the decommitments of the trace and the composition, each with the path of its
+            // twiddle factors and its DEEP quotients
+            for (label, proof, k) in [
+                (format!("trace_decommitment[{}]", q), trace_proof, 1),
+                (
+                    format!("composition_decommitment[{}]", q),
+                    composition_proof,
+                    0,
+                ),
+            ] {
+                proof.write(writer, &label)?;
+                if let Some(twiddle_proof) = fri_query_hint.twiddle_proofs.get(k) {
+                    twiddle_proof.write(writer, &label)?;
+                }
+                if let Some(fri_answer_hint) = fri_answer_hints.get(1 - k) {
+                    fri_answer_hint.write(writer, &label)?;
+                }
+            }
+
+            // the twin and then the path of the twiddle factors for each inner layer
+            for (j, inner_layer_proof) in fri_query_hint.inner_layer_proofs.iter().enumerate() {
+                inner_layer_proof.write(writer, &format!("fri_twin_decommitment[{}][{}]", q, j))?;
+                if let Some(twiddle_proof) = fri_query_hint.twiddle_proofs.first() {
+                    twiddle_proof.write(writer, &format!("fri_fold[{}][{}]", q, j))?;
+                }
+            }
+
+            // the x-coordinate in the last layer, whose twiddle factor comes from the path of the
+            // twiddle factors if the last layer domain has at least four points
+            if let Some(x) = fri_query_hint.last_layer_x {
+                let label = format!("last_layer_check[{}]", q);
+                if let Some(twiddle_proof) = fri_query_hint
+                    .twiddle_proofs
+                    .first()
+                    .filter(|twiddle_proof| twiddle_proof.elements.len() >= n_layers + 2)
+                {
+                    twiddle_proof.write(writer, &label)?;
+                }
+                writer.write(&label, StateValue::M31(x))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Split the hints among the disprovable steps of the verifier, see
+    /// `VerifierGadget::disprovable_steps`, and return the hints of each step, as stack elements
+    /// from the bottom to the top, for `DisprovableStep::disprove_witness`.
+    ///
+    /// The steps and the configuration must be the ones the hints are generated for, which fails
+    /// if the hints declared by the streams of the steps are not the ones written.
+    pub fn step_hints(
+        &self,
+        steps: &[DisprovableStep],
+        config: VerifierConfig,
+    ) -> Result<Vec<Vec<Vec<u8>>>, HintStreamError> {
+        let mut stream = HintStream::new();
+        for step in steps.iter() {
+            stream.extend(step.stream.clone());
+        }
+        let mut writer = stream.writer();
+        self.write_step_hints(&mut writer, config)?;
+
+        let mut hints = writer.finish()?.into_iter();
+        Ok(steps
+            .iter()
+            .map(|step| hints.by_ref().take(step.stream.n_elements()).collect())
+            .collect())
+    }
+}
+
+/// A verifier program that generates hints, with the FRI and PoW parameters of the prover.
+pub fn verify_with_hints<A: ScriptAir>(
+    proof: StarkProof,
+    air: &A,
+    channel: &mut BWSSha256Channel,
+    config: VerifierConfig,
+) -> Result<VerifierHints, HintGenerationError> {
+    if proof.commitments.0.len() != 2
+        || proof.commitment_scheme_proof.decommitments.0.len() != 2
+        || proof.commitment_scheme_proof.queried_values.0.len() != 2
+    {
+        return Err(HintGenerationError::InvalidNumTrees);
+    }
+
+    // the sizes are checked before the proof is read, so that the column log sizes are consistent
+    VerifierSizes::new(air, config)?;
+
+    let fri_config = config.fri_config;
+
+    // Read trace commitment.
+    let mut commitment_scheme = CommitmentSchemeVerifier::new();
+    commitment_scheme.commit(proof.commitments[0], air.column_log_sizes(), channel);
+    let (random_coeff, random_coeff_hint) = channel.draw_felt_and_hints();
+
+    // Read composition polynomial commitment.
+    commitment_scheme.commit(
+        proof.commitments[1],
+        vec![air.composition_log_degree_bound(); 4],
+        channel,
+    );
+
+    // Draw OODS point.
+    let (oods_point, oods_hint) = CirclePoint::<SecureField>::get_random_point_with_hint(channel);
+
+    // Get mask sample points relative to oods point.
+    let trace_sample_points = air.mask_points(oods_point);
+
+    // TODO(spapini): Change when we support multiple interactions.
+    // First tree - trace.
+    let mut sampled_points = TreeVec::new(vec![trace_sample_points.flatten()]);
+    // Second tree - composition polynomial.
+    sampled_points.push(vec![vec![oods_point]; 4]);
+
+    // the hints rely on each column having one sampled value for each sampled point
+    let sampled_values = &proof.commitment_scheme_proof.sampled_values;
+    if sampled_values.0.len() != sampled_points.0.len()
+        || sampled_points
+            .0
+            .iter()
+            .zip(sampled_values.0.iter())
+            .any(|(points, values)| {
+                points.len() != values.len()
+                    || points
+                        .iter()
+                        .zip(values.iter())
+                        .any(|(points, values)| points.len() != values.len())
+            })
+    {
+        return Err(HintGenerationError::MalformedSampledValues);
+    }
+
+    // TODO(spapini): Save clone.
+    let (trace_oods_values, composition_oods_value) =
+        sampled_values_to_mask(air, proof.commitment_scheme_proof.sampled_values.clone())
+            .map_err(|_| HintGenerationError::MalformedSampledValues)?;
+
+    if composition_oods_value
+        != air.eval_composition_polynomial_at_point(oods_point, &trace_oods_values, random_coeff)
+    {
+        return Err(HintGenerationError::OodsNotMatching);
+    }
+
+    let composition_hint = air.composition_hint(oods_point, &trace_oods_values);
+
+    let sample_values = &proof.commitment_scheme_proof.sampled_values.0;
+
+    channel.mix_felts(
+        &proof
+            .commitment_scheme_proof
+            .sampled_values
+            .clone()
+            .flatten_cols(),
+    );
+    let (random_coeff, random_coeff_hint2) = channel.draw_felt_and_hints();
+
+    let bounds = commitment_scheme
+        .column_log_sizes()
+        .zip_cols(&sampled_points)
+        .map_cols(|(log_size, sampled_points)| {
+            vec![
+                CirclePolyDegreeBound::new(log_size - fri_config.log_blowup_factor);
+                sampled_points.len()
+            ]
+        })
+        .flatten_cols()
+        .into_iter()
+        .sorted()
+        .rev()
+        .dedup()
+        .collect_vec();
+
+    // from fri-verifier
+    let max_column_bound = bounds[0];
+
+    // Circle polynomials can all be folded with the same alpha.
+    let (circle_poly_alpha, circle_poly_alpha_hint) = channel.draw_felt_and_hints();
+
+    let mut inner_layers = Vec::new();
+    let mut layer_bound = max_column_bound.fold_to_line();
+    let mut layer_domain = LineDomain::new(Coset::half_odds(
+        layer_bound.log_degree_bound + fri_config.log_blowup_factor,
+    ));
+
+    let mut fri_commitment_and_folding_hints = vec![];
+
+    for (layer_index, proof) in proof
+        .commitment_scheme_proof
+        .fri_proof
+        .inner_layers
+        .into_iter()
+        .enumerate()
+    {
+        channel.mix_digest(proof.commitment);
+
+        let (folding_alpha, folding_alpha_hint) = channel.draw_felt_and_hints();
+
+        fri_commitment_and_folding_hints.push((proof.commitment, folding_alpha_hint));
+
+        inner_layers.push(FriLayerVerifier {
+            degree_bound: layer_bound,
+            domain: layer_domain,
+            folding_alpha,
+            layer_index,
+            proof,
+        });
+
+        layer_bound = layer_bound
+            .fold(FOLD_STEP)
+            .ok_or(HintGenerationError::InvalidNumFriLayers)?;
+        layer_domain = layer_domain.double();
+    }
+
+    if layer_bound.log_degree_bound != fri_config.log_last_layer_degree_bound {
+        return Err(HintGenerationError::InvalidNumFriLayers);
+    }
+
+    let last_layer_domain = layer_domain;
+    let last_layer_poly = proof.commitment_scheme_proof.fri_proof.last_layer_poly;
+
+    // the script mixes exactly the coefficients of the degree bound, so a shorter polynomial would
+    // give hints pulled out of order
+    if last_layer_poly.len() != (1 << fri_config.log_last_layer_degree_bound) {
+        return Err(HintGenerationError::LastLayerDegreeInvalid);
+    }
+
+    channel.mix_felts(&last_layer_poly);
+
+    let pow_hint = PoWHint::new(
+        channel.digest,
+        proof.commitment_scheme_proof.proof_of_work.nonce,
+        config.pow_bits,
+    );
+
+    // Verify proof of work.
+    ProofOfWork::new(config.pow_bits)
+        .verify(channel, &proof.commitment_scheme_proof.proof_of_work)
+        .map_err(|_| HintGenerationError::ProofOfWork)?;
+
+    let column_log_sizes = bounds
+        .iter()
+        .dedup()
+        .map(|b| b.log_degree_bound + fri_config.log_blowup_factor)
+        .collect_vec();
+
+    // keep the queries in the order they are drawn, which is how the script processes them
+    let (raw_queries, queries_hints) =
+        channel.draw_queries_and_hints(fri_config.n_queries, column_log_sizes[0] as usize);
+    let queries = Queries {
+        positions: raw_queries.iter().copied().sorted().dedup().collect(),
+        log_domain_size: column_log_sizes[0],
+    };
+    let fri_query_domains = get_opening_positions(&queries, &column_log_sizes);
+    let opening_positions = fri_query_domains
+        .iter()
+        .map(|(&log_size, domain)| {
+            let mut points = domain.flatten();
+            points.sort();
+            (log_size, points)
+        })
+        .collect::<BTreeMap<u32, Vec<usize>>>();
+
+    // Reconstruct the trace tree and the composition tree from the decommitments for the Merkle paths.
+    let sparse_merkle_trees = commitment_scheme
+        .trees
+        .0
+        .iter()
+        .zip(proof.commitment_scheme_proof.decommitments.0.iter())
+        .zip(proof.commitment_scheme_proof.queried_values.0.iter())
+        .enumerate()
+        .map(|(tree_index, ((tree, decommitment), queried_values))| {
+            let log_size = tree.column_log_sizes[0];
+            if !tree.column_log_sizes.iter().all_equal() {
+                return Err(HintGenerationError::DecommitmentFailure { tree_index });
+            }
+
+            let positions = opening_positions
+                .get(&log_size)
+                .ok_or(HintGenerationError::DecommitmentFailure { tree_index })?;
+            SparseMerkleTree::from_queried_columns(
+                log_size as usize,
+                positions,
+                queried_values,
+                &decommitment.hash_witness,
+            )
+            .filter(|sparse_merkle_tree| {
+                sparse_merkle_tree.root_hash() == proof.commitments[tree_index]
+            })
+            .ok_or(HintGenerationError::DecommitmentFailure { tree_index })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut merkle_proofs = sparse_merkle_trees
+        .iter()
+        .map(|sparse_merkle_tree| {
+            let log_size = sparse_merkle_tree.layers.len() - 1;
+            raw_queries
+                .iter()
+                .map(|&query| {
+                    sparse_merkle_tree
+                        .query((query >> (column_log_sizes[0] as usize - log_size)) & !1)
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    let merkle_proofs_traces = merkle_proofs.remove(0);
+    let merkle_proofs_compositions = merkle_proofs.remove(0);
+
+    // Verify merkle decommitments, which also checks the structure of the queried values.
+    commitment_scheme
+        .trees
+        .as_ref()
+        .zip(proof.commitment_scheme_proof.decommitments)
+        .zip(proof.commitment_scheme_proof.queried_values.clone())
+        .map(|((tree, decommitment), queried_values)| {
+            tree.verify(opening_positions.clone(), queried_values, decommitment)
+        })
+        .0
+        .into_iter()
+        .enumerate()
+        .try_for_each(|(tree_index, result)| {
+            result.map_err(|_| HintGenerationError::DecommitmentFailure { tree_index })
+        })?;
+
+    // compute the DEEP quotients at the opening positions, which are the inputs of the circle FRI
+    let samples = sampled_points
+        .0
+        .iter()
+        .flatten()
+        .zip(sample_values.iter().flatten())
+        .map(|(points, values)| {
+            points
+                .iter()
+                .copied()
+                .zip(values.iter().copied())
+                .collect_vec()
+        })
+        .collect_vec();
+    let log_sizes_per_column = commitment_scheme
+        .column_log_sizes()
+        .0
+        .iter()
+        .flatten()
+        .copied()
+        .collect_vec();
+    let first_layer_evals = fri_answers(
+        &log_sizes_per_column,
+        &samples,
+        random_coeff,
+        &opening_positions,
+        &proof
+            .commitment_scheme_proof
+            .queried_values
+            .0
+            .iter()
+            .flatten()
+            .cloned()
+            .collect_vec(),
+    );
+
+    // the script computes the DEEP quotients from the smallest column log size
+    let sample_batches = sample_batches_by_log_size(&log_sizes_per_column, &samples);
+    let fri_answer_hints = raw_queries
+        .iter()
+        .map(|&query| {
+            sample_batches
+                .iter()
+                .rev()
+                .map(|(log_size, sample_batches)| {
+                    FriAnswerPairHint::new(
+                        sample_batches,
+                        *log_size,
+                        query >> (column_log_sizes[0] - log_size),
+                    )
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+
+    let fri_query_hints = generate_fri_query_hints(
+        &raw_queries,
+        &column_log_sizes,
+        &first_layer_evals,
+        circle_poly_alpha,
+        &inner_layers,
+        last_layer_domain,
+        &last_layer_poly,
+    )?;
+
+    Ok(VerifierHints {
+        commitments: [proof.commitments[0], proof.commitments[1]],
+        random_coeff_hint,
+        oods_hint,
+        trace_oods_values: sample_values[0].iter().flatten().copied().collect(),
+        composition_oods_values: [
+            sample_values[1][0][0],
+            sample_values[1][1][0],
+            sample_values[1][2][0],
+            sample_values[1][3][0],
+        ],
+        composition_hint,
+        random_coeff_hint2,
+        circle_poly_alpha_hint,
+        fri_commitment_and_folding_hints,
+        last_layer: last_layer_poly.to_vec(),
+        pow_hint,
+        queries_hints,
+        merkle_proofs_traces,
+        merkle_proofs_compositions,
+        fri_answer_hints,
+        fri_query_hints,
+    })
+}
+
+fn sampled_values_to_mask(
+    air: &impl Air,
+    mut sampled_values: TreeVec<ColumnVec<Vec<SecureField>>>,
+) -> Result<(ComponentVec<Vec<SecureField>>, SecureField), InvalidOodsSampleStructure> {
+    let composition_partial_sampled_values =
+        sampled_values.pop().ok_or(InvalidOodsSampleStructure)?;
+    let composition_oods_value = SecureCirclePoly::<CpuBackend>::eval_from_partial_evals(
+        composition_partial_sampled_values
+            .iter()
+            .flatten()
+            .cloned()
+            .collect_vec()
+            .try_into()
+            .map_err(|_| InvalidOodsSampleStructure)?,
+    );
+
+    // Retrieve sampled mask values for each component.
+    let flat_trace_values = &mut sampled_values
+        .pop()
+        .ok_or(InvalidOodsSampleStructure)?
+        .into_iter();
+    let trace_oods_values = ComponentVec(
+        air.components()
+            .iter()
+            .map(|c| {
+                flat_trace_values
+                    .take(c.mask_points(CirclePoint::zero()).len())
+                    .collect_vec()
+            })
+            .collect(),
+    );
+
+    Ok((trace_oods_values, composition_oods_value))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunker::{StateItem, StateValue};
+    use crate::hint_stream::{HintDecl, HintStreamError};
+    use crate::verifier::{
+        verify_with_hints, HintGenerationError, UnsupportedAirError, VerifierConfig, VerifierGadget,
+    };
+    use stwo_prover::core::channel::{BWSSha256Channel, Channel};
+    use stwo_prover::core::fields::m31::{BaseField, M31};
+    use stwo_prover::core::fields::IntoSlice;
+    use stwo_prover::core::fri::FriConfig;
+    use stwo_prover::core::prover::prove;
+    use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hasher;
+    use stwo_prover::core::vcs::hasher::Hasher;
+    use stwo_prover::examples::fibonacci::Fibonacci;
+
+    #[test]
+    fn test_malformed_proof() {
+        let fib = Fibonacci::new(5, M31::reduce(443693538));
+        let new_channel = || {
+            BWSSha256Channel::new(BWSSha256Hasher::hash(BaseField::into_slice(&[fib
+                .air
+                .component
+                .claim])))
+        };
+
+        let new_proof = || prove(&fib.air, &mut new_channel(), vec![fib.get_trace()]).unwrap();
+
+        let mut malformed = new_proof();
+        malformed.commitment_scheme_proof.sampled_values.0[0][0].pop();
+        assert!(matches!(
+            verify_with_hints(
+                malformed,
+                &fib.air,
+                &mut new_channel(),
+                VerifierConfig::default()
+            ),
+            Err(HintGenerationError::MalformedSampledValues)
+        ));
+
+        let mut malformed = new_proof();
+        malformed.commitment_scheme_proof.queried_values.0[0][0].pop();
+        assert!(matches!(
+            verify_with_hints(
+                malformed,
+                &fib.air,
+                &mut new_channel(),
+                VerifierConfig::default()
+            ),
+            Err(HintGenerationError::DecommitmentFailure { tree_index: 0 })
+        ));
+
+        let mut malformed = new_proof();
+        malformed
+            .commitment_scheme_proof
+            .fri_proof
+            .inner_layers
+            .pop();
+        assert!(matches!(
+            verify_with_hints(
+                malformed,
+                &fib.air,
+                &mut new_channel(),
+                VerifierConfig::default()
+            ),
+            Err(HintGenerationError::InvalidNumFriLayers)
+        ));
+
+        // with one fewer inner layer, the degree bound of the last layer has two coefficients, and
+        // the constant polynomial of the proof is too short
+        let mut malformed = new_proof();
+        malformed
+            .commitment_scheme_proof
+            .fri_proof
+            .inner_layers
+            .pop();
+        let config = VerifierConfig {
+            fri_config: FriConfig::new(1, 1, 3),
+            ..VerifierConfig::default()
+        };
+        assert!(matches!(
+            verify_with_hints(malformed, &fib.air, &mut new_channel(), config),
+            Err(HintGenerationError::LastLayerDegreeInvalid)
+        ));
+
+        // the configuration is checked before the proof is read
+        let config = VerifierConfig {
+            fri_config: FriConfig::new(6, 1, 3),
+            ..VerifierConfig::default()
+        };
+        assert!(matches!(
+            verify_with_hints(new_proof(), &fib.air, &mut new_channel(), config),
+            Err(HintGenerationError::UnsupportedAir(
+                UnsupportedAirError::TooFewFriLayers
+            ))
+        ));
+
+        assert!(verify_with_hints(
+            new_proof(),
+            &fib.air,
+            &mut new_channel(),
+            VerifierConfig::default()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_unsupported_air() {
+        let fib = Fibonacci::new(5, M31::reduce(443693538));
+        let channel = BWSSha256Channel::new(BWSSha256Hasher::hash(BaseField::into_slice(&[fib
+            .air
+            .component
+            .claim])));
+
+        // a last layer larger than the composition polynomial leaves no inner FRI layers
+        let config = VerifierConfig {
+            fri_config: FriConfig::new(6, 1, 3),
+            ..VerifierConfig::default()
+        };
+        assert_eq!(
+            VerifierGadget::run_verifier(&fib.air, &channel, config).err(),
+            Some(UnsupportedAirError::TooFewFriLayers)
+        );
+        assert_eq!(
+            VerifierGadget::run_verifier_stages(&fib.air, &channel, config).err(),
+            Some(UnsupportedAirError::TooFewFriLayers)
+        );
+
+        // a trace of 2 rows is too small for the FRI query
+        let small_fib = Fibonacci::new(1, M31::reduce(443693538));
+        let config = VerifierConfig {
+            fri_config: FriConfig::new(0, 1, 3),
+            ..VerifierConfig::default()
+        };
+        assert_eq!(
+            VerifierGadget::run_verifier(&small_fib.air, &channel, config).err(),
+            Some(UnsupportedAirError::ColumnLogSizeTooSmall)
+        );
+
+        // the PoW must check at least one bit, and at most the whole digest
+        for pow_bits in [0, 257] {
+            let config = VerifierConfig {
+                pow_bits,
+                ..VerifierConfig::default()
+            };
+            assert_eq!(
+                VerifierGadget::run_verifier(&fib.air, &channel, config).err(),
+                Some(UnsupportedAirError::InvalidPowBits)
+            );
+        }
+
+        assert!(
+            VerifierGadget::run_verifier(&fib.air, &channel, VerifierConfig::default()).is_ok()
+        );
+    }
+
+    #[test]
+    fn test_hints_order() {
+        let fib = Fibonacci::new(5, M31::reduce(443693538));
+        let new_channel = || {
+            BWSSha256Channel::new(BWSSha256Hasher::hash(BaseField::into_slice(&[fib
+                .air
+                .component
+                .claim])))
+        };
+
+        let proof = prove(&fib.air, &mut new_channel(), vec![fib.get_trace()]).unwrap();
+        let hints = verify_with_hints(
+            proof,
+            &fib.air,
+            &mut new_channel(),
+            VerifierConfig::default(),
+        )
+        .unwrap();
+
+        let stream =
+            VerifierGadget::hint_stream(&fib.air, &new_channel(), VerifierConfig::default())
+                .unwrap();
+        assert_eq!(
+            hints.to_elements(&stream).unwrap().len(),
+            stream.n_elements()
+        );
+
+        // a hint written out of order is reported with the one the verifier pulls at its place
+        let mut writer = stream.writer();
+        writer
+            .write("commitments[0]", StateValue::Digest(hints.commitments[0]))
+            .unwrap();
+        assert_eq!(
+            writer.write("commitments[1]", StateValue::Digest(hints.commitments[1])),
+            Err(HintStreamError::UnexpectedHint {
+                index: 1,
+                expected: Some(stream.decls()[1].clone()),
+                found: HintDecl {
+                    label: "commitments[1]".to_string(),
+                    item: StateItem::Digest,
+                },
+            })
+        );
+
+        // so is a missing hint, which shifts the ones after it
+        let mut wrong_hints = hints.clone();
+        wrong_hints.trace_oods_values.pop();
+        assert!(matches!(
+            wrong_hints.to_elements(&stream),
+            Err(HintStreamError::UnexpectedHint { .. })
+        ));
+    }
+}
@@ -0,0 +1,718 @@
+use crate::air::ScriptAir;
+use crate::channel::ChannelWithHint;
+use crate::chunker::StateValue;
+use crate::constraints::fast_pair_vanishing;
+use crate::fri::fold;
+use crate::hint_stream::HintDecl;
+use crate::merkle_tree::{MerkleTree, MerkleTreeTwinProof};
+use crate::oods::OODS;
+use crate::pow::{hash_with_nonce, PoWHint};
+use crate::precomputed_merkle_tree::PrecomputedMerkleTree;
+use crate::quotients::{domain_point, fri_answer, sample_batches_by_log_size};
+use crate::utils::bit_reverse_index;
+use crate::verifier::{
+    UnsupportedAirError, VerifierConfig, VerifierGadget, VerifierHints, VerifierSizes,
+    N_COMPOSITION_COLUMNS,
+};
+use itertools::Itertools;
+use num_traits::One;
+use std::fmt::{Display, Formatter};
+use stwo_prover::core::air::AirExt;
+use stwo_prover::core::backend::CpuBackend;
+use stwo_prover::core::channel::{BWSSha256Channel, Channel};
+use stwo_prover::core::circle::{CirclePoint, Coset};
+use stwo_prover::core::fields::cm31::CM31;
+use stwo_prover::core::fields::qm31::SecureField;
+use stwo_prover::core::poly::circle::SecureCirclePoly;
+use stwo_prover::core::poly::line::{LineDomain, LinePoly};
+use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hash;
+use stwo_prover::core::ComponentVec;
+
+/// Label reported for a hint left after the ones declared by the stream of the verifier.
+const LEFTOVER_HINT_LABEL: &str = "leftover hints";
+
+/// Error in checking the hints off-chain, which means that the verifier script would fail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HintCheckError {
+    /// Index of the failing hint in the stream of the verifier, see `VerifierGadget::hint_stream`,
+    /// i.e., of the first of its declarations, or the number of the declarations for a leftover
+    /// hint.
+    pub hint_index: usize,
+    /// Label of the failing hint in the stream, as the script shows it, such as
+    /// "fri_commitment[3]".
+    pub label: String,
+}
+
+impl Display for HintCheckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "hint #{} ({}) fails the check",
+            self.hint_index, self.label
+        )
+    }
+}
+
+impl std::error::Error for HintCheckError {}
+
+/// Error in checking the hints off-chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyHintsError {
+    /// The AIR is not supported by the verifier script.
+    UnsupportedAir(UnsupportedAirError),
+    /// A hint fails the check.
+    Hint(HintCheckError),
+}
+
+impl Display for VerifyHintsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VerifyHintsError::UnsupportedAir(e) => write!(f, "unsupported AIR: {}", e),
+            VerifyHintsError::Hint(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for VerifyHintsError {}
+
+impl From<UnsupportedAirError> for VerifyHintsError {
+    fn from(e: UnsupportedAirError) -> Self {
+        VerifyHintsError::UnsupportedAir(e)
+    }
+}
+
+impl From<HintCheckError> for VerifyHintsError {
+    fn from(e: HintCheckError) -> Self {
+        VerifyHintsError::Hint(e)
+    }
+}
+
+/// Cursor over the declarations of the stream of the verifier, in the order the script pulls
+/// them, where the consecutive declarations under the same label are one hint.
+struct HintCursor<'a> {
+    decls: &'a [HintDecl],
+    next_index: usize,
+}
+
+impl<'a> HintCursor<'a> {
+    fn new(decls: &'a [HintDecl]) -> Self {
+        Self {
+            decls,
+            next_index: 0,
+        }
+    }
+
+    /// Move past the next hint, which has the label, returning the error to report if this hint
+    /// fails the check.
+    fn pull(&mut self, label: &str) -> HintCheckError {
+        let err = self.peek();
+        debug_assert_eq!(
+            err.label, label,
+            "the hints are checked in the order of the stream"
+        );
+        while self
+            .decls
+            .get(self.next_index)
+            .is_some_and(|decl| decl.label == label)
+        {
+            self.next_index += 1;
+        }
+        err
+    }
+
+    /// Return the error to report if the next hint is unexpected, without moving.
+    fn peek(&self) -> HintCheckError {
+        HintCheckError {
+            hint_index: self.next_index,
+            label: self
+                .decls
+                .get(self.next_index)
+                .map_or(LEFTOVER_HINT_LABEL, |decl| decl.label.as_str())
+                .to_string(),
+        }
+    }
+}
+
+fn ensure(condition: bool, err: HintCheckError) -> Result<(), HintCheckError> {
+    if condition {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+// verify a Merkle proof of a queried pair, whose lengths are not trusted
+fn verify_twin(
+    root_hash: &BWSSha256Hash,
+    logn: usize,
+    n_columns: usize,
+    proof: &MerkleTreeTwinProof,
+    pos: usize,
+) -> bool {
+    proof.left.len() == n_columns
+        && proof.right.len() == n_columns
+        && proof.siblings.len() == logn - 1
+        && MerkleTree::verify_twin(root_hash, logn, proof, pos)
+}
+
+/// Verify the hints in Rust as `VerifierGadget::run_verifier` does in Bitcoin script, i.e., read
+/// the hints in the same order, and recompute the same channel digests, draws, and checks.
+///
+/// An error indicates either that the AIR is not supported, as `run_verifier` reports, or the
+/// first hint at which the script would fail.
+pub fn verify_hints<A: ScriptAir>(
+    air: &A,
+    channel: &BWSSha256Channel,
+    hints: &VerifierHints,
+    config: VerifierConfig,
+) -> Result<(), VerifyHintsError> {
+    check_hints(air, channel, hints, config).map(|_| ())
+}
+
+/// Return the initial state of the disprovable steps of the verifier, i.e., the values that the
+/// prover asserts before any step, from the bottom to the top, after checking the hints as
+/// `verify_hints` does, see `VerifierGadget::disprovable_steps`.
+///
+/// The initial state has the commitments, the oods values, the FRI commitments, the last layer,
+/// the PoW nonce, and then, for each query, the folded circle polynomials of the largest size and
+/// of the trace, and the twins in the inner FRI layers.
+pub fn disprove_initial_state<A: ScriptAir>(
+    air: &A,
+    channel: &BWSSha256Channel,
+    hints: &VerifierHints,
+    config: VerifierConfig,
+) -> Result<Vec<StateValue>, VerifyHintsError> {
+    let first_layer_folds = check_hints(air, channel, hints, config)?;
+
+    let mut state = vec![
+        StateValue::Digest(hints.commitments[0]),
+        StateValue::Digest(hints.commitments[1]),
+    ];
+    for value in hints
+        .trace_oods_values
+        .iter()
+        .chain(hints.composition_oods_values.iter())
+    {
+        state.push(StateValue::QM31(*value));
+    }
+    for (commitment, _) in hints.fri_commitment_and_folding_hints.iter() {
+        state.push(StateValue::Digest(*commitment));
+    }
+    for coeff in hints.last_layer.iter() {
+        state.push(StateValue::QM31(*coeff));
+    }
+    state.push(StateValue::Bytes(
+        hints.pow_hint.nonce.to_le_bytes().to_vec(),
+    ));
+    for (folded, fri_query_hint) in first_layer_folds.iter().zip(hints.fri_query_hints.iter()) {
+        state.push(StateValue::QM31(folded[0]));
+        state.push(StateValue::QM31(folded[1]));
+        for proof in fri_query_hint.inner_layer_proofs.iter() {
+            state.push(StateValue::QM31(proof.twin));
+        }
+    }
+
+    debug_assert!(state
+        .iter()
+        .map(StateValue::item)
+        .eq(
+            VerifierGadget::disprove_initial_slots(&VerifierSizes::new(air, config)?, config)
+                .into_iter()
+                .map(|(_, item)| item)
+        ));
+    Ok(state)
+}
+
+/// Check the hints as `verify_hints` does, and return the folded circle polynomials of each
+/// query, of the largest size and then of the trace.
+fn check_hints<A: ScriptAir>(
+    air: &A,
+    channel: &BWSSha256Channel,
+    hints: &VerifierHints,
+    config: VerifierConfig,
+) -> Result<Vec<[SecureField; 2]>, VerifyHintsError> {
+    let log_last_layer_degree_bound = config.fri_config.log_last_layer_degree_bound;
+    let n_queries = config.fri_config.n_queries;
+
+    let VerifierSizes {
+        n_trace_columns,
+        n_mask_points,
+        t,
+        n,
+        n_layers,
+        ..
+    } = VerifierSizes::new(air, config)?;
+
+    let stream = VerifierGadget::hint_stream(air, channel, config)?;
+    let mut channel = channel.clone();
+    let mut cursor = HintCursor::new(stream.decls());
+
+    // draw random_coeff after the trace commitment
+    cursor.pull("commitments[0]");
+    channel.mix_digest(hints.commitments[0]);
+    let (random_coeff, random_coeff_hint) = channel.draw_felt_and_hints();
+    ensure(
+        hints.random_coeff_hint == random_coeff_hint,
+        cursor.pull("random_coeff_hint"),
+    )?;
+
+    // draw the OODS point after the composition commitment
+    cursor.pull("commitments[1]");
+    channel.mix_digest(hints.commitments[1]);
+    let (oods_point, oods_hint) =
+        CirclePoint::<SecureField>::get_random_point_with_hint(&mut channel);
+    ensure(
+        hints.oods_hint.x == oods_hint.x
+            && hints.oods_hint.y == oods_hint.y
+            && hints.oods_hint.hint == oods_hint.hint,
+        cursor.pull("oods_hint"),
+    )?;
+
+    // mix the trace oods values and the composition oods raw values one by one
+    for i in 0..n_mask_points {
+        let err = cursor.pull(&format!("trace_oods_values[{}]", i));
+        channel.mix_felts(&[*hints.trace_oods_values.get(i).ok_or(err)?]);
+    }
+    ensure(
+        hints.trace_oods_values.len() == n_mask_points,
+        cursor.peek(),
+    )?;
+    // the composition oods raw values are checked against the composition hint below, and a
+    // mismatch is reported at the first of them
+    let composition_oods_values_err = cursor.peek();
+    for (i, value) in hints.composition_oods_values.iter().enumerate() {
+        cursor.pull(&format!("composition_oods_values[{}]", i));
+        channel.mix_felts(&[*value]);
+    }
+
+    // check the composition polynomial at the OODS point
+    let mut trace_oods_values = hints.trace_oods_values.iter().copied();
+    let mask_values = ComponentVec(
+        air.mask_points(oods_point)
+            .0
+            .iter()
+            .map(|points| {
+                points
+                    .iter()
+                    .map(|column_points| {
+                        trace_oods_values
+                            .by_ref()
+                            .take(column_points.len())
+                            .collect_vec()
+                    })
+                    .collect_vec()
+            })
+            .collect_vec(),
+    );
+    ensure(
+        hints.composition_hint.constraint_eval_quotients_by_mask
+            == air
+                .composition_hint(oods_point, &mask_values)
+                .constraint_eval_quotients_by_mask,
+        cursor.pull("composition_hint"),
+    )?;
+    ensure(
+        SecureCirclePoly::<CpuBackend>::eval_from_partial_evals(hints.composition_oods_values)
+            == air.eval_composition_polynomial_at_point(oods_point, &mask_values, random_coeff),
+        composition_oods_values_err,
+    )?;
+
+    // draw random_coeff2 and circle_poly_alpha
+    let (random_coeff2, random_coeff_hint2) = channel.draw_felt_and_hints();
+    ensure(
+        hints.random_coeff_hint2 == random_coeff_hint2,
+        cursor.pull("random_coeff_hint2"),
+    )?;
+    let (circle_poly_alpha, circle_poly_alpha_hint) = channel.draw_felt_and_hints();
+    ensure(
+        hints.circle_poly_alpha_hint == circle_poly_alpha_hint,
+        cursor.pull("circle_poly_alpha_hint"),
+    )?;
+
+    // draw the folding alphas after the inner layer commitments
+    let mut layer_commitments = vec![];
+    let mut folding_alphas = vec![];
+    for i in 0..n_layers {
+        let err = cursor.pull(&format!("fri_commitment[{}]", i));
+        let (commitment, folding_alpha_hint) =
+            hints.fri_commitment_and_folding_hints.get(i).ok_or(err)?;
+        channel.mix_digest(*commitment);
+
+        let (folding_alpha, expected_hint) = channel.draw_felt_and_hints();
+        ensure(
+            *folding_alpha_hint == expected_hint,
+            cursor.pull(&format!("fri_folding_hint[{}]", i)),
+        )?;
+
+        layer_commitments.push(*commitment);
+        folding_alphas.push(folding_alpha);
+    }
+    ensure(
+        hints.fri_commitment_and_folding_hints.len() == n_layers,
+        cursor.peek(),
+    )?;
+
+    // mix the last layer coefficients one by one
+    for i in 0..(1 << log_last_layer_degree_bound) {
+        let err = cursor.pull(&format!("last_layer[{}]", i));
+        channel.mix_felts(&[*hints.last_layer.get(i).ok_or(err)?]);
+    }
+    ensure(
+        hints.last_layer.len() == 1 << log_last_layer_degree_bound,
+        cursor.peek(),
+    )?;
+
+    // check the PoW, which ends with zero bits, and mix the nonce
+    let pow_digest = hash_with_nonce(channel.digest.as_ref(), hints.pow_hint.nonce);
+    let pow_hint = PoWHint::new(channel.digest, hints.pow_hint.nonce, config.pow_bits);
+    let n_bits = config.pow_bits as usize;
+    ensure(
+        hints.pow_hint.prefix == pow_hint.prefix
+            && hints.pow_hint.msb == pow_hint.msb
+            && pow_digest[32 - n_bits / 8..].iter().all(|&b| b == 0)
+            && pow_hint.msb.map_or(true, |msb| msb < 1 << (8 - n_bits % 8)),
+        cursor.pull("pow_hint"),
+    )?;
+    channel.mix_nonce(hints.pow_hint.nonce);
+
+    // draw the queries
+    let (queries, queries_hints) = channel.draw_queries_and_hints(n_queries, n);
+    ensure(
+        hints.queries_hints == queries_hints,
+        cursor.pull("queries_hints"),
+    )?;
+
+    // the sample batches of the trace and of the composition, in the increasing order of log sizes
+    let mut trace_oods_values = hints.trace_oods_values.iter().copied();
+    let mut samples_per_column = air
+        .mask_points(oods_point)
+        .flatten()
+        .iter()
+        .map(|points| {
+            points
+                .iter()
+                .copied()
+                .zip(trace_oods_values.by_ref())
+                .collect_vec()
+        })
+        .collect_vec();
+    let mut log_sizes_per_column = vec![t as u32; n_trace_columns];
+    for value in hints.composition_oods_values.iter() {
+        samples_per_column.push(vec![(oods_point, *value)]);
+        log_sizes_per_column.push(n as u32);
+    }
+    let mut sample_batches = sample_batches_by_log_size(&log_sizes_per_column, &samples_per_column);
+    sample_batches.reverse();
+
+    let twiddle_trees = [n, t]
+        .iter()
+        .map(|&log_size| PrecomputedMerkleTree::new(log_size - 1))
+        .collect_vec();
+    let last_layer_domain = (0..n_layers).fold(
+        LineDomain::new(Coset::half_odds(n as u32 - 1)),
+        |domain, _| domain.double(),
+    );
+
+    let mut first_layer_folds = vec![];
+    for (i, &query) in queries.iter().enumerate() {
+        // check the queried values against the commitments
+        let err = cursor.pull(&format!("merkle_proofs_traces[{}]", i));
+        let trace_proof = hints.merkle_proofs_traces.get(i).ok_or(err.clone())?;
+        ensure(
+            verify_twin(
+                &hints.commitments[0],
+                t,
+                n_trace_columns,
+                trace_proof,
+                (query >> (n - t + 1)) << 1,
+            ),
+            err,
+        )?;
+
+        let err = cursor.pull(&format!("merkle_proofs_compositions[{}]", i));
+        let composition_proof = hints.merkle_proofs_compositions.get(i).ok_or(err.clone())?;
+        ensure(
+            verify_twin(
+                &hints.commitments[1],
+                n,
+                N_COMPOSITION_COLUMNS,
+                composition_proof,
+                (query >> 1) << 1,
+            ),
+            err,
+        )?;
+
+        // compute the DEEP quotients on the queried pairs, for the trace and then the composition
+        let fri_answer_hints = hints
+            .fri_answer_hints
+            .get(i)
+            .map_or(&[][..], |fri_answer_hints| fri_answer_hints.as_slice());
+
+        let mut first_layer_pairs = vec![];
+        for (j, ((log_size, batches), proof)) in sample_batches
+            .iter()
+            .zip([trace_proof, composition_proof])
+            .enumerate()
+        {
+            let err = cursor.pull(&format!("fri_answer_hints[{}][{}]", i, j));
+            let fri_answer_hint = fri_answer_hints.get(j).ok_or(err.clone())?;
+            let pos = query >> (n - *log_size as usize);
+            let z_even = domain_point(*log_size, pos & !1);
+            let z_odd = domain_point(*log_size, pos | 1);
+            ensure(fri_answer_hint.domain_point == z_even, err.clone())?;
+
+            for (z, hint) in [
+                (z_even, &fri_answer_hint.even),
+                (z_odd, &fri_answer_hint.odd),
+            ] {
+                ensure(
+                    hint.denominator_inverses.len() == batches.len()
+                        && batches.iter().zip(hint.denominator_inverses.iter()).all(
+                            |(batch, &denominator_inverse)| {
+                                fast_pair_vanishing(batch.point, z).1 * denominator_inverse
+                                    == CM31::one()
+                            },
+                        ),
+                    err.clone(),
+                )?;
+            }
+
+            first_layer_pairs.push((
+                fri_answer(batches, random_coeff2, z_even, &proof.left),
+                fri_answer(batches, random_coeff2, z_odd, &proof.right),
+            ));
+        }
+        // an extra hint is where the script expects the FRI hints
+        ensure(
+            fri_answer_hints.len() == sample_batches.len(),
+            cursor.peek(),
+        )?;
+        // the first step of the FRI goes from the largest log size
+        first_layer_pairs.reverse();
+
+        // check the FRI folding
+        let err = cursor.pull(&format!("fri_query_hints[{}]", i));
+        let fri_query_hint = hints.fri_query_hints.get(i).ok_or(err.clone())?;
+        ensure(
+            fri_query_hint.twiddle_proofs.len() == 2
+                && fri_query_hint.inner_layer_proofs.len() == n_layers
+                && fri_query_hint.last_layer_x.is_some() == (log_last_layer_degree_bound > 0),
+            err.clone(),
+        )?;
+
+        let mut folded = vec![];
+        for (((&log_size, twiddle_tree), twiddle_proof), (v_even, v_odd)) in [n, t]
+            .iter()
+            .zip(twiddle_trees.iter())
+            .zip(fri_query_hint.twiddle_proofs.iter())
+            .zip(first_layer_pairs.iter())
+        {
+            ensure(
+                twiddle_proof.elements.len() == log_size - 1
+                    && twiddle_proof.siblings.len() == log_size - 1
+                    && PrecomputedMerkleTree::verify(
+                        twiddle_tree.root_hash,
+                        log_size - 1,
+                        twiddle_proof,
+                        query >> (n - log_size),
+                    ),
+                err.clone(),
+            )?;
+            folded.push(fold(
+                *v_even,
+                *v_odd,
+                twiddle_proof.elements[log_size - 2],
+                circle_poly_alpha,
+            ));
+        }
+
+        first_layer_folds.push([folded[0], folded[1]]);
+
+        let mut acc = folded[0];
+        for (j, proof) in fri_query_hint.inner_layer_proofs.iter().enumerate() {
+            if j > 0 && n - j == t {
+                acc = acc * circle_poly_alpha * circle_poly_alpha + folded[1];
+            }
+
+            let pos = query >> (j + 1);
+            ensure(
+                proof.siblings.len() == n - 2 - j
+                    && MerkleTree::verify_sibling(
+                        &layer_commitments[j],
+                        n - 1 - j,
+                        proof,
+                        acc,
+                        pos,
+                    ),
+                err.clone(),
+            )?;
+
+            let (v_even, v_odd) = if pos & 1 == 0 {
+                (acc, proof.twin)
+            } else {
+                (proof.twin, acc)
+            };
+            acc = fold(
+                v_even,
+                v_odd,
+                fri_query_hint.twiddle_proofs[0].elements[n - 3 - j],
+                folding_alphas[j],
+            );
+        }
+
+        // check against the last layer
+        match fri_query_hint.last_layer_x {
+            None => ensure(acc == hints.last_layer[0], err)?,
+            Some(x) => {
+                let expected_x = last_layer_domain.at(bit_reverse_index(
+                    query >> (n_layers + 1),
+                    last_layer_domain.log_size() as usize,
+                ));
+                ensure(
+                    x == expected_x
+                        && acc == LinePoly::new(hints.last_layer.clone()).eval_at_point(x.into()),
+                    err,
+                )?;
+            }
+        }
+    }
+
+    // no hint should be left
+    ensure(
+        hints.merkle_proofs_traces.len() == n_queries
+            && hints.merkle_proofs_compositions.len() == n_queries
+            && hints.fri_answer_hints.len() == n_queries
+            && hints.fri_query_hints.len() == n_queries,
+        cursor.peek(),
+    )?;
+    Ok(first_layer_folds)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::verifier::{
+        verify_hints, verify_with_hints, HintCheckError, UnsupportedAirError, VerifierConfig,
+        VerifierGadget, VerifierHints, VerifyHintsError,
+    };
+    use num_traits::One;
+    use stwo_prover::core::channel::{BWSSha256Channel, Channel};
+    use stwo_prover::core::fields::m31::{BaseField, M31};
+    use stwo_prover::core::fields::qm31::QM31;
+    use stwo_prover::core::fields::IntoSlice;
+    use stwo_prover::core::fri::FriConfig;
+    use stwo_prover::core::prover::prove;
+    use stwo_prover::core::vcs::bws_sha256_hash::BWSSha256Hasher;
+    use stwo_prover::core::vcs::hasher::Hasher;
+    use stwo_prover::examples::fibonacci::Fibonacci;
+
+    #[test]
+    fn test_verify_hints() {
+        let fib = Fibonacci::new(5, M31::reduce(443693538));
+        let channel = BWSSha256Channel::new(BWSSha256Hasher::hash(BaseField::into_slice(&[fib
+            .air
+            .component
+            .claim])));
+
+        let new_hints = || -> VerifierHints {
+            let proof = prove(&fib.air, &mut channel.clone(), vec![fib.get_trace()]).unwrap();
+            verify_with_hints(
+                proof,
+                &fib.air,
+                &mut channel.clone(),
+                VerifierConfig::default(),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            verify_hints(&fib.air, &channel, &new_hints(), VerifierConfig::default()),
+            Ok(())
+        );
+
+        let hint_check_error = |hints: &VerifierHints| -> HintCheckError {
+            match verify_hints(&fib.air, &channel, hints, VerifierConfig::default()) {
+                Err(VerifyHintsError::Hint(err)) => err,
+                res => panic!("unexpected result {:?}", res),
+            }
+        };
+
+        // the errors are reported at the first declaration of the hint in the stream of the
+        // verifier, under its label
+        let stream =
+            VerifierGadget::hint_stream(&fib.air, &channel, VerifierConfig::default()).unwrap();
+        let at_label = |label: &str| HintCheckError {
+            hint_index: stream
+                .decls()
+                .iter()
+                .position(|decl| decl.label == label)
+                .unwrap(),
+            label: label.to_string(),
+        };
+
+        // the commitments, the draw hints, the OODS hint, 3 trace oods values and 4 composition
+        // oods raw values take 21 declarations, followed by the composition hint
+        let mut hints = new_hints();
+        hints.trace_oods_values[0] += QM31::one();
+        assert_eq!(hint_check_error(&hints), at_label("composition_hint"));
+        assert_eq!(at_label("composition_hint").hint_index, 21);
+
+        // a wrong composition oods raw value is reported at the first of them
+        let mut hints = new_hints();
+        hints.composition_oods_values[2] += QM31::one();
+        assert_eq!(
+            hint_check_error(&hints),
+            at_label("composition_oods_values[0]")
+        );
+
+        let mut hints = new_hints();
+        hints.circle_poly_alpha_hint.1.push(0);
+        assert_eq!(hint_check_error(&hints), at_label("circle_poly_alpha_hint"));
+
+        // a wrong FRI commitment is reported at the folding hint drawn after it
+        let mut hints = new_hints();
+        hints.fri_commitment_and_folding_hints[1].0 = hints.commitments[0];
+        assert_eq!(hint_check_error(&hints), at_label("fri_folding_hint[1]"));
+
+        // a missing DEEP quotient hint is reported where it is expected
+        let mut hints = new_hints();
+        hints.fri_answer_hints[0][1].domain_point.x += M31::one();
+        let expected = hint_check_error(&hints);
+        assert_eq!(expected, at_label("fri_answer_hints[0][1]"));
+
+        let mut hints = new_hints();
+        hints.fri_answer_hints[0].pop();
+        assert_eq!(hint_check_error(&hints), expected);
+
+        let mut hints = new_hints();
+        hints.fri_query_hints[1].inner_layer_proofs[0].twin += QM31::one();
+        assert_eq!(hint_check_error(&hints), at_label("fri_query_hints[1]"));
+
+        let mut hints = new_hints();
+        hints.fri_query_hints.pop();
+        assert_eq!(hint_check_error(&hints), at_label("fri_query_hints[2]"));
+
+        let mut hints = new_hints();
+        hints
+            .merkle_proofs_traces
+            .push(hints.merkle_proofs_traces[0].clone());
+        assert_eq!(
+            hint_check_error(&hints),
+            HintCheckError {
+                hint_index: stream.decls().len(),
+                label: "leftover hints".to_string(),
+            }
+        );
+
+        // a last layer larger than the composition polynomial leaves no inner FRI layers
+        let config = VerifierConfig {
+            fri_config: FriConfig::new(6, 1, 3),
+            ..VerifierConfig::default()
+        };
+        assert_eq!(
+            verify_hints(&fib.air, &channel, &new_hints(), config),
+            Err(VerifyHintsError::UnsupportedAir(
+                UnsupportedAirError::TooFewFriLayers
+            ))
+        );
+    }
+}